@@ -0,0 +1,270 @@
+#ifndef CHIP8_EMU_H
+#define CHIP8_EMU_H
+
+/* Generated from src/capi.rs by cbindgen: do not edit. Run `UPDATE_HEADER=1 cargo test --test capi' to regenerate. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Display width in pixels.
+ */
+#define CHIP8_WIDTH 64
+
+/**
+ * Display height in pixels.
+ */
+#define CHIP8_HEIGHT 32
+
+/**
+ * Memory size in bytes.
+ */
+#define CHIP8_MEM_SIZE 4096
+
+#define CHIP8_NUM_REGS 16
+
+#define CHIP8_NUM_KEYS 16
+
+typedef enum Chip8Status {
+  CHIP8_STATUS_OK = 0,
+  /**
+   * A pointer argument is null.
+   */
+  CHIP8_STATUS_NULL_POINTER = -1,
+  /**
+   * The ROM is empty or does not fit in memory.
+   */
+  CHIP8_STATUS_ROM_SIZE = -2,
+  /**
+   * An address, register or key is out of range.
+   */
+  CHIP8_STATUS_OUT_OF_RANGE = -3,
+  /**
+   * The output buffer is too small.
+   */
+  CHIP8_STATUS_BUFFER_TOO_SMALL = -4,
+  /**
+   * The save state is not valid.
+   */
+  CHIP8_STATUS_BAD_STATE = -5,
+  /**
+   * The quirks are not valid.
+   */
+  CHIP8_STATUS_BAD_QUIRKS = -6,
+  /**
+   * The CPU has stopped with a fault (eg: a stack
+   * overflow); it runs again after a reset.
+   */
+  CHIP8_STATUS_FAULT = -7,
+} Chip8Status;
+
+/**
+ * An interpreter: the CPU, with the ROM it was last loaded
+ * with. Opaque to C.
+ */
+typedef struct Chip8 Chip8;
+
+/**
+ * The registers, as read by `chip8_registers'.
+ */
+typedef struct Chip8Registers {
+  uint8_t v[CHIP8_NUM_REGS];
+  uint16_t i;
+  uint16_t pc;
+  uint8_t delay;
+  uint8_t sound;
+} Chip8Registers;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Create an interpreter, with no ROM loaded. Free it with
+ * `chip8_free'.
+ */
+struct Chip8 *chip8_new(void);
+
+/**
+ * # Safety
+ *
+ * `c' must be null or a handle from `chip8_new', not yet
+ * freed.
+ */
+void chip8_free(struct Chip8 *c);
+
+/**
+ * Set the interpreter quirks, given as for --quirks (eg:
+ * "vip", "schip,jump-vx").
+ *
+ * # Safety
+ *
+ * `c' must be a valid handle and `quirks' a C string.
+ */
+enum Chip8Status chip8_set_quirks(struct Chip8 *c, const char *quirks);
+
+/**
+ * Load a ROM image (copied) and start it afresh.
+ *
+ * # Safety
+ *
+ * `c' must be a valid handle and `data' must hold `size'
+ * bytes.
+ */
+enum Chip8Status chip8_load_rom(struct Chip8 *c, const uint8_t *data, size_t size);
+
+/**
+ * Start the loaded ROM afresh.
+ *
+ * # Safety
+ *
+ * `c' must be a valid handle.
+ */
+enum Chip8Status chip8_reset(struct Chip8 *c);
+
+/**
+ * Execute `n' instructions; the timers do not run. Returns
+ * `Fault' if the CPU has stopped.
+ *
+ * # Safety
+ *
+ * `c' must be a valid handle.
+ */
+enum Chip8Status chip8_step(struct Chip8 *c, uint32_t n);
+
+/**
+ * Run a frame: `insns_per_frame' instructions and a tick of
+ * the timers, which run at 60 Hz. Returns `Fault' if the CPU
+ * has stopped.
+ *
+ * # Safety
+ *
+ * `c' must be a valid handle.
+ */
+enum Chip8Status chip8_run_frame(struct Chip8 *c, uint32_t insns_per_frame);
+
+/**
+ * Set the state of the keypad: bit k is set if key k is
+ * pressed.
+ *
+ * # Safety
+ *
+ * `c' must be a valid handle.
+ */
+enum Chip8Status chip8_set_keys(struct Chip8 *c, uint16_t keys);
+
+/**
+ * Copy the display to `out', row by row, one byte per pixel
+ * (0 for off). `size' must be at least CHIP8_WIDTH *
+ * CHIP8_HEIGHT.
+ *
+ * # Safety
+ *
+ * `c' must be a valid handle and `out' must have room for
+ * `size' bytes.
+ */
+enum Chip8Status chip8_framebuffer(const struct Chip8 *c, uint8_t *out, size_t size);
+
+/**
+ * Return true while the sound timer runs.
+ *
+ * # Safety
+ *
+ * `c' must be null or a valid handle.
+ */
+bool chip8_beeping(const struct Chip8 *c);
+
+/**
+ * # Safety
+ *
+ * `c' must be a valid handle and `out' must point to a
+ * `Chip8Registers'.
+ */
+enum Chip8Status chip8_registers(const struct Chip8 *c, struct Chip8Registers *out);
+
+/**
+ * Set register V`n'.
+ *
+ * # Safety
+ *
+ * `c' must be a valid handle.
+ */
+enum Chip8Status chip8_set_register(struct Chip8 *c, uint8_t n, uint8_t value);
+
+/**
+ * Continue execution at `pc'.
+ *
+ * # Safety
+ *
+ * `c' must be a valid handle.
+ */
+enum Chip8Status chip8_set_pc(struct Chip8 *c, uint16_t pc);
+
+/**
+ * Copy `size' bytes of memory, starting at `addr', to `out'.
+ *
+ * # Safety
+ *
+ * `c' must be a valid handle and `out' must have room for
+ * `size' bytes.
+ */
+enum Chip8Status chip8_read_memory(const struct Chip8 *c, uint16_t addr, uint8_t *out, size_t size);
+
+/**
+ * Copy `size' bytes from `data' to memory, starting at `addr'.
+ *
+ * # Safety
+ *
+ * `c' must be a valid handle and `data' must hold `size'
+ * bytes.
+ */
+enum Chip8Status chip8_write_memory(struct Chip8 *c,
+                                    uint16_t addr,
+                                    const uint8_t *data,
+                                    size_t size);
+
+/**
+ * Return the size of a save state, or 0 if `c' is null.
+ *
+ * # Safety
+ *
+ * `c' must be null or a valid handle.
+ */
+size_t chip8_state_size(const struct Chip8 *c);
+
+/**
+ * Save the state of the machine to `out', which must have
+ * room for `chip8_state_size' bytes. The quirks and the ROM
+ * are not part of it.
+ *
+ * # Safety
+ *
+ * `c' must be a valid handle and `out' must have room for
+ * `size' bytes.
+ */
+enum Chip8Status chip8_save_state(const struct Chip8 *c, uint8_t *out, size_t size);
+
+/**
+ * Restore a state saved by `chip8_save_state'.
+ *
+ * # Safety
+ *
+ * `c' must be a valid handle and `data' must hold `size'
+ * bytes.
+ */
+enum Chip8Status chip8_load_state(struct Chip8 *c, const uint8_t *data, size_t size);
+
+/**
+ * Return a description of `status', as a static C string.
+ * Values which are not a `Chip8Status' give "unknown status".
+ */
+const char *chip8_status_message(int status);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CHIP8_EMU_H */
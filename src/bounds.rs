@@ -0,0 +1,132 @@
+// bounds.rs
+
+//! What to do when a program reaches outside of memory or
+//! of the stack, and how such faults (and others, like bad
+//! instructions) are reported.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// How an out of bounds access is handled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BoundsPolicy {
+    /// Addresses wrap around at 4K. The stack pointer wraps
+    /// around within the stack area, making it circular.
+    Wrap,
+    /// Stop the program with a fault.
+    Trap,
+    /// Do what the COSMAC VIP interpreter does: I can grow
+    /// beyond 4K, with memory repeating every 4K, and the
+    /// stack is not checked at all, so that it overflows
+    /// into (and underflows from) the memory around it.
+    Vip,
+}
+
+impl FromStr for BoundsPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BoundsPolicy, String> {
+        match s {
+            "wrap" => Ok(BoundsPolicy::Wrap),
+            "trap" => Ok(BoundsPolicy::Trap),
+            "vip" => Ok(BoundsPolicy::Vip),
+            _ => Err(format!("unknown bounds policy: {}", s)),
+        }
+    }
+}
+
+/// The policy for each kind of access.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    /// Memory accessed through I (Dxyn, Fx33, Fx55, Fx65).
+    pub memory: BoundsPolicy,
+    /// Instruction fetch.
+    pub pc: BoundsPolicy,
+    /// Call and return.
+    pub stack: BoundsPolicy,
+}
+
+impl Bounds {
+    /// Use the same policy for everything.
+    pub fn all(policy: BoundsPolicy) -> Bounds {
+        Bounds { memory: policy, pc: policy, stack: policy }
+    }
+
+    /// Parse a policy for everything (eg: "wrap"), or a comma
+    /// separated list of "area=policy" settings, area being
+    /// one of mem, pc and stack (eg: "mem=wrap, stack=trap").
+    /// Areas which are not mentioned get the default policy.
+    pub fn parse(text: &str) -> Result<Bounds, String> {
+        if let Ok(policy) = text.trim().parse() {
+            return Ok(Bounds::all(policy));
+        }
+        let mut bounds = Bounds::default();
+        for setting in text.split(',') {
+            let mut parts = setting.splitn(2, '=');
+            let area = parts.next().unwrap().trim();
+            let policy = parts.next()
+                            .ok_or_else(|| format!("expected \"area=policy\": {}", setting.trim()))?
+                            .trim().parse()?;
+            match area {
+                "mem" => bounds.memory = policy,
+                "pc" => bounds.pc = policy,
+                "stack" => bounds.stack = policy,
+                _ => return Err(format!("unknown bounds area: {} (expected mem, pc or stack)", area)),
+            }
+        }
+        Ok(bounds)
+    }
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Bounds::all(BoundsPolicy::Trap)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultKind {
+    /// Memory at the given address was accessed through I.
+    Memory(usize),
+    /// The PC left memory.
+    Pc,
+    StackOverflow,
+    StackUnderflow,
+    /// 0nnn: a call to a machine language routine.
+    MachineCode,
+    /// Not a CHIP-8 instruction.
+    UnknownOpcode,
+}
+
+/// A program error which stopped the CPU.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fault {
+    pub kind: FaultKind,
+    /// Address of the faulting instruction.
+    pub pc: usize,
+    /// The faulting instruction; None if it could not
+    /// be fetched.
+    pub opcode: Option<u16>,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            FaultKind::Memory(addr) => write!(f, "memory access out of bounds ({:#05x})", addr)?,
+            FaultKind::Pc => write!(f, "PC out of bounds")?,
+            FaultKind::StackOverflow => write!(f, "stack overflow")?,
+            FaultKind::StackUnderflow => write!(f, "stack underflow")?,
+            FaultKind::MachineCode => write!(f, "machine language call (SYS)")?,
+            FaultKind::UnknownOpcode => write!(f, "unknown instruction")?,
+        }
+        write!(f, " at {:#05x}", self.pc)?;
+        if let Some(op) = self.opcode {
+            write!(f, " ({:04x})", op)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path="./bounds_test.rs"]
+mod bounds_test;
@@ -0,0 +1,20 @@
+
+use super::*;
+
+#[test]
+fn test_parse() {
+    assert_eq!(Bounds::parse("wrap"), Ok(Bounds::all(BoundsPolicy::Wrap)));
+    assert_eq!(Bounds::parse("stack = vip, pc=wrap"),
+               Ok(Bounds { memory: BoundsPolicy::Trap, pc: BoundsPolicy::Wrap, stack: BoundsPolicy::Vip }));
+    assert!(Bounds::parse("loop").is_err());
+    assert!(Bounds::parse("mem=loop").is_err());
+    assert!(Bounds::parse("regs=wrap").is_err());
+}
+
+#[test]
+fn test_fault_display() {
+    let f = Fault { kind: FaultKind::Memory(0x1000), pc: 0x2a4, opcode: Some(0xf155) };
+    assert_eq!(f.to_string(), "memory access out of bounds (0x1000) at 0x2a4 (f155)");
+    let f = Fault { kind: FaultKind::Pc, pc: 0x1000, opcode: None };
+    assert_eq!(f.to_string(), "PC out of bounds at 0x1000");
+}
@@ -0,0 +1,434 @@
+// capi.rs
+
+//! The C API, for embedding the interpreter in programs not
+//! written in Rust. include/chip8_emu.h is generated from this
+//! file with cbindgen (see tests/capi.rs):
+//!
+//! ```text
+//! Chip8 *c = chip8_new();
+//! chip8_load_rom(c, rom, rom_size);
+//! for (;;) {
+//!     chip8_set_keys(c, keys);
+//!     chip8_run_frame(c, 10);
+//!     chip8_framebuffer(c, pixels, sizeof pixels);
+//!     ...
+//! }
+//! chip8_free(c);
+//! ```
+//!
+//! Functions which can fail return a `Chip8Status'. Handles
+//! are not thread-safe; use one per thread.
+
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::slice;
+
+use cpu::{self, CPU, MEM_SIZE, NUM_KEYS, NUM_REGS};
+use font::{self, FontStyle};
+use framebuffer;
+use quirks::Quirks;
+
+// These are literals, not paths to the constants they copy,
+// so that cbindgen can put them in the header.
+
+/// Display width in pixels.
+pub const CHIP8_WIDTH: usize = 64;
+/// Display height in pixels.
+pub const CHIP8_HEIGHT: usize = 32;
+/// Memory size in bytes.
+pub const CHIP8_MEM_SIZE: usize = 4096;
+pub const CHIP8_NUM_REGS: usize = 16;
+pub const CHIP8_NUM_KEYS: usize = 16;
+
+const _: () = assert!(CHIP8_WIDTH == framebuffer::WIDTH && CHIP8_HEIGHT == framebuffer::HEIGHT);
+const _: () = assert!(CHIP8_MEM_SIZE == MEM_SIZE && CHIP8_NUM_REGS == NUM_REGS && CHIP8_NUM_KEYS == NUM_KEYS);
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Chip8Status {
+    Ok = 0,
+    /// A pointer argument is null.
+    NullPointer = -1,
+    /// The ROM is empty or does not fit in memory.
+    RomSize = -2,
+    /// An address, register or key is out of range.
+    OutOfRange = -3,
+    /// The output buffer is too small.
+    BufferTooSmall = -4,
+    /// The save state is not valid.
+    BadState = -5,
+    /// The quirks are not valid.
+    BadQuirks = -6,
+    /// The CPU has stopped with a fault (eg: a stack
+    /// overflow); it runs again after a reset.
+    Fault = -7,
+}
+
+/// An interpreter: the CPU, with the ROM it was last loaded
+/// with. Opaque to C.
+pub struct Chip8 {
+    cpu: CPU,
+    game: Vec<u8>,
+}
+
+/// The registers, as read by `chip8_registers'.
+#[repr(C)]
+pub struct Chip8Registers {
+    pub v: [u8; CHIP8_NUM_REGS],
+    pub i: u16,
+    pub pc: u16,
+    pub delay: u8,
+    pub sound: u8,
+}
+
+impl Chip8 {
+    fn load(&mut self) {
+        self.cpu.reset();
+        self.cpu.load_font(FontStyle::Standard.data(), font::DEFAULT_FONT_BASE);
+        self.cpu.load_bytes(&self.game, cpu::PC_START);
+    }
+
+    fn status(&self) -> Chip8Status {
+        if self.cpu.fault().is_some() { Chip8Status::Fault } else { Chip8Status::Ok }
+    }
+}
+
+/// Turn `$c', a `*mut Chip8', into a reference, returning
+/// `NullPointer' if it is null. `handle!(const $c)' does the
+/// same for a `*const Chip8', giving a shared reference.
+macro_rules! handle {
+    (const $c:expr) => {
+        match $c.as_ref() {
+            Some(c) => c,
+            None => return Chip8Status::NullPointer,
+        }
+    };
+    ($c:expr) => {
+        match $c.as_mut() {
+            Some(c) => c,
+            None => return Chip8Status::NullPointer,
+        }
+    };
+}
+
+/// Create an interpreter, with no ROM loaded. Free it with
+/// `chip8_free'.
+#[no_mangle]
+pub extern "C" fn chip8_new() -> *mut Chip8 {
+    let mut c = Box::new(Chip8 { cpu: CPU::new(), game: Vec::new() });
+    c.load();
+    Box::into_raw(c)
+}
+
+/// # Safety
+///
+/// `c' must be null or a handle from `chip8_new', not yet
+/// freed.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(c: *mut Chip8) {
+    if !c.is_null() {
+        drop(Box::from_raw(c));
+    }
+}
+
+/// Set the interpreter quirks, given as for --quirks (eg:
+/// "vip", "schip,jump-vx").
+///
+/// # Safety
+///
+/// `c' must be a valid handle and `quirks' a C string.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_quirks(c: *mut Chip8, quirks: *const c_char) -> Chip8Status {
+    let c = handle!(c);
+    if quirks.is_null() {
+        return Chip8Status::NullPointer;
+    }
+    match CStr::from_ptr(quirks).to_str().map(Quirks::parse) {
+        Ok(Ok(q)) => {
+            c.cpu.set_quirks(q);
+            Chip8Status::Ok
+        }
+        _ => Chip8Status::BadQuirks,
+    }
+}
+
+/// Load a ROM image (copied) and start it afresh.
+///
+/// # Safety
+///
+/// `c' must be a valid handle and `data' must hold `size'
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(c: *mut Chip8, data: *const u8, size: usize) -> Chip8Status {
+    let c = handle!(c);
+    if data.is_null() {
+        return Chip8Status::NullPointer;
+    }
+    if size == 0 || size > MEM_SIZE - cpu::PC_START {
+        return Chip8Status::RomSize;
+    }
+    c.game = slice::from_raw_parts(data, size).to_vec();
+    c.load();
+    Chip8Status::Ok
+}
+
+/// Start the loaded ROM afresh.
+///
+/// # Safety
+///
+/// `c' must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_reset(c: *mut Chip8) -> Chip8Status {
+    let c = handle!(c);
+    c.load();
+    Chip8Status::Ok
+}
+
+/// Execute `n' instructions; the timers do not run. Returns
+/// `Fault' if the CPU has stopped.
+///
+/// # Safety
+///
+/// `c' must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step(c: *mut Chip8, n: u32) -> Chip8Status {
+    let c = handle!(c);
+    for _ in 0..n {
+        c.cpu.execute_insn();
+    }
+    c.status()
+}
+
+/// Run a frame: `insns_per_frame' instructions and a tick of
+/// the timers, which run at 60 Hz. Returns `Fault' if the CPU
+/// has stopped.
+///
+/// # Safety
+///
+/// `c' must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_frame(c: *mut Chip8, insns_per_frame: u32) -> Chip8Status {
+    let c = handle!(c);
+    c.cpu.run_frame(insns_per_frame);
+    c.status()
+}
+
+/// Set the state of the keypad: bit k is set if key k is
+/// pressed.
+///
+/// # Safety
+///
+/// `c' must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_keys(c: *mut Chip8, keys: u16) -> Chip8Status {
+    let c = handle!(c);
+    let mut pressed = [false; NUM_KEYS];
+    for (k, p) in pressed.iter_mut().enumerate() {
+        *p = keys & (1 << k) != 0;
+    }
+    c.cpu.set_keys(pressed);
+    Chip8Status::Ok
+}
+
+/// Copy the display to `out', row by row, one byte per pixel
+/// (0 for off). `size' must be at least CHIP8_WIDTH *
+/// CHIP8_HEIGHT.
+///
+/// # Safety
+///
+/// `c' must be a valid handle and `out' must have room for
+/// `size' bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(c: *const Chip8, out: *mut u8, size: usize) -> Chip8Status {
+    let c = handle!(const c);
+    if out.is_null() {
+        return Chip8Status::NullPointer;
+    }
+    if size < CHIP8_WIDTH * CHIP8_HEIGHT {
+        return Chip8Status::BufferTooSmall;
+    }
+    let out = slice::from_raw_parts_mut(out, size);
+    for (o, p) in out.iter_mut().zip(c.cpu.display().rows().flat_map(|row| row.iter())) {
+        *o = *p;
+    }
+    Chip8Status::Ok
+}
+
+/// Return true while the sound timer runs.
+///
+/// # Safety
+///
+/// `c' must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_beeping(c: *const Chip8) -> bool {
+    c.as_ref().is_some_and(|c| c.cpu.beeping())
+}
+
+/// # Safety
+///
+/// `c' must be a valid handle and `out' must point to a
+/// `Chip8Registers'.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_registers(c: *const Chip8, out: *mut Chip8Registers) -> Chip8Status {
+    let c = handle!(const c);
+    if out.is_null() {
+        return Chip8Status::NullPointer;
+    }
+    let mut v = [0; NUM_REGS];
+    for (n, r) in v.iter_mut().enumerate() {
+        *r = c.cpu.v(n);
+    }
+    // The CPU keeps I and the PC within 16 bits, and load_state
+    // refuses states which do not.
+    let (i, pc) = match (u16::try_from(c.cpu.i()), u16::try_from(c.cpu.pc())) {
+        (Ok(i), Ok(pc)) => (i, pc),
+        _ => return Chip8Status::OutOfRange,
+    };
+    let (delay, sound) = c.cpu.timers();
+    *out = Chip8Registers { v, i, pc, delay, sound };
+    Chip8Status::Ok
+}
+
+/// Set register V`n'.
+///
+/// # Safety
+///
+/// `c' must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_register(c: *mut Chip8, n: u8, value: u8) -> Chip8Status {
+    let c = handle!(c);
+    if usize::from(n) >= NUM_REGS {
+        return Chip8Status::OutOfRange;
+    }
+    c.cpu.set_v(usize::from(n), value);
+    Chip8Status::Ok
+}
+
+/// Continue execution at `pc'.
+///
+/// # Safety
+///
+/// `c' must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_pc(c: *mut Chip8, pc: u16) -> Chip8Status {
+    let c = handle!(c);
+    if usize::from(pc) >= MEM_SIZE {
+        return Chip8Status::OutOfRange;
+    }
+    c.cpu.set_pc(usize::from(pc));
+    Chip8Status::Ok
+}
+
+/// Copy `size' bytes of memory, starting at `addr', to `out'.
+///
+/// # Safety
+///
+/// `c' must be a valid handle and `out' must have room for
+/// `size' bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_read_memory(c: *const Chip8, addr: u16, out: *mut u8, size: usize) -> Chip8Status {
+    let c = handle!(const c);
+    if out.is_null() {
+        return Chip8Status::NullPointer;
+    }
+    let addr = usize::from(addr);
+    if addr.checked_add(size).is_none_or(|end| end > MEM_SIZE) {
+        return Chip8Status::OutOfRange;
+    }
+    ptr::copy_nonoverlapping(c.cpu.mem()[addr..].as_ptr(), out, size);
+    Chip8Status::Ok
+}
+
+/// Copy `size' bytes from `data' to memory, starting at `addr'.
+///
+/// # Safety
+///
+/// `c' must be a valid handle and `data' must hold `size'
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_write_memory(c: *mut Chip8, addr: u16, data: *const u8, size: usize) -> Chip8Status {
+    let c = handle!(c);
+    if data.is_null() {
+        return Chip8Status::NullPointer;
+    }
+    let addr = usize::from(addr);
+    if addr.checked_add(size).is_none_or(|end| end > MEM_SIZE) {
+        return Chip8Status::OutOfRange;
+    }
+    ptr::copy_nonoverlapping(data, c.cpu.mem_mut()[addr..].as_mut_ptr(), size);
+    Chip8Status::Ok
+}
+
+/// Return the size of a save state, or 0 if `c' is null.
+///
+/// # Safety
+///
+/// `c' must be null or a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_state_size(c: *const Chip8) -> usize {
+    c.as_ref().map_or(0, |c| c.cpu.save_state().len())
+}
+
+/// Save the state of the machine to `out', which must have
+/// room for `chip8_state_size' bytes. The quirks and the ROM
+/// are not part of it.
+///
+/// # Safety
+///
+/// `c' must be a valid handle and `out' must have room for
+/// `size' bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state(c: *const Chip8, out: *mut u8, size: usize) -> Chip8Status {
+    let c = handle!(const c);
+    if out.is_null() {
+        return Chip8Status::NullPointer;
+    }
+    let state = c.cpu.save_state();
+    if size < state.len() {
+        return Chip8Status::BufferTooSmall;
+    }
+    ptr::copy_nonoverlapping(state.as_ptr(), out, state.len());
+    Chip8Status::Ok
+}
+
+/// Restore a state saved by `chip8_save_state'.
+///
+/// # Safety
+///
+/// `c' must be a valid handle and `data' must hold `size'
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_state(c: *mut Chip8, data: *const u8, size: usize) -> Chip8Status {
+    let c = handle!(c);
+    if data.is_null() {
+        return Chip8Status::NullPointer;
+    }
+    match c.cpu.load_state(slice::from_raw_parts(data, size)) {
+        Ok(()) => Chip8Status::Ok,
+        Err(_) => Chip8Status::BadState,
+    }
+}
+
+/// Return a description of `status', as a static C string.
+/// Values which are not a `Chip8Status' give "unknown status".
+#[no_mangle]
+pub extern "C" fn chip8_status_message(status: c_int) -> *const c_char {
+    // Taken as an int: a C caller can pass any value, which
+    // would not be a valid Rust enum.
+    let messages: [(Chip8Status, &'static [u8]); 8] = [
+        (Chip8Status::Ok, b"ok\0"),
+        (Chip8Status::NullPointer, b"null pointer\0"),
+        (Chip8Status::RomSize, b"ROM is empty or too large\0"),
+        (Chip8Status::OutOfRange, b"out of range\0"),
+        (Chip8Status::BufferTooSmall, b"buffer too small\0"),
+        (Chip8Status::BadState, b"invalid save state\0"),
+        (Chip8Status::BadQuirks, b"invalid quirks\0"),
+        (Chip8Status::Fault, b"the CPU has stopped with a fault\0"),
+    ];
+    let msg = messages.iter()
+                .find(|(s, _)| *s as c_int == status)
+                .map_or(&b"unknown status\0"[..], |(_, msg)| msg);
+    msg.as_ptr() as *const c_char
+}
@@ -1,34 +1,522 @@
 // chip8.rs
 
 use screen;
+use screenshot;
+use frontend::Frontend;
+use terminal::{Glyphs, Terminal};
 use cpu;
-use std::{thread, time};
+use palette::Palette;
+use quirks::Quirks;
+use bounds::{Bounds, Fault};
+use script::{Action, Script};
+use control::{self, Control};
+use netplay::{self, Session};
+use record::Recorder;
+use stack::StackModel;
+use std::cmp;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
-const NTICKS: u32 = 8;
-const DELAY_MS: u64 = 1;
+/// The delay and sound timers count down at 60 Hz. The
+/// emulator runs one "frame" per timer tick: a batch of
+/// instructions, one timer decrement and one screen update.
+pub const FRAMES_PER_SECOND: u32 = cpu::TIMER_HZ;
 
+/// Number of instructions executed per frame if the user
+/// does not ask for a different speed. 10 instructions per
+/// frame gives a CPU speed of 600 Hz.
+pub const DEFAULT_INSNS_PER_FRAME: u32 = 10;
 
-pub fn chip8_run(font_file: &str, game_file: &str, scale_factor: u32) {
-    let  s = screen::Screen::new(
-        u32::from(screen::SCREEN_WIDTH), 
-        u32::from(screen::SCREEN_HEIGHT), 
-        scale_factor);
+/// If we fall behind the schedule by more than these many
+/// frames (say, the process was suspended), stop trying to
+/// catch up and restart the schedule from the current time.
+const MAX_LAG_FRAMES: u32 = 5;
 
-    let delay = time::Duration::from_millis(DELAY_MS); 
-    let mut tick_counter = 0;
+/// Return the time at which frame number `n` (counting from
+/// 0) should start, relative to the start of the schedule.
+///
+/// Computing this from the frame count (rather than adding
+/// 1/60 second to the previous deadline) keeps rounding
+/// errors from accumulating.
+fn frame_deadline(start: Instant, n: u64) -> Instant {
+    start + Duration::from_secs(n) / FRAMES_PER_SECOND
+}
 
-    let mut c = cpu::CPU::new(Some(s));
-    c.load_rom(font_file, 0);
-    c.load_rom(game_file, cpu::PC_START);
+/// While turbo is on, these many frames are run in the
+/// time of one.
+const TURBO_FRAMES: u32 = 4;
 
-    loop {
-        thread::sleep(delay);  
-        if tick_counter == NTICKS { 
-            c.decrement_counters();
-            tick_counter = 0;
-        }     
-        c.execute_insn();
-        tick_counter += 1;
+/// In slow motion, one frame is run in the time of these many.
+const SLOW_MOTION_DIVISOR: u64 = 4;
+
+/// Requests from the user (usually through hotkeys) or from
+/// the control server which control the emulator.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Quit,
+    /// Reload the game and start it afresh.
+    Reset,
+    TogglePause,
+    /// Pause (true) or resume (false).
+    Pause(bool),
+    /// While paused, run a single frame.
+    FrameAdvance,
+    /// While paused, run these many frames at once.
+    Step(u32),
+    ToggleSlowMotion,
+    /// Fast forward on (true) or off (false).
+    Turbo(bool),
+    SpeedDown,
+    SpeedUp,
+    /// Save the display as an image.
+    Screenshot,
+    /// Start or stop recording a video.
+    ToggleRecording,
+}
+
+/// Settings for running a game, usually taken from
+/// the command line.
+pub struct Config {
+    /// Sprites for the hex digits, loaded at `font_base'.
+    pub font: Vec<u8>,
+    pub font_base: usize,
+    /// The ROM image, loaded at PC_START.
+    pub game: Vec<u8>,
+    pub quirks: Quirks,
+    pub bounds: Bounds,
+    pub stack_model: StackModel,
+    pub opcode_policy: cpu::OpcodePolicy,
+    pub insns_per_frame: u32,
+    pub screen: screen::Options,
+    /// Play in the terminal, drawn with these glyphs, rather
+    /// than in a window.
+    pub terminal: Option<Glyphs>,
+    /// Screenshots are saved as "<name>-<frame>.png" etc.
+    pub name: String,
+    /// Take a screenshot once these many frames have been run.
+    pub screenshot_at: Option<u64>,
+    /// Record a video (see `Recorder::create') from the start.
+    pub record_video: Option<String>,
+    /// Stop once these many frames have been run.
+    pub max_frames: Option<u64>,
+    /// Started once the game is loaded.
+    pub script: Option<Script>,
+    /// Takes requests from other programs.
+    pub control: Option<Control>,
+    /// Play with someone on another machine. Not with a
+    /// script, whose hooks netplay would skip.
+    pub netplay: Option<netplay::Options>,
+}
+
+/// How fast the emulation runs, as changed by the user.
+struct Speed {
+    paused: bool,
+    slow_motion: bool,
+    turbo: bool,
+    insns_per_frame: u32,
+    /// Counts real frames, for pacing slow motion.
+    ticks: u64,
+}
+
+impl Speed {
+    fn new(insns_per_frame: u32) -> Speed {
+        Speed { paused: false, slow_motion: false, turbo: false, insns_per_frame, ticks: 0 }
+    }
+
+    /// Number of emulated frames to run during the current
+    /// real frame. `advance' is true if the user asked for a
+    /// single frame to be run while paused.
+    fn frames_to_run(&mut self, advance: bool) -> u32 {
+        self.ticks += 1;
+        if self.paused {
+            if advance { 1 } else { 0 }
+        } else if self.turbo {
+            TURBO_FRAMES
+        } else if self.slow_motion {
+            if self.ticks.is_multiple_of(SLOW_MOTION_DIVISOR) { 1 } else { 0 }
+        } else {
+            1
+        }
+    }
+
+    fn speed_up(&mut self) {
+        self.insns_per_frame += cmp::max(1, self.insns_per_frame / 4);
+    }
+
+    fn speed_down(&mut self) {
+        let step = cmp::max(1, self.insns_per_frame / 5);
+        self.insns_per_frame = cmp::max(1, self.insns_per_frame.saturating_sub(step));
+    }
+}
+
+/// Load the font and the game into memory.
+fn load(c: &mut cpu::CPU, font: &[u8], font_base: usize, game: &[u8]) {
+    c.load_font(font, font_base);
+    c.load_bytes(game, cpu::PC_START);
+}
+
+/// Create the CPU, set up as asked in `config', with the
+/// font and the game loaded.
+fn new_cpu(config: &Config) -> cpu::CPU {
+    let mut c = cpu::CPU::new();
+    c.set_quirks(config.quirks);
+    c.set_bounds(config.bounds);
+    c.set_stack_model(config.stack_model);
+    c.set_opcode_policy(config.opcode_policy);
+    load(&mut c, &config.font, config.font_base, &config.game);
+    c
+}
+
+/// Describe the fault which stopped the CPU, along with the
+/// calls which led to it.
+fn fault_message(c: &cpu::CPU, fault: &Fault) -> String {
+    let calls: Vec<String> = c.return_addresses().iter()
+                                .rev()
+                                .map(|addr| format!("{:#05x}", addr))
+                                .collect();
+    if calls.is_empty() {
+        fault.to_string()
+    } else {
+        format!("{}\nreturn addresses (innermost first): {}", fault, calls.join(" "))
+    }
+}
+
+/// Save the display as "<name>-<frame>.png" etc. Returns
+/// false if it could not be saved.
+fn screenshot(c: &cpu::CPU, palette: &Palette, name: &str, frame: u64, scale: u32) -> bool {
+    let prefix = format!("{}-{:06}", name, frame);
+    match screenshot::save(&prefix, c.display(), palette, scale as usize) {
+        Ok(names) => {
+            eprintln!("screenshot saved: {}", names.join(", "));
+            true
+        },
+        Err(e) => {
+            eprintln!("warning: screenshot: {}", e);
+            false
+        },
+    }
+}
+
+/// Start recording to `filename'. Returns None if the files
+/// could not be created.
+fn start_recording(filename: &str, palette: &Palette, scale: u32) -> Option<Recorder> {
+    match Recorder::create(filename, palette, scale as usize) {
+        Ok(r) => {
+            eprintln!("recording to {}", r.names().join(", "));
+            Some(r)
+        },
+        Err(e) => {
+            eprintln!("warning: recording: {}", e);
+            None
+        },
+    }
+}
+
+fn stop_recording(r: Recorder) {
+    match r.finish() {
+        Ok(names) => eprintln!("recording saved: {}", names.join(", ")),
+        Err(e) => eprintln!("warning: recording: {}", e),
+    }
+}
+
+/// Run one frame, through the script if there is one.
+fn run_frame(c: &mut cpu::CPU, script: &mut Option<Script>, insns_per_frame: u32) {
+    match *script {
+        Some(ref mut s) => s.run_frame(c, insns_per_frame),
+        None => c.run_frame(insns_per_frame),
     }
- 
-}
\ No newline at end of file
+}
+
+/// Add the current frame to the recording, if there is one.
+/// A recording which fails is dropped.
+fn record_frame(recorder: &mut Option<Recorder>, c: &cpu::CPU, palette: &Palette) {
+    let failed = match *recorder {
+        Some(ref mut r) => r.add_frame(c.display(), palette, c.beeping()).err(),
+        None => None,
+    };
+    if let Some(e) = failed {
+        eprintln!("warning: recording stopped: {}", e);
+        *recorder = None;
+    }
+}
+
+/// Run the game in a window or in the terminal until the
+/// user quits. Returns an error if the terminal cannot be used
+/// or if the script fails.
+pub fn chip8_run(mut config: Config) -> Result<(), String> {
+    let mut speed = Speed::new(config.insns_per_frame);
+    let scale = config.screen.scale_factor;
+    let mut c = new_cpu(&config);
+    let mut script = config.script.take();
+    if let Some(ref mut script) = script {
+        script.start(&mut c);
+    }
+    let mut control = config.control.take();
+    if let Some(ref control) = control {
+        eprintln!("control server listening on {}", control.address());
+    }
+    // The session runs the frames itself, without the hooks.
+    if script.is_some() && config.netplay.is_some() {
+        return Err(String::from("scripts cannot be used in netplay"));
+    }
+    let mut netplay: Option<Session> = match config.netplay {
+        Some(ref options) => Some(netplay::start(options, &c, speed.insns_per_frame)?),
+        None => None,
+    };
+    let mut netplay_error = None;
+    // Set while waiting for the other player.
+    let mut waiting = false;
+    let mut s: Box<dyn Frontend> = match config.terminal {
+        Some(glyphs) => Box::new(Terminal::new(config.screen, glyphs)?),
+        None => Box::new(screen::Screen::new(config.screen)),
+    };
+
+    let mut start = Instant::now();
+    let mut frame: u64 = 0;
+    let mut fault_reported = false;
+    // Number of frames emulated, not counting the ones
+    // skipped while paused.
+    let mut emulated: u64 = 0;
+
+    // Recordings started with the hotkey are named after the
+    // game, in the format of the --record-video file if any.
+    let video_format = config.record_video.as_ref()
+                        .and_then(|f| Path::new(f).extension())
+                        .map_or(String::from("gif"), |e| e.to_string_lossy().into_owned());
+    let mut recorder = config.record_video.as_ref()
+                        .and_then(|f| start_recording(f, s.palette(), scale));
+
+    'running: loop {
+        let mut advance = false;
+        let mut steps: u32 = 0;
+        let mut commands = s.poll_events();
+        if let Some(ref mut control) = control {
+            let status = control::Status {
+                paused: speed.paused,
+                frame: emulated,
+                insns_per_frame: speed.insns_per_frame,
+                netplay: netplay.is_some(),
+            };
+            commands.extend(control.poll(&mut c, s.palette(), &status));
+        }
+        for command in commands {
+            // Anything which changes the game for one player only
+            // would break netplay.
+            let allowed = matches!(command, Command::Quit | Command::Screenshot |
+                                            Command::ToggleRecording | Command::Turbo(false));
+            if netplay.is_some() && !allowed {
+                s.show_message("not during netplay");
+                continue;
+            }
+            match command {
+                Command::Quit => break 'running,
+                Command::Reset => {
+                    c.reset();
+                    load(&mut c, &config.font, config.font_base, &config.game);
+                    if fault_reported {
+                        fault_reported = false;
+                        s.set_status(None);
+                    }
+                    s.show_message("reset");
+                },
+                Command::TogglePause | Command::Pause(_) => {
+                    speed.paused = match command {
+                        Command::Pause(on) => on,
+                        _ => !speed.paused,
+                    };
+                    s.set_status(if speed.paused { Some("paused") } else { None });
+                },
+                Command::FrameAdvance => {
+                    if speed.paused {
+                        advance = true;
+                    } else {
+                        s.show_message("pause first");
+                    }
+                },
+                Command::Step(n) => {
+                    if speed.paused {
+                        steps = steps.saturating_add(n);
+                    }
+                },
+                Command::ToggleSlowMotion => {
+                    speed.slow_motion = !speed.slow_motion;
+                    s.show_message(if speed.slow_motion { "slow motion on" } else { "slow motion off" });
+                },
+                Command::Turbo(on) => {
+                    speed.turbo = on;
+                    s.set_status(if on { Some("turbo") } else { None });
+                },
+                Command::SpeedDown | Command::SpeedUp => {
+                    if command == Command::SpeedUp {
+                        speed.speed_up();
+                    } else {
+                        speed.speed_down();
+                    }
+                    s.show_message(&format!("speed {} ipf", speed.insns_per_frame));
+                },
+                Command::Screenshot => {
+                    let saved = screenshot(&c, s.palette(), &config.name, emulated, scale);
+                    s.show_message(if saved { "screenshot saved" } else { "screenshot failed" });
+                },
+                Command::ToggleRecording => {
+                    if let Some(r) = recorder.take() {
+                        stop_recording(r);
+                        s.show_message("recording stopped");
+                    } else {
+                        let filename = format!("{}-{:06}.{}", config.name, emulated, video_format);
+                        recorder = start_recording(&filename, s.palette(), scale);
+                        s.show_message(if recorder.is_some() { "recording" } else { "recording failed" });
+                    }
+                },
+            }
+        }
+
+        let mut keys = s.keys();
+        if let Some(ref control) = control {
+            for (k, pressed) in keys.iter_mut().zip(control.keys().iter()) {
+                *k |= *pressed;
+            }
+        }
+        match script {
+            Some(ref mut script) => script.set_keys(&mut c, keys),
+            None => c.set_keys(keys),
+        }
+        let frames = match netplay {
+            Some(ref mut session) => match session.run_frame(&mut c, keys, speed.insns_per_frame) {
+                Ok(ran) => {
+                    if waiting == ran {
+                        waiting = !ran;
+                        s.set_status(if waiting { Some("waiting") } else { None });
+                    }
+                    u32::from(ran)
+                },
+                Err(e) => {
+                    netplay_error = Some(e);
+                    break;
+                },
+            },
+            None => speed.frames_to_run(advance).saturating_add(steps),
+        };
+        for _ in 0..frames {
+            // The session has run the frame.
+            if netplay.is_none() {
+                run_frame(&mut c, &mut script, speed.insns_per_frame);
+            }
+            emulated += 1;
+            record_frame(&mut recorder, &c, s.palette());
+            if Some(emulated) == config.screenshot_at {
+                screenshot(&c, s.palette(), &config.name, emulated, scale);
+            }
+            if let Some(ref mut control) = control {
+                control.frame(&c, emulated);
+            }
+        }
+        if let Some(ref mut control) = control {
+            control.end_frames(emulated);
+        }
+        if let Some(ref mut script) = script {
+            for action in script.take_actions() {
+                match action {
+                    Action::Message(text) => s.show_message(&text),
+                    Action::Overlay(text) => s.set_status(text.as_deref()),
+                    Action::Screenshot => {
+                        screenshot(&c, s.palette(), &config.name, emulated, scale);
+                    },
+                }
+            }
+            if script.error().is_some() {
+                break;
+            }
+        }
+        if let Some(fault) = c.fault() {
+            if !fault_reported {
+                eprintln!("error: {}", fault_message(&c, &fault));
+                s.set_status(Some("fault"));
+                s.show_message(&format!("at {:#05x}", fault.pc));
+                fault_reported = true;
+            }
+        }
+        s.present(c.display());
+        if config.max_frames.is_some_and(|n| emulated >= n) {
+            break;
+        }
+
+        frame += 1;
+        let deadline = frame_deadline(start, frame);
+        let now = Instant::now();
+        if deadline > now {
+            thread::sleep(deadline - now);
+        } else if now - deadline > Duration::from_secs(1) * MAX_LAG_FRAMES / FRAMES_PER_SECOND {
+            start = now;
+            frame = 0;
+        }
+    }
+    // Restore the terminal before the last messages.
+    drop(s);
+    if let Some(r) = recorder {
+        stop_recording(r);
+    }
+    if let Some(session) = netplay {
+        eprintln!("netplay: {} frames, {} rollbacks", emulated, session.rollbacks());
+    }
+    if let Some(e) = netplay_error {
+        return Err(e);
+    }
+    match script.and_then(|s| s.error()) {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Run the game without a window (and so with no keys
+/// pressed), as fast as possible, until `max_frames' frames
+/// have been run or the CPU faults. Screenshots and
+/// recordings are made as usual, with the palette of the
+/// screen options; so are the screenshots asked for by the
+/// script, whose messages are not shown. Returns an error if
+/// the CPU faults or the script fails.
+pub fn chip8_run_headless(mut config: Config) -> Result<(), String> {
+    let scale = config.screen.scale_factor;
+    let palette = &config.screen.palette;
+    let mut c = new_cpu(&config);
+    let mut script = config.script.take();
+    if let Some(ref mut script) = script {
+        script.start(&mut c);
+    }
+    let mut recorder = config.record_video.as_ref()
+                        .and_then(|f| start_recording(f, palette, scale));
+
+    let mut result = Ok(());
+    for emulated in 1 ..= config.max_frames.unwrap_or(u64::MAX) {
+        if let Some(ref mut script) = script {
+            script.set_keys(&mut c, [false; cpu::NUM_KEYS]);
+        }
+        run_frame(&mut c, &mut script, config.insns_per_frame);
+        record_frame(&mut recorder, &c, palette);
+        if Some(emulated) == config.screenshot_at {
+            screenshot(&c, palette, &config.name, emulated, scale);
+        }
+        if let Some(ref mut script) = script {
+            if script.take_actions().contains(&Action::Screenshot) {
+                screenshot(&c, palette, &config.name, emulated, scale);
+            }
+            if let Some(e) = script.error() {
+                result = Err(e);
+                break;
+            }
+        }
+        if let Some(fault) = c.fault() {
+            result = Err(fault_message(&c, &fault));
+            break;
+        }
+    }
+    if let Some(r) = recorder {
+        stop_recording(r);
+    }
+    result
+}
+
+#[cfg(test)]
+#[path="./chip8_test.rs"]
+mod chip8_test;
@@ -0,0 +1,43 @@
+
+use super::*;
+
+#[test]
+fn test_frames_to_run() {
+    let mut speed = Speed::new(10);
+    assert_eq!(speed.frames_to_run(false), 1);
+
+    speed.turbo = true;
+    assert_eq!(speed.frames_to_run(false), TURBO_FRAMES);
+    speed.turbo = false;
+
+    speed.paused = true;
+    assert_eq!(speed.frames_to_run(false), 0);
+    assert_eq!(speed.frames_to_run(true), 1);
+    speed.paused = false;
+
+    speed.slow_motion = true;
+    let total: u32 = (0..SLOW_MOTION_DIVISOR * 3).map(|_| speed.frames_to_run(false)).sum();
+    assert_eq!(total, 3);
+}
+
+#[test]
+fn test_speed_up_down() {
+    let mut speed = Speed::new(10);
+    speed.speed_up();
+    assert_eq!(speed.insns_per_frame, 12);
+    speed.speed_down();
+    speed.speed_down();
+    assert_eq!(speed.insns_per_frame, 8);
+
+    let mut speed = Speed::new(1);
+    speed.speed_down();
+    assert_eq!(speed.insns_per_frame, 1);
+    speed.speed_up();
+    assert_eq!(speed.insns_per_frame, 2);
+}
+
+#[test]
+fn test_frame_deadline() {
+    let start = Instant::now();
+    assert_eq!(frame_deadline(start, FRAMES_PER_SECOND as u64), start + Duration::from_secs(1));
+}
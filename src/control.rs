@@ -0,0 +1,479 @@
+// control.rs
+
+//! A JSON-RPC 2.0 server for driving the emulator from other
+//! programs (eg: test harnesses), started with --control. It
+//! listens on a TCP address or on a Unix socket; requests and
+//! responses are JSON objects, one per line:
+//!
+//! ```text
+//! --> {"jsonrpc": "2.0", "id": 1, "method": "read_memory", "params": {"address": 512, "length": 2}}
+//! <-- {"jsonrpc": "2.0", "id": 1, "result": [96, 5]}
+//! ```
+//!
+//! The methods are:
+//!
+//! ```text
+//! status                          {paused, frame, insns_per_frame, fault}
+//! pause, resume
+//! step {frames = 1}               run frames while paused; returns {frame}
+//! reset, quit
+//! registers                       {pc, i, v: [16 values], delay, sound}
+//! set_register {name, value}      name is v0 to vf, i or pc
+//! read_memory {address, length}   an array of bytes
+//! write_memory {address, data}    data is an array of bytes
+//! press {key}, release {key}      held until released
+//! screenshot {scale = 1}          {png}, a base64 PNG image
+//! save_state                      {state}, in base64
+//! load_state {state}
+//! subscribe {display = false}     a "frame" notification after
+//! unsubscribe                     every frame: {frame, beeping,
+//!                                 display}; display is in base64,
+//!                                 one byte per pixel
+//! ```
+//!
+//! Requests are answered between frames, in order. There is no
+//! authentication: anyone who can connect has full control.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use serde_json::{self, Map, Value};
+
+use chip8::Command;
+use cpu::{CPU, MEM_SIZE, NUM_KEYS, NUM_REGS};
+use palette::Palette;
+use screenshot;
+
+/// JSON-RPC error codes.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The request cannot be carried out now (eg: a step while the
+/// emulator is running).
+const NOT_ALLOWED: i64 = -32000;
+
+/// Most frames run by one step (a minute's worth), so that it
+/// does not hold up the emulator and the other clients for long.
+const MAX_STEP: u64 = 3600;
+
+/// What the connection threads tell the emulator.
+enum Event {
+    Connected(usize, Sender<String>),
+    /// A line from a client.
+    Request(usize, String),
+    Disconnected(usize),
+}
+
+struct Client {
+    /// Lines for the client's writer thread.
+    tx: Sender<String>,
+    /// Set if the client wants frame notifications; true if
+    /// with the display.
+    subscribed: Option<bool>,
+}
+
+/// The state of the emulator, as reported by `status'.
+pub struct Status {
+    pub paused: bool,
+    /// The number of frames run.
+    pub frame: u64,
+    pub insns_per_frame: u32,
+    /// Set when playing with someone on another machine; the
+    /// game must then change through the keys only, and keep
+    /// running in step with the other side.
+    pub netplay: bool,
+}
+
+/// An error response: a JSON-RPC code and a message.
+type Error = (i64, String);
+
+fn invalid_params(msg: String) -> Error {
+    (INVALID_PARAMS, msg)
+}
+
+pub struct Control {
+    address: String,
+    events: Receiver<Event>,
+    clients: HashMap<usize, Client>,
+    /// Keys held down by the clients.
+    keys: [bool; NUM_KEYS],
+    /// The client and request id (None for a notification) of
+    /// a step in progress.
+    step: Option<(usize, Option<Value>)>,
+    /// Commands for the emulator, returned by `poll'.
+    commands: Vec<Command>,
+    /// The Unix socket, removed when the server stops.
+    socket: Option<PathBuf>,
+}
+
+/// Read requests from a client and write its responses, on
+/// two threads of their own.
+fn serve<S>(id: usize, reader: S, mut writer: S, events: Sender<Event>)
+    where S: io::Read + Write + Send + 'static {
+    let (tx, rx) = mpsc::channel::<String>();
+    if events.send(Event::Connected(id, tx)).is_err() {
+        return;
+    }
+    thread::spawn(move || {
+        for line in rx {
+            if writeln!(writer, "{}", line).and_then(|_| writer.flush()).is_err() {
+                break;
+            }
+        }
+    });
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            match line {
+                Ok(line) => {
+                    if events.send(Event::Request(id, line)).is_err() {
+                        return;
+                    }
+                },
+                Err(_) => break,
+            }
+        }
+        let _ = events.send(Event::Disconnected(id));
+    });
+}
+
+/// Accept connections on `listener' until the emulator stops.
+macro_rules! accept_loop {
+    ($listener:expr, $events:expr) => {
+        thread::spawn(move || {
+            for (id, stream) in $listener.incoming().enumerate() {
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(_) => continue,
+                };
+                match stream.try_clone() {
+                    Ok(writer) => serve(id, stream, writer, $events.clone()),
+                    Err(_) => continue,
+                }
+            }
+        })
+    };
+}
+
+impl Control {
+    /// Start listening on `address': "unix:<path>" for a Unix
+    /// socket, otherwise a TCP address such as
+    /// "127.0.0.1:7000". A port on its own listens on
+    /// localhost.
+    pub fn bind(address: &str) -> Result<Control, String> {
+        let (tx, rx) = mpsc::channel();
+        let err = |e: io::Error| format!("--control {}: {}", address, e);
+        let mut control = Control {
+            address: String::new(),
+            events: rx,
+            clients: HashMap::new(),
+            keys: [false; NUM_KEYS],
+            step: None,
+            commands: Vec::new(),
+            socket: None,
+        };
+        if let Some(path) = address.strip_prefix("unix:") {
+            control.bind_unix(path, tx).map_err(err)?;
+        } else {
+            let address = if address.parse::<u16>().is_ok() {
+                format!("127.0.0.1:{}", address)
+            } else {
+                address.to_string()
+            };
+            let listener = TcpListener::bind(&address).map_err(err)?;
+            control.address = listener.local_addr().map_err(err)?.to_string();
+            accept_loop!(listener, tx);
+        }
+        Ok(control)
+    }
+
+    #[cfg(unix)]
+    fn bind_unix(&mut self, path: &str, tx: Sender<Event>) -> io::Result<()> {
+        let listener = UnixListener::bind(path)?;
+        self.address = format!("unix:{}", path);
+        self.socket = Some(PathBuf::from(path));
+        accept_loop!(listener, tx);
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn bind_unix(&mut self, _path: &str, _tx: Sender<Event>) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "Unix sockets are not supported"))
+    }
+
+    /// The address the server listens on, with the port number
+    /// if it was chosen by the system.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// The keys held down by the clients.
+    pub fn keys(&self) -> [bool; NUM_KEYS] {
+        self.keys
+    }
+
+    /// Answer the pending requests. A step stops the answering
+    /// until the frames have been run (see `end_frames'), so
+    /// that the next requests see their effect. Returns the
+    /// commands for the emulator.
+    pub fn poll(&mut self, c: &mut CPU, palette: &Palette, status: &Status) -> Vec<Command> {
+        while self.step.is_none() {
+            match self.events.try_recv() {
+                Ok(Event::Connected(id, tx)) => {
+                    self.clients.insert(id, Client { tx, subscribed: None });
+                },
+                Ok(Event::Request(id, line)) => {
+                    if let Some(response) = self.request(id, &line, c, palette, status) {
+                        self.send(id, &response);
+                    }
+                },
+                Ok(Event::Disconnected(id)) => {
+                    self.clients.remove(&id);
+                },
+                Err(_) => break,
+            }
+        }
+        mem::take(&mut self.commands)
+    }
+
+    /// Send the frame notifications, after each frame.
+    pub fn frame(&mut self, c: &CPU, frame: u64) {
+        let display: Vec<u8> = c.display().rows().flat_map(|row| row.iter().cloned()).collect();
+        for client in self.clients.values() {
+            if let Some(with_display) = client.subscribed {
+                let mut params = json!({"frame": frame, "beeping": c.beeping()});
+                if with_display {
+                    params["display"] = Value::from(base64_encode(&display));
+                }
+                let notification = json!({"jsonrpc": "2.0", "method": "frame", "params": params});
+                let _ = client.tx.send(notification.to_string());
+            }
+        }
+    }
+
+    /// Answer the step in progress, once the frames of the
+    /// last `Command::Step' have been run.
+    pub fn end_frames(&mut self, frame: u64) {
+        if let Some((client, Some(id))) = self.step.take() {
+            self.send(client, &json!({"jsonrpc": "2.0", "id": id, "result": {"frame": frame}}));
+        }
+    }
+
+    fn send(&self, client: usize, response: &Value) {
+        if let Some(client) = self.clients.get(&client) {
+            let _ = client.tx.send(response.to_string());
+        }
+    }
+
+    /// Handle a request line, returning the response (None for
+    /// notifications and steps).
+    fn request(&mut self, client: usize, line: &str, c: &mut CPU, palette: &Palette,
+               status: &Status) -> Option<Value> {
+        let error = |id: Value, (code, message): Error| {
+            json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+        };
+        let request: Value = match serde_json::from_str(line) {
+            Ok(r) => r,
+            Err(e) => return Some(error(Value::Null, (PARSE_ERROR, e.to_string()))),
+        };
+        let id = request.get("id").cloned();
+        let method = match request.get("method").and_then(Value::as_str) {
+            Some(m) if request.get("jsonrpc") == Some(&Value::from("2.0")) => m,
+            _ => return Some(error(id.unwrap_or(Value::Null),
+                                   (INVALID_REQUEST, String::from("not a JSON-RPC 2.0 request")))),
+        };
+        let params = match request.get("params") {
+            None => Map::new(),
+            Some(Value::Object(p)) => p.clone(),
+            Some(_) => return id.map(|id| error(id, invalid_params(String::from("params must be an object")))),
+        };
+        let result = self.call(client, method, &params, c, palette, status);
+        if method == "step" && result.is_ok() {
+            self.step = Some((client, id));
+            return None;
+        }
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(e) => error(id, e),
+        })
+    }
+
+    fn call(&mut self, client: usize, method: &str, params: &Map<String, Value>, c: &mut CPU,
+            palette: &Palette, status: &Status) -> Result<Value, Error> {
+        if status.netplay && matches!(method, "set_register" | "write_memory" | "load_state"
+                                               | "pause" | "resume" | "reset") {
+            return Err((NOT_ALLOWED, String::from("not during netplay")));
+        }
+        match method {
+            "status" => Ok(json!({
+                "paused": status.paused,
+                "frame": status.frame,
+                "insns_per_frame": status.insns_per_frame,
+                "fault": c.fault().map(|f| f.to_string()),
+            })),
+            "pause" | "resume" => {
+                self.commands.push(Command::Pause(method == "pause"));
+                Ok(Value::Bool(true))
+            },
+            "step" => {
+                if !status.paused {
+                    return Err((NOT_ALLOWED, String::from("pause first")));
+                }
+                let frames = uint_param(params, "frames", MAX_STEP)?.unwrap_or(1);
+                self.commands.push(Command::Step(frames as u32));
+                Ok(Value::Null)
+            },
+            "reset" => {
+                self.commands.push(Command::Reset);
+                Ok(Value::Bool(true))
+            },
+            "quit" => {
+                self.commands.push(Command::Quit);
+                Ok(Value::Bool(true))
+            },
+            "registers" => {
+                let (delay, sound) = c.timers();
+                let v: Vec<u8> = (0..NUM_REGS).map(|n| c.v(n)).collect();
+                Ok(json!({"pc": c.pc(), "i": c.i(), "v": v, "delay": delay, "sound": sound}))
+            },
+            "set_register" => {
+                let name = params.get("name").and_then(Value::as_str)
+                            .ok_or_else(|| invalid_params(String::from("missing name")))?;
+                let max = if name.starts_with('v') { 0xff } else { MEM_SIZE as u64 - 1 };
+                let value = required(uint_param(params, "value", max)?, "value")? as usize;
+                match name {
+                    "pc" => c.set_pc(value),
+                    "i" => c.set_i(value),
+                    _ => match name.strip_prefix('v').and_then(|n| usize::from_str_radix(n, 16).ok()) {
+                        Some(n) if n < NUM_REGS && name.len() == 2 => c.set_v(n, value as u8),
+                        _ => return Err(invalid_params(format!("unknown register: {}", name))),
+                    },
+                }
+                Ok(Value::Bool(true))
+            },
+            "read_memory" => {
+                let addr = required(uint_param(params, "address", MEM_SIZE as u64)?, "address")? as usize;
+                let len = required(uint_param(params, "length", (MEM_SIZE - addr) as u64)?, "length")? as usize;
+                Ok(Value::from(&c.mem()[addr .. addr + len]))
+            },
+            "write_memory" => {
+                let addr = required(uint_param(params, "address", MEM_SIZE as u64)?, "address")? as usize;
+                let data = params.get("data").and_then(Value::as_array)
+                            .ok_or_else(|| invalid_params(String::from("missing data")))?;
+                if data.len() > MEM_SIZE - addr {
+                    return Err(invalid_params(String::from("data out of range")));
+                }
+                let bytes = data.iter()
+                            .map(|b| b.as_u64().filter(|b| *b <= 0xff).map(|b| b as u8))
+                            .collect::<Option<Vec<u8>>>()
+                            .ok_or_else(|| invalid_params(String::from("data must be bytes")))?;
+                c.mem_mut()[addr .. addr + bytes.len()].copy_from_slice(&bytes);
+                Ok(Value::Bool(true))
+            },
+            "press" | "release" => {
+                let key = required(uint_param(params, "key", NUM_KEYS as u64 - 1)?, "key")?;
+                self.keys[key as usize] = method == "press";
+                Ok(Value::Bool(true))
+            },
+            "screenshot" => {
+                let scale = uint_param(params, "scale", 64)?.unwrap_or(1).max(1);
+                let mut png = Vec::new();
+                screenshot::write_png(&mut png, c.display(), palette, scale as usize)
+                    .map_err(|e| (NOT_ALLOWED, e))?;
+                Ok(json!({"png": base64_encode(&png)}))
+            },
+            "save_state" => Ok(json!({"state": base64_encode(&c.save_state())})),
+            "load_state" => {
+                let state = params.get("state").and_then(Value::as_str)
+                            .ok_or_else(|| invalid_params(String::from("missing state")))?;
+                let state = base64_decode(state).map_err(invalid_params)?;
+                c.load_state(&state).map_err(invalid_params)?;
+                Ok(Value::Bool(true))
+            },
+            "subscribe" | "unsubscribe" => {
+                let with_display = params.get("display").and_then(Value::as_bool).unwrap_or(false);
+                if let Some(client) = self.clients.get_mut(&client) {
+                    client.subscribed = if method == "subscribe" { Some(with_display) } else { None };
+                }
+                Ok(Value::Bool(true))
+            },
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method: {}", method))),
+        }
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.socket {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Return the parameter `name', which must be a whole number
+/// no larger than `max', if given.
+fn uint_param(params: &Map<String, Value>, name: &str, max: u64) -> Result<Option<u64>, Error> {
+    match params.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(v) => match v.as_u64() {
+            Some(n) if n <= max => Ok(Some(n)),
+            _ => Err(invalid_params(format!("{} must be a number from 0 to {}", name, max))),
+        },
+    }
+}
+
+fn required<T>(value: Option<T>, name: &str) -> Result<T, Error> {
+    value.ok_or_else(|| invalid_params(format!("missing {}", name)))
+}
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode `data' in base64, with padding.
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (k, b)| n | u32::from(*b) << (16 - 8 * k));
+        for k in 0..4 {
+            if k <= chunk.len() {
+                text.push(BASE64_CHARS[(n >> (18 - 6 * k) & 0x3f) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// Decode base64 text, with or without padding.
+pub fn base64_decode(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim_end_matches('=');
+    let mut data = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut n, mut bits) = (0u32, 0);
+    for ch in text.bytes() {
+        let val = BASE64_CHARS.iter().position(|c| *c == ch)
+                    .ok_or_else(|| format!("invalid base64 character: {:?}", ch as char))?;
+        n = n << 6 | val as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((n >> bits) as u8);
+        }
+    }
+    if bits >= 6 {
+        return Err(String::from("invalid base64 length"));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+#[path="./control_test.rs"]
+mod control_test;
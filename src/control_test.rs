@@ -0,0 +1,196 @@
+
+use super::*;
+use std::io::BufRead;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use cpu::PC_START;
+use framebuffer::{WIDTH, HEIGHT};
+
+/// A server on a port chosen by the system, with a connected
+/// client.
+struct Harness {
+    control: Control,
+    cpu: CPU,
+    palette: Palette,
+    status: Status,
+    client: BufReader<TcpStream>,
+}
+
+impl Harness {
+    fn new() -> Harness {
+        let control = Control::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(control.address()).unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        let mut cpu = CPU::new();
+        // Instructions: 0x6005 0x7001 0x1202
+        cpu.load_bytes(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x02], PC_START);
+        Harness {
+            control,
+            cpu,
+            palette: Palette::default(),
+            status: Status { paused: false, frame: 0, insns_per_frame: 10, netplay: false },
+            client: BufReader::new(client),
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        writeln!(self.client.get_mut(), "{}", line).unwrap();
+    }
+
+    /// Poll the server until the client gets a line, returning
+    /// it and the commands for the emulator.
+    fn receive(&mut self) -> (Value, Vec<Command>) {
+        let start = Instant::now();
+        let mut commands = Vec::new();
+        let mut line = String::new();
+        while !line.ends_with('\n') {
+            assert!(start.elapsed() < Duration::from_secs(5), "no response");
+            commands.extend(self.control.poll(&mut self.cpu, &self.palette, &self.status));
+            let _ = self.client.read_line(&mut line);
+        }
+        (serde_json::from_str(&line).unwrap(), commands)
+    }
+
+    /// Call `method', returning the result or the error.
+    fn call(&mut self, method: &str, params: Value) -> Value {
+        let request = json!({"jsonrpc": "2.0", "id": 7, "method": method, "params": params});
+        self.send(&request.to_string());
+        let (response, _) = self.receive();
+        assert_eq!(response["id"], 7);
+        match response.get("result") {
+            Some(result) => result.clone(),
+            None => response["error"].clone(),
+        }
+    }
+}
+
+#[test]
+fn test_base64() {
+    for (data, text) in &[(&b""[..], ""), (b"f", "Zg=="), (b"fo", "Zm8="), (b"foo", "Zm9v"),
+                          (b"foob", "Zm9vYg=="), (b"\xff\x00\xfe", "/wD+")] {
+        assert_eq!(base64_encode(data), *text);
+        assert_eq!(base64_decode(text).unwrap(), *data);
+    }
+    assert_eq!(base64_decode("Zm8").unwrap(), b"fo");
+    assert!(base64_decode("Zm9v!").is_err());
+    assert!(base64_decode("Z").is_err());
+}
+
+#[test]
+fn test_registers_and_memory() {
+    let mut h = Harness::new();
+    h.cpu.execute_insn();
+    let regs = h.call("registers", json!({}));
+    assert_eq!(regs["pc"], 0x202);
+    assert_eq!(regs["v"][0], 5);
+    assert_eq!(regs["v"].as_array().unwrap().len(), NUM_REGS);
+
+    assert_eq!(h.call("set_register", json!({"name": "vf", "value": 9})), true);
+    assert_eq!(h.call("set_register", json!({"name": "i", "value": 0x300})), true);
+    assert_eq!((h.cpu.v(0xf), h.cpu.i()), (9, 0x300));
+    assert_eq!(h.call("set_register", json!({"name": "v16", "value": 1}))["code"], INVALID_PARAMS);
+    assert_eq!(h.call("set_register", json!({"name": "v1", "value": 256}))["code"], INVALID_PARAMS);
+
+    assert_eq!(h.call("write_memory", json!({"address": 0x300, "data": [1, 2, 3]})), true);
+    assert_eq!(h.call("read_memory", json!({"address": 0x2ff, "length": 3})), json!([0, 1, 2]));
+    assert_eq!(h.call("read_memory", json!({"address": 4095, "length": 2}))["code"], INVALID_PARAMS);
+    assert_eq!(h.call("write_memory", json!({"address": 0, "data": [256]}))["code"], INVALID_PARAMS);
+}
+
+#[test]
+fn test_commands_and_keys() {
+    let mut h = Harness::new();
+    h.send(r#"{"jsonrpc": "2.0", "id": 1, "method": "pause"}"#);
+    assert_eq!(h.receive().1, vec![Command::Pause(true)]);
+
+    assert_eq!(h.call("step", json!({}))["code"], NOT_ALLOWED);
+    h.status.paused = true;
+    h.send(r#"{"jsonrpc": "2.0", "id": 2, "method": "step", "params": {"frames": 3}}"#);
+    h.send(r#"{"jsonrpc": "2.0", "id": 3, "method": "status"}"#);
+    // The status waits for the frames to be run.
+    let start = Instant::now();
+    let mut commands = Vec::new();
+    while commands.is_empty() && start.elapsed() < Duration::from_secs(5) {
+        commands = h.control.poll(&mut h.cpu, &h.palette, &h.status);
+    }
+    assert_eq!(commands, vec![Command::Step(3)]);
+    assert!(h.control.poll(&mut h.cpu, &h.palette, &h.status).is_empty());
+    h.status.frame = 3;
+    h.control.end_frames(3);
+    let (response, _) = h.receive();
+    assert_eq!(response, json!({"jsonrpc": "2.0", "id": 2, "result": {"frame": 3}}));
+    let (response, _) = h.receive();
+    assert_eq!(response["result"]["frame"], 3);
+    assert_eq!(response["result"]["fault"], Value::Null);
+    assert_eq!(h.call("step", json!({"frames": MAX_STEP + 1}))["code"], INVALID_PARAMS);
+
+    h.call("press", json!({"key": 0xa}));
+    h.call("press", json!({"key": 2}));
+    h.call("release", json!({"key": 0xa}));
+    let mut keys = [false; NUM_KEYS];
+    keys[2] = true;
+    assert_eq!(h.control.keys(), keys);
+    assert_eq!(h.call("press", json!({"key": 16}))["code"], INVALID_PARAMS);
+}
+
+#[test]
+fn test_state_and_screenshot() {
+    let mut h = Harness::new();
+    let state = h.call("save_state", json!({}))["state"].as_str().unwrap().to_string();
+    h.cpu.execute_insn();
+    assert_eq!(h.call("load_state", json!({"state": state})), true);
+    assert_eq!(h.cpu.pc(), PC_START);
+    assert_eq!(h.call("load_state", json!({"state": "AAAA"}))["code"], INVALID_PARAMS);
+
+    let png = h.call("screenshot", json!({"scale": 2}))["png"].as_str().unwrap().to_string();
+    assert!(base64_decode(&png).unwrap().starts_with(b"\x89PNG"));
+}
+
+#[test]
+fn test_netplay() {
+    let mut h = Harness::new();
+    h.status.netplay = true;
+    let state = h.call("save_state", json!({}))["state"].clone();
+    assert_eq!(h.call("load_state", json!({"state": state}))["code"], NOT_ALLOWED);
+    assert_eq!(h.call("write_memory", json!({"address": 0x300, "data": [1]}))["code"], NOT_ALLOWED);
+    assert_eq!(h.call("set_register", json!({"name": "v0", "value": 1}))["code"], NOT_ALLOWED);
+    assert_eq!((h.cpu.mem()[0x300], h.cpu.v(0)), (0, 0));
+    for method in &["pause", "resume", "reset"] {
+        h.send(&json!({"jsonrpc": "2.0", "id": 1, "method": method}).to_string());
+        let (response, commands) = h.receive();
+        assert_eq!(response["error"]["code"], NOT_ALLOWED);
+        assert_eq!(commands, vec![]);
+    }
+    // Keys are sent to the other player like the local ones.
+    assert_eq!(h.call("press", json!({"key": 1})), true);
+}
+
+#[test]
+fn test_frame_events() {
+    let mut h = Harness::new();
+    assert_eq!(h.call("subscribe", json!({"display": true})), true);
+    h.control.frame(&h.cpu, 1);
+    let (event, _) = h.receive();
+    assert_eq!(event["method"], "frame");
+    assert_eq!(event["params"]["frame"], 1);
+    let display = base64_decode(event["params"]["display"].as_str().unwrap()).unwrap();
+    assert_eq!(display.len(), WIDTH * HEIGHT);
+
+    h.call("unsubscribe", json!({}));
+    h.control.frame(&h.cpu, 2);
+    assert_eq!(h.call("status", json!({}))["frame"], 0);
+}
+
+#[test]
+fn test_errors() {
+    let mut h = Harness::new();
+    h.send("{not json");
+    assert_eq!(h.receive().0["error"]["code"], PARSE_ERROR);
+    h.send(r#"{"id": 1, "method": "status"}"#);
+    assert_eq!(h.receive().0["error"]["code"], INVALID_REQUEST);
+    assert_eq!(h.call("bogus", json!({}))["code"], METHOD_NOT_FOUND);
+    // Notifications get no response, not even errors.
+    h.send(r#"{"jsonrpc": "2.0", "method": "bogus"}"#);
+    h.send(r#"{"jsonrpc": "2.0", "method": "release", "params": {"key": 1}}"#);
+    assert_eq!(h.call("status", json!({}))["paused"], false);
+}
@@ -4,40 +4,72 @@
 /// (1) <http://devernay.free.fr/hacks/chip8/C8TECH10.HTM>
 /// (2) <https://en.wikipedia.org/wiki/CHIP-8>
 
-use std::io::prelude::*;
-use std::fs::File;
-use std::collections::HashMap;
-use rand;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use rand::{self, Rng, SeedableRng, XorShiftRng};
 
-use screen;
+use framebuffer::{self, Framebuffer};
+use font;
+use quirks::Quirks;
+use bounds::{Bounds, BoundsPolicy, Fault, FaultKind};
+use stack::{Stack, StackModel};
 
 /// CHIP-8 Memory is 4K bytes in size
-const MEM_SIZE: usize = 4096;
+pub const MEM_SIZE: usize = 4096;
 
 /// There are 16 general purpose registers in the CHIP-8,
 /// named V0 to VF. VF is used as a flag register in some
 /// instructions and it is better to avoid using it for 
 /// other purposes.
-const NUM_REGS: usize = 16; 
-
-/// The stack has 24 entries, each one 2 bytes long, for a 
-/// total size of 48 bytes.
-/// 
-/// It occupies locations from 0xea0 to 0xeff, both inclusive.
-/// 
-/// A push operation first increments the stack pointer by 2
-/// and then stores a value at the location that is now pointed
-/// to by the stack pointer. The first push operation (after reset)
-/// should store the value at 0xea0.
-const SP_BOTTOM: usize = 0xe9e;
+pub const NUM_REGS: usize = 16; 
+
+/// Number of keys on the CHIP-8 hex keypad.
+pub const NUM_KEYS: usize = 16;
 
 /// Machine code is stored in memory starting at location
 /// 0x200.
 pub const PC_START: usize = 0x200;
 
+/// The delay and sound timers count down at 60 Hz.
+pub const TIMER_HZ: u32 = 60;
+
+/// Save states start with this, followed by a version number.
+const STATE_MAGIC: &[u8] = b"CH8S";
+const STATE_VERSION: u8 = 1;
+
 /// The Instruction Pointer type 
 type InsnPtr = fn(&mut CPU) -> ();
 
+/// What to do with "0nnn" (SYS, a call to a machine language
+/// routine, which can not be emulated) and with opcodes which
+/// are not CHIP-8 instructions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpcodePolicy {
+    /// Log a warning (once per address) and go on with the
+    /// next instruction.
+    Ignore,
+    /// Stop with a fault.
+    Halt,
+}
+
+impl FromStr for OpcodePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OpcodePolicy, String> {
+        match s {
+            "ignore" => Ok(OpcodePolicy::Ignore),
+            "halt" => Ok(OpcodePolicy::Halt),
+            _ => Err(format!("unknown opcode policy: {}", s)),
+        }
+    }
+}
+
+/// A function called with SYS and unknown opcodes, before the
+/// opcode policy is applied. If it returns true, the opcode is
+/// taken as handled and the PC advances to the next instruction.
+pub type OpcodeHandler = Box<dyn FnMut(&mut CPU, u16) -> bool + Send>;
+
 pub struct CPU {
     /// 4K Memory. 2 byte objects are stored in big-endian
     /// format.
@@ -49,17 +81,43 @@ pub struct CPU {
     /// The address register.
     i: usize,
 
+    /// Interpreter behaviours to follow.
+    quirks: Quirks,
+
+    /// What to do on out of bounds accesses.
+    bounds: Bounds,
+
+    /// What to do with SYS and unknown opcodes.
+    opcode_policy: OpcodePolicy,
+    opcode_handler: Option<OpcodeHandler>,
+
+    /// Addresses of the ignored opcodes which have been
+    /// warned about.
+    warned: HashSet<usize>,
+
+    /// Set when the program does something illegal (according
+    /// to `bounds'); the CPU stops until it is reset.
+    fault: Option<Fault>,
+
+    /// Address of the sprite for digit 0; FX29 points I
+    /// into the font starting here.
+    font_base: usize,
+
     /// The Program Counter, not directly accessible
     /// from CHIP-8 programs.
     pc: usize,
 
-    /// The Stack Pointer, not directly accessible 
-    /// from CHIP-8 programs.
-    sp: usize,
+    /// The call stack, not directly accessible from CHIP-8
+    /// programs (unless it is kept in memory).
+    stack: Stack,
+
+    /// The display. All the display instructions operate on
+    /// this; frontends only show it.
+    display: Framebuffer,
 
-    /// The CPU has a screen attached to it. During
-    /// unit tests, the value of this field will be None.
-    screen: Option<screen::Screen>,
+    /// State of the hex keypad; true if the key is pressed.
+    /// Updated by the frontend through `set_keys'.
+    keys: [bool; NUM_KEYS],
 
     /// The delay timer.
     delay: u8,
@@ -67,22 +125,140 @@ pub struct CPU {
     /// The sound timer.
     sound: u8,
 
+    /// Addresses written by the program since `take_writes',
+    /// if writes are being logged.
+    writes: Option<Vec<usize>>,
+
+    /// Random numbers for CXNN, if they are to be repeatable;
+    /// see `seed_rng'.
+    rng: Option<XorShiftRng>,
+
 } 
 
 impl CPU {
-    pub fn new(screen: Option<screen::Screen>) -> Self {
+    pub fn new() -> Self {
         CPU { 
             mem: [0; MEM_SIZE],
             v: [0; NUM_REGS],
             i: 0,
+            quirks: Quirks::default(),
+            bounds: Bounds::default(),
+            fault: None,
+            opcode_policy: OpcodePolicy::Ignore,
+            opcode_handler: None,
+            writes: None,
+            rng: None,
+            warned: HashSet::new(),
+            font_base: font::DEFAULT_FONT_BASE,
             pc: PC_START,
-            sp: SP_BOTTOM,
-            screen: screen,
+            stack: Stack::new(StackModel::default()),
+            display: Framebuffer::new(),
+            keys: [false; NUM_KEYS],
             delay: 0,
             sound: 0,
         }
     }
 
+    /// Put the CPU back in its power-on state: memory, registers,
+    /// timers and display are cleared. Font and program have to
+    /// be loaded again. Quirks, bounds policies, the stack
+    /// model, the opcode policy and handler, the logging of
+    /// writes and the random number generator are kept.
+    pub fn reset(&mut self) {
+        let (quirks, bounds) = (self.quirks, self.bounds);
+        let stack = Stack::new(self.stack.model());
+        let (policy, handler) = (self.opcode_policy, self.opcode_handler.take());
+        let writes = self.writes.as_ref().map(|_| Vec::new());
+        let rng = self.rng.take();
+        *self = CPU::new();
+        self.quirks = quirks;
+        self.bounds = bounds;
+        self.stack = stack;
+        self.opcode_policy = policy;
+        self.opcode_handler = handler;
+        self.writes = writes;
+        self.rng = rng;
+    }
+
+    /// Make CXNN draw its random numbers from a generator
+    /// seeded with `seed', so that runs can be repeated.
+    pub fn seed_rng(&mut self, seed: u64) {
+        let (lo, hi) = (seed as u32, (seed >> 32) as u32);
+        // The generator must not be seeded with all zeros.
+        self.rng = Some(XorShiftRng::from_seed([lo, hi, lo ^ 0x9e37_79b9, hi ^ 0x7f4a_7c15]));
+    }
+
+    /// Select what to do with SYS and unknown opcodes.
+    pub fn set_opcode_policy(&mut self, policy: OpcodePolicy) {
+        self.opcode_policy = policy;
+    }
+
+    /// Register a function to be called with SYS and unknown
+    /// opcodes; see `OpcodeHandler'. For programs embedding the
+    /// CPU; the emulator itself does not use one.
+    pub fn set_opcode_handler(&mut self, handler: Option<OpcodeHandler>) {
+        self.opcode_handler = handler;
+    }
+
+    /// Select where the stack is kept and how deep it is.
+    /// The stack is emptied.
+    pub fn set_stack_model(&mut self, model: StackModel) {
+        self.stack = Stack::new(model);
+    }
+
+    /// The return addresses on the call stack, from the
+    /// outermost call to the innermost one.
+    pub fn return_addresses(&self) -> Vec<usize> {
+        self.stack.return_addresses(&self.mem)
+    }
+
+    /// Select what to do on out of bounds accesses.
+    pub fn set_bounds(&mut self, bounds: Bounds) {
+        self.bounds = bounds;
+    }
+
+    /// The fault which stopped the CPU, if any.
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
+    /// Deal with an opcode the CPU can not execute: give the
+    /// handler (if any) a chance, then apply the opcode policy.
+    fn bad_opcode(&mut self, kind: FaultKind) {
+        let op = self.opcode();
+        if let Some(mut handler) = self.opcode_handler.take() {
+            let handled = handler(self, op);
+            // The handler may have replaced itself.
+            if self.opcode_handler.is_none() {
+                self.opcode_handler = Some(handler);
+            }
+            if handled {
+                self.inc_pc(1);
+                return;
+            }
+        }
+        match self.opcode_policy {
+            OpcodePolicy::Ignore => {
+                if self.warned.insert(self.pc) {
+                    eprintln!("warning: ignored {}", Fault { kind, pc: self.pc, opcode: Some(op) });
+                }
+                self.inc_pc(1);
+            },
+            OpcodePolicy::Halt => self.raise(kind),
+        }
+    }
+
+    /// Stop the CPU with a fault at the current instruction.
+    fn raise(&mut self, kind: FaultKind) {
+        let opcode = if kind == FaultKind::Pc { None } else { Some(self.opcode()) };
+        self.fault = Some(Fault { kind, pc: self.pc, opcode });
+    }
+
+    /// Select the interpreter behaviours to follow.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
     /// Increment the program counter.
     /// download?logged_out=1&lang=en
     /// Each instruction is 2 bytes long, so 
@@ -91,16 +267,19 @@ impl CPU {
         self.pc += 2 * n;
     }
     
+    /// Return the instruction pointed to by the PC. An
+    /// instruction at 0xfff wraps around to 0x000.
+    fn opcode(&self) -> u16 {
+        (u16::from(self.mem[self.pc]) << 8) | u16::from(self.mem[(self.pc + 1) % MEM_SIZE])
+    }
+
     /// Get the 12 bit memory address encoded as part of 
     /// the instruction.
     /// 
     /// If you have an instruction of the form "1nnn", you
     /// need to get "nnn", the 12 bit address.
     fn get_address(&self) -> usize {
-        let address_high_nibble = self.mem[self.pc] & 0xf;
-        let address_low_byte = self.mem[self.pc + 1];
-        ((address_high_nibble as usize) << 8) + address_low_byte as usize    
-
+        usize::from(self.opcode() & 0xfff)
     }
 
     /// Get the 8 bit constant encoded as part of the instruction.
@@ -108,31 +287,43 @@ impl CPU {
     /// If you have an instruction say "7xnn", this function 
     /// returns "nn".
     fn get_constant(&self) -> u8 {
-        self.mem[self.pc + 1] 
-    }
-
-    /// Copy 2 bytes from a usize value to top-of-stack.
-    /// Value stored on stack is in big endian format.
-    fn copy_16bits_to_tos(&mut self, src: usize) {
-        self.mem[self.sp] = ((src >> 8) & 0xffusize) as u8;
-        self.mem[self.sp + 1] = (src & 0xffusize) as u8;        
-    }
-
-    /// Return the 2 byte value taken from top-of-stack.
-    fn get_16bits_from_tos(&self) -> usize {
-        ((self.mem[self.sp] as usize) << 8) | (self.mem[self.sp + 1] as usize)
+        (self.opcode() & 0xff) as u8
     }
 
     /// Return the lower nibble of the high byte of the 
     /// instruction pointed to by PC.
     fn nibble_x(&self) -> usize {
-        (self.mem[self.pc] & 0xf) as usize
+        usize::from((self.opcode() >> 8) & 0xf)
     }
 
     /// Return the high nibble of the low byte of the
     /// instruction pointed to by PC.
     fn nibble_y(&self) -> usize {
-        ((self.mem[self.pc + 1] >> 4) & 0xf) as usize
+        usize::from((self.opcode() >> 4) & 0xf)
+    }
+
+    /// Return the memory locations of the `len' bytes starting
+    /// at I, applying the memory bounds policy to those beyond
+    /// the end of memory. Returns None (after raising a fault)
+    /// if the access is not allowed; nothing should be read or
+    /// written in that case.
+    fn mem_range(&mut self, len: usize) -> Option<Vec<usize>> {
+        let end = self.i + len;
+        if end > MEM_SIZE && self.bounds.memory == BoundsPolicy::Trap {
+            self.raise(FaultKind::Memory(cmp::max(self.i, MEM_SIZE)));
+            return None;
+        }
+        Some((self.i .. end).map(|addr| addr % MEM_SIZE).collect())
+    }
+
+    /// Set I, applying the memory bounds policy. I is 12 bits
+    /// wide when wrapping and 16 bits wide otherwise; with `Trap'
+    /// an address past the end of memory faults when accessed.
+    pub fn set_i(&mut self, i: usize) {
+        self.i = match self.bounds.memory {
+            BoundsPolicy::Wrap => i % MEM_SIZE,
+            BoundsPolicy::Vip | BoundsPolicy::Trap => i & 0xffff,
+        };
     }
 
     /// Execute a jump instruction of the form "1nnn"
@@ -144,22 +335,30 @@ impl CPU {
     /// Call subroutine.
     /// 
     /// The call instruction is of the form "2nnn".
-    /// The instruction first increments
-    /// the stack pointer by 2 and copies the address of the next
-    /// instruction to the new location on the stack. It then sets
-    /// the program counter to "nnn".
+    /// The instruction pushes the address of the next
+    /// instruction on the stack and then sets the program
+    /// counter to "nnn".
+    /// 
+    /// Pushing onto a full stack is handled according to
+    /// the stack bounds policy.
     fn call(&mut self) {
         let target_address = self.get_address();
         let next_insn_address = self.pc + 2;
-        self.sp += 2;
-        self.copy_16bits_to_tos(next_insn_address);
-        self.pc = target_address;
+        match self.stack.push(&mut self.mem, next_insn_address, self.bounds.stack) {
+            Ok(()) => self.pc = target_address,
+            Err(kind) => self.raise(kind),
+        }
     }
 
     /// Subroutine return. Opcode "0x00ee".
+    /// 
+    /// Returning with an empty stack is handled according to
+    /// the stack bounds policy.
     fn ret(&mut self) {
-        self.pc = self.get_16bits_from_tos();
-        self.sp -= 2;
+        match self.stack.pop(&self.mem, self.bounds.stack) {
+            Ok(addr) => self.pc = addr,
+            Err(kind) => self.raise(kind),
+        }
     }
 
     /// Skip next instruction if v[x] == nn.
@@ -220,11 +419,30 @@ impl CPU {
         self.inc_pc(1);
     }
 
+    /// With the `logic_resets_vf' quirk, set v[f] to 0 as
+    /// the VIP does after 8xy1, 8xy2 and 8xy3.
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.v[0xf] = 0;
+        }
+    }
+
+    /// Return the value to be shifted by 8xy6 and 8xyE:
+    /// v[y] with the `shift_uses_vy' quirk, otherwise v[x].
+    fn shift_operand(&self) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.v[self.nibble_y()]
+        } else {
+            self.v[self.nibble_x()]
+        }
+    }
+
     /// v[x] = v[x] | v[y]
     /// 
     /// This instruction is of the form "8xy1"
     fn assign_vx_or_vy_to_vx(&mut self) {
         self.v[self.nibble_x()] = self.v[self.nibble_x()] | self.v[self.nibble_y()];
+        self.reset_vf_after_logic();
         self.inc_pc(1);
     }
 
@@ -233,6 +451,7 @@ impl CPU {
     /// This instruction is of the form "8xy2"
     fn assign_vx_and_vy_to_vx(&mut self) {
         self.v[self.nibble_x()] = self.v[self.nibble_x()] & self.v[self.nibble_y()];
+        self.reset_vf_after_logic();
         self.inc_pc(1);
     }
 
@@ -241,6 +460,7 @@ impl CPU {
     /// This instruction is of the form "8xy3"
     fn assign_vx_xor_vy_to_vx(&mut self) {
         self.v[self.nibble_x()] = self.v[self.nibble_x()] ^ self.v[self.nibble_y()];
+        self.reset_vf_after_logic();
         self.inc_pc(1);
     }
 
@@ -286,8 +506,10 @@ impl CPU {
     /// and the instruction described in the Wikipedia page. This
     /// implementation follows the Python version available here:
     /// <https://github.com/craigthomas/Chip8Python/blob/master/chip8/cpu.py>
+    /// The COSMAC VIP shifted v[y] instead; see the
+    /// `shift_uses_vy' quirk.
     fn shr_vx(&mut self) {
-        let vx = self.v[self.nibble_x()];
+        let vx = self.shift_operand();
         self.v[0xf] = vx & 1;
         self.v[self.nibble_x()] = vx >> 1;
         self.inc_pc(1);
@@ -319,9 +541,10 @@ impl CPU {
     /// instruction too is implemented differently from what
     /// is given in the Wikipedia page. This implementation is
     /// based on the Python project whose URL is given in the
-    /// comment to the "shr_vx" function.
+    /// comment to the "shr_vx" function. The `shift_uses_vy'
+    /// quirk applies here too.
     fn shl_vx(&mut self) {
-        let vx = self.v[self.nibble_x()];
+        let vx = self.shift_operand();
         self.v[0xf] = (vx >> 7) & 1; 
         self.v[self.nibble_x()] = vx << 1;
         self.inc_pc(1);
@@ -351,9 +574,12 @@ impl CPU {
     /// instruction, add v[0] to it and jump to that
     /// location.
     /// 
-    /// This instruction has the form: "bnnn".
+    /// This instruction has the form: "bnnn". With the
+    /// `jump_uses_vx' quirk, v[x] (x being the high nibble of
+    /// nnn) is added instead of v[0].
     fn jmp_to_address_plus_v0(&mut self) {
-        self.pc = usize::from(self.v[0]) + self.get_address();
+        let x = if self.quirks.jump_uses_vx { self.nibble_x() } else { 0 };
+        self.pc = usize::from(self.v[x]) + self.get_address();
     }
 
     /// v[x] = rand() & nn
@@ -363,17 +589,23 @@ impl CPU {
     /// 
     /// This instruction has the form: "cxnn".
     fn assign_rand_bitand_const_to_vx(&mut self) {
-        let randval = if cfg!(test) { 0xff } else { rand::random::<u8>() };
+        let randval = match self.rng {
+            Some(ref mut rng) => rng.gen::<u8>(),
+            None if cfg!(test) => 0xff,
+            None => rand::random::<u8>(),
+        };
         self.v[self.nibble_x()] = self.get_constant() & randval;
         self.inc_pc(1);
     }
 
     /// i += v[x]
-    /// Assign to i the sum of v[x] and i
+    /// Assign to i the sum of v[x] and i, applying the memory
+    /// bounds policy.
     /// 
     /// This instruction has the form: "fx1e"
     fn assign_i_plus_vx_to_i(&mut self) {
-        self.i += usize::from(self.v[self.nibble_x()]);
+        let i = self.i + usize::from(self.v[self.nibble_x()]);
+        self.set_i(i);
         self.inc_pc(1);
     }
 
@@ -386,33 +618,56 @@ impl CPU {
     /// This instruction has the form: "0xfx33".
     fn store_bcd_of_vx_to_mem(&mut self) {
         let vx = self.v[self.nibble_x()];
-        let (a, b, c) = (vx / 100, (vx / 10) % 10, vx % 10);
-        self.mem[self.i] = a; // most significant digit at lowest address
-        self.mem[self.i + 1] = b;
-        self.mem[self.i + 2] = c;
+        let addrs = match self.mem_range(3) {
+            Some(addrs) => addrs,
+            None => return,
+        };
+        // most significant digit at lowest address
+        for (addr, digit) in addrs.into_iter().zip(&[vx / 100, (vx / 10) % 10, vx % 10]) {
+            self.store(addr, *digit);
+        }
         self.inc_pc(1);
     }
 
     /// Store content of v[0] to v[x] (including v[x])
     /// to memory locations starting from the one whose
-    /// address is stored in the "i" register.
+    /// address is stored in the "i" register. The
+    /// `load_store_increments_i' quirk applies.
     /// 
     /// This instruction has the form: "0xfx55".
     fn store_v0_to_vx_to_mem(&mut self) {
-        for n in 0..self.nibble_x() + 1 {
-            self.mem[self.i + n] = self.v[n];
+        let addrs = match self.mem_range(self.nibble_x() + 1) {
+            Some(addrs) => addrs,
+            None => return,
+        };
+        for (n, addr) in addrs.into_iter().enumerate() {
+            let val = self.v[n];
+            self.store(addr, val);
+        }
+        if self.quirks.load_store_increments_i {
+            let i = self.i + self.nibble_x() + 1;
+            self.set_i(i);
         }
         self.inc_pc(1);
     }
 
     /// Copy the contents of memory locations starting from
     /// the one whose address is stored in the "i" register
-    /// to registers v[0], v[1], ..., v[x].
+    /// to registers v[0], v[1], ..., v[x]. The
+    /// `load_store_increments_i' quirk applies.
     /// 
     /// This instruction has the form: 0xfx65.
     fn fill_v0_to_vx_from_mem(&mut self) {
-        for n in 0..self.nibble_x() + 1 {
-            self.v[n] = self.mem[self.i + n];
+        let addrs = match self.mem_range(self.nibble_x() + 1) {
+            Some(addrs) => addrs,
+            None => return,
+        };
+        for (n, addr) in addrs.into_iter().enumerate() {
+            self.v[n] = self.mem[addr];
+        }
+        if self.quirks.load_store_increments_i {
+            let i = self.i + self.nibble_x() + 1;
+            self.set_i(i);
         }
         self.inc_pc(1);
     }
@@ -434,114 +689,68 @@ impl CPU {
     /// unset, it is otherwise set to 0.
     /// 
     /// A pixel is drawn by Xoring it to the value already present on
-    /// the screen at that location.
+    /// the screen at that location. Sprites wrap around the edges
+    /// of the screen unless the `clip_sprites' quirk is set.
     /// 
     /// References:
     /// (1) <http://www.emulator101.com/chip-8-sprites.html>
     /// (2) <http://tibasicdev.wikidot.com/68k:sprites> (Explains the Xor logic)
     fn draw_sprite(&mut self) {
-        let mut flipped = false;
-        let mut r:bool;
         let (x, y) = (self.v[self.nibble_x()], self.v[self.nibble_y()]);
-        let n = usize::from(self.mem[self.pc + 1] & 0xf);
-
-        self.v[0xf] = 0;
-        if let Some(ref mut scr) = self.screen {
-            for y_index in 0usize .. n {
-                let val = self.mem[self.i + y_index];
-                r = CPU::draw_sprite_row(
-                        scr,    
-                        val, u32::from(x), 
-                        (u32::from(y) + y_index as u32) % u32::from(screen::SCREEN_HEIGHT));
-                if r { flipped = true; }
-            }
-            scr.canvas.present();
-            if flipped { 
-                self.v[0xf] = 1;
-            }
+        let n = usize::from(self.opcode() & 0xf);
+        let sprite: Vec<u8> = match self.mem_range(n) {
+            Some(addrs) => addrs.into_iter().map(|addr| self.mem[addr]).collect(),
+            None => return,
+        };
+
+        let (x, y) = (usize::from(x), usize::from(y));
+        let flipped = if self.quirks.clip_sprites {
+            self.display.xor_sprite_clipped(x, y, &sprite)
         } else {
-            panic!("Error: screen not attached!")
-        }
+            self.display.xor_sprite(x, y, &sprite)
+        };
+        self.v[0xf] = if flipped { 1 } else { 0 };
         self.inc_pc(1);
     }
 
-    /// Draw a row of the sprite at position x, y.
-    /// 
-    /// "val" represents the byte to be drawn.
-    /// Each bit of "val", starting from the leftmost
-    /// one, will be plotted at (x, y), (x+1, y), (x+2, y)
-    /// etc. If "x" exceeds the screen width, it will wrap
-    /// to 0.
-    /// 
-    /// Pixel plotting is done by Xoring the current pixel
-    /// color with the sprite color.
-    fn draw_sprite_row(scr: &mut screen::Screen, val: u8, x: u32, y: u32) -> bool {
-        let mut flipped = false;
-        for i in 0..8 {
-            let _x = (x + i) % u32::from(screen::SCREEN_WIDTH);
-            let current_color = scr.get_pixel(_x, y);
-            let sprite_color = (val >> (7 - i)) & 1;
-            let new_color = current_color ^ sprite_color;
-            if (current_color == 1) && (new_color == 0) {
-                flipped = true;
-            } 
-            scr.draw_pixel(_x, y, screen::PIXEL_COLORS[new_color as usize]);
-        }
-        flipped
-    }
-
     /// Get key press. Pressed key stored in v[x]. Operation
     /// is blocking.
     /// 
     /// This instruction has the form "fx0a".
+    /// 
+    /// Waiting is done by not advancing the PC, so that the
+    /// instruction is executed again; the timers keep running
+    /// and the frontend keeps control in the meanwhile.
     fn get_key(&mut self) {
         let x = self.nibble_x();
-        if let Some(ref mut scr) = self.screen {
-            self.v[x] = scr.read_key_blocking().expect("error: invalid key pressed!");
-        } else {
-            panic!("get_key: screen not attached!")
+        if let Some(k) = self.keys.iter().position(|pressed| *pressed) {
+            self.v[x] = k as u8;
+            self.inc_pc(1);
         }
-        self.inc_pc(1);
     }
 
-    /// Skip the next instruction if the read key
-    /// is equal to the one whose code is stored in
-    /// v[x].
+    /// Skip the next instruction if the key whose
+    /// code is stored in v[x] is pressed.
     /// 
     /// This instruction has the form: "ex9e".
     fn skip_if_key_eq_vx(&mut self) {
         let x = self.nibble_x();
         let mut n = 1;
-        if let Some(ref mut scr) = self.screen {
-            let key = scr.read_key_noblocking();
-            if let Some(k) = key {
-                if k == self.v[x] {
-                    n = 2; // skip next instruction
-                }
-            }
-        } else {
-            panic!("error: screen not attached!");
+        if self.keys[usize::from(self.v[x] & 0xf)] {
+            n = 2; // skip next instruction
         }
         self.inc_pc(n);
     }
 
-    /// Skip the next instruction if the read key
-    /// is not equal to the one whose code is stored
-    /// in v[x].
+    /// Skip the next instruction if the key whose
+    /// code is stored in v[x] is not pressed.
     /// 
     /// This instruction has the form: "exa1".
     fn skip_if_key_ne_vx(&mut self) {
         let x = self.nibble_x();
         let mut n = 2;
-        if let Some(ref mut scr) = self.screen {
-            let key = scr.read_key_noblocking();
-            if let Some(k) = key {
-                if k == self.v[x] {
-                    n = 1; // don't skip next instruction
-                }
-            }
-        } else {
-            panic!("error: screen not attached!");
+        if self.keys[usize::from(self.v[x] & 0xf)] {
+            n = 1; // don't skip next instruction
         }
         self.inc_pc(n);
     }
@@ -573,26 +782,115 @@ impl CPU {
     /// Set the "i" register to address of the sprite
     /// character stored in v[x]. The sprite characters
     /// are from 0 to 0xf. Each character is represented
-    /// by 5 bytes in memory.
+    /// by 5 bytes in memory, starting at the font base.
+    /// Only the low nibble of v[x] is used.
     /// 
     /// This instruction has the form: "fx29".
     fn set_ireg_to_sprite_address(&mut self){
-        self.i = usize::from(self.v[self.nibble_x()] * 5);
+        let digit = usize::from(self.v[self.nibble_x()] & 0xf);
+        self.i = self.font_base + digit * font::GLYPH_BYTES;
         self.inc_pc(1);
     } 
 
-    /// Load program code / font data into memory starting
-    /// at the location mem[offset].
-    pub fn load_rom(&mut self, filename: &str, offset: usize) {
-        let mut f = File::open(filename).
-                    expect(&format!("load_rom: failed to load {}", filename));
-        let mut buf = Vec::new();
-        f.read_to_end(&mut buf).expect("load_rom: failed to read from file");
-        for (index, val) in buf.iter().enumerate() {
+    /// Copy the font into memory at `base' and point FX29
+    /// at it.
+    pub fn load_font(&mut self, data: &[u8], base: usize) {
+        self.mem[base..base + data.len()].copy_from_slice(data);
+        self.font_base = base;
+    }
+
+    /// Copy program code / data into memory starting at
+    /// the location mem[offset]. The data must fit; see
+    /// `rom::check'.
+    pub fn load_bytes(&mut self, data: &[u8], offset: usize) {
+        for (index, val) in data.iter().enumerate() {
             self.mem[offset + index] = *val;
         }
     }
 
+    /// Return the display.
+    pub fn display(&self) -> &Framebuffer {
+        &self.display
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Continue execution at `pc', which must be in memory.
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    /// The address register.
+    pub fn i(&self) -> usize {
+        self.i
+    }
+
+    /// Return register V`n'.
+    pub fn v(&self, n: usize) -> u8 {
+        self.v[n]
+    }
+
+    pub fn set_v(&mut self, n: usize, val: u8) {
+        self.v[n] = val;
+    }
+
+    pub fn mem(&self) -> &[u8] {
+        &self.mem
+    }
+
+    /// Memory, for changes made from outside the program;
+    /// these are not logged as writes.
+    pub fn mem_mut(&mut self) -> &mut [u8] {
+        &mut self.mem
+    }
+
+    /// The delay and sound timers.
+    pub fn timers(&self) -> (u8, u8) {
+        (self.delay, self.sound)
+    }
+
+    /// The state of the hex keypad, as last set.
+    pub fn keys(&self) -> [bool; NUM_KEYS] {
+        self.keys
+    }
+
+    /// Start or stop logging the memory writes made by the
+    /// program (FX33 and FX55; the stack is not included).
+    pub fn log_writes(&mut self, on: bool) {
+        self.writes = if on { Some(Vec::new()) } else { None };
+    }
+
+    /// Return the addresses written since the last call, in
+    /// the order of the writes.
+    pub fn take_writes(&mut self) -> Vec<usize> {
+        match self.writes {
+            Some(ref mut writes) => writes.split_off(0),
+            None => Vec::new(),
+        }
+    }
+
+    /// Write a byte of memory on behalf of the program.
+    fn store(&mut self, addr: usize, val: u8) {
+        self.mem[addr] = val;
+        if let Some(ref mut writes) = self.writes {
+            writes.push(addr);
+        }
+    }
+
+    /// True while the sound timer is running, which is
+    /// when the beeper sounds.
+    pub fn beeping(&self) -> bool {
+        self.sound > 0
+    }
+
+    /// Update the state of the hex keypad. keys[k] is
+    /// true if key k is pressed.
+    pub fn set_keys(&mut self, keys: [bool; NUM_KEYS]) {
+        self.keys = keys;
+    }
+
     pub fn decrement_counters(&mut self) {
         if self.sound > 0 {
             self.sound -= 1;
@@ -602,64 +900,196 @@ impl CPU {
         }
     }
 
-    /// Execute the instruction pointed to by the PC.
+    /// Run one frame: a batch of instructions followed by
+    /// a timer tick. A fault stops the CPU, timers included.
+    pub fn run_frame(&mut self, insns_per_frame: u32) {
+        for _ in 0..insns_per_frame {
+            self.execute_insn();
+            if self.fault.is_some() {
+                return;
+            }
+        }
+        self.decrement_counters();
+    }
+
+    /// Return the state of the machine: memory, registers,
+    /// timers, keys, stack and display. The settings (quirks,
+    /// bounds, stack model and opcode policy) are not saved,
+    /// nor is the fault, if any.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(STATE_MAGIC);
+        out.push(STATE_VERSION);
+        out.extend_from_slice(&self.mem);
+        out.extend_from_slice(&self.v);
+        out.extend_from_slice(&(self.i as u32).to_be_bytes());
+        out.extend_from_slice(&(self.pc as u32).to_be_bytes());
+        out.extend_from_slice(&(self.font_base as u32).to_be_bytes());
+        out.push(self.delay);
+        out.push(self.sound);
+        let keys = self.keys.iter().enumerate()
+                    .fold(0u16, |bits, (k, pressed)| bits | (u16::from(*pressed) << k));
+        out.extend_from_slice(&keys.to_be_bytes());
+        let (top, entries) = self.stack.state();
+        out.extend_from_slice(&(top as i32).to_be_bytes());
+        out.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        for addr in entries {
+            out.extend_from_slice(&(*addr as u32).to_be_bytes());
+        }
+        for row in self.display.rows() {
+            out.extend_from_slice(row);
+        }
+        out
+    }
+
+    /// Restore a state returned by `save_state'. The CPU must
+    /// have the same stack model and stack bounds policy as when
+    /// it was saved. A fault
+    /// is cleared. Nothing is changed if `data' is invalid.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut r = StateReader { data };
+        if r.bytes(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(String::from("not a save state"));
+        }
+        let version = r.u8()?;
+        if version != STATE_VERSION {
+            return Err(format!("unsupported save state version: {}", version));
+        }
+        let mut mem = [0; MEM_SIZE];
+        mem.copy_from_slice(r.bytes(MEM_SIZE)?);
+        let mut v = [0; NUM_REGS];
+        v.copy_from_slice(r.bytes(NUM_REGS)?);
+        let (i, pc, font_base) = (r.u32()? as usize, r.u32()? as usize, r.u32()? as usize);
+        let (delay, sound) = (r.u8()?, r.u8()?);
+        let key_bits = r.u16()?;
+        let top = r.u32()? as i32 as isize;
+        let count = r.u32()? as usize;
+        let entries = (0..count).map(|_| r.u32().map(|a| a as usize))
+                        .collect::<Result<Vec<_>, _>>()?;
+        let pixels = r.bytes(framebuffer::WIDTH * framebuffer::HEIGHT)?;
+        if !r.data.is_empty() {
+            return Err(String::from("save state is too long"));
+        }
+        if i > 0xffff || pc > 0xffff || font_base + font::FONT_SIZE > MEM_SIZE
+            || pixels.iter().any(|p| *p > 1) {
+            return Err(String::from("invalid save state"));
+        }
+        let mut stack = Stack::new(self.stack.model());
+        stack.set_state(top, entries, self.bounds.stack)?;
+
+        self.mem = mem;
+        self.v = v;
+        self.i = i;
+        self.pc = pc;
+        self.font_base = font_base;
+        self.delay = delay;
+        self.sound = sound;
+        for (k, pressed) in self.keys.iter_mut().enumerate() {
+            *pressed = key_bits & (1 << k) != 0;
+        }
+        self.stack = stack;
+        for (n, val) in pixels.iter().enumerate() {
+            self.display.set(n % framebuffer::WIDTH, n / framebuffer::WIDTH, *val);
+        }
+        self.fault = None;
+        Ok(())
+    }
+
+    /// Execute the instruction pointed to by the PC. Does
+    /// nothing once the CPU has stopped with a fault.
     pub fn execute_insn(&mut self) {
+        if self.fault.is_some() {
+            return;
+        }
+        if self.pc > MEM_SIZE - 2 {
+            match self.bounds.pc {
+                BoundsPolicy::Trap => return self.raise(FaultKind::Pc),
+                BoundsPolicy::Wrap | BoundsPolicy::Vip => self.pc %= MEM_SIZE,
+            }
+        }
+        let op = self.opcode();
+        let (hi, lo) = ((op >> 8) as u8, (op & 0xff) as u8);
+
         // Return from subroutine.
         // Instruction: 0x00ee
-        if (self.mem[self.pc] == 0x0) && (self.mem[self.pc + 1] == 0xee) {
+        if (hi == 0x0) && (lo == 0xee) {
             self.ret();
             return;
         }
         // Clear the screen.
         // Instruction format: 0x00e0
-        if (self.mem[self.pc] == 0x0) && (self.mem[self.pc + 1] == 0xe0) {
-            if let Some(ref mut scr) = self.screen {
-                scr.canvas.set_draw_color(screen::PIXEL_COLORS[0]);
-                scr.canvas.clear();
-            } else {
-                panic!("Error: screen not attached!");
-            }
+        if (hi == 0x0) && (lo == 0xe0) {
+            self.display.clear();
             self.inc_pc(1);
             return;    
         }
         // Skip next instruction if key whose code is stored in
         // v[x] is pressed.
-        if (((self.mem[self.pc] >> 4) & 0xf) == 0xe) && 
-            (self.mem[self.pc + 1] == 0x9e) {
+        if (((hi >> 4) & 0xf) == 0xe) && 
+            (lo == 0x9e) {
                 self.skip_if_key_eq_vx();
                 return;
         }
         // Skip next instruction if key whose code is stored in 
         // v[x] is not pressed.
-        if (((self.mem[self.pc] >> 4) & 0xf) == 0xe) &&
-            (self.mem[self.pc + 1] == 0xa1) {
+        if (((hi >> 4) & 0xf) == 0xe) &&
+            (lo == 0xa1) {
                 self.skip_if_key_ne_vx();
                 return;
             }
 
         // Get the leftmost nibble
-        let t = (self.mem[self.pc] >> 4) & 0xf;
-        if ((t >= 1) && (t <= 7)) || ((t >= 9) && (t <= 0xd)) {
-            let insn = INSN_LUT1.get(&t).expect("Bad Instruction");
-            insn(self);
-            return;            
-        }         
-        if t == 8 {
+        let t = (hi >> 4) & 0xf;
+        let insn = match t {
+            0 => return self.bad_opcode(FaultKind::MachineCode),
             // Get the rightmost nibble
-            let t = self.mem[self.pc + 1] & 0xf;
-            let insn = INSN_LUT2.get(&t).expect("Bad Instruction");
-            insn(self);
-            return;
-        }
-        if t == 0xf {
-            let t = self.mem[self.pc + 1];
-            let insn = INSN_LUT3.get(&t).expect("Bad Instruction");
-            insn(self);
-            return;
+            8 => INSN_LUT2.get(&(lo & 0xf)),
+            0xf => INSN_LUT3.get(&lo),
+            _ => INSN_LUT1.get(&t),
+        };
+        match insn {
+            Some(insn) => insn(self),
+            None => self.bad_opcode(FaultKind::UnknownOpcode),
         }
     }
 } 
 
+/// Reads the fields of a save state, in order.
+struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < n {
+            return Err(String::from("save state is too short"));
+        }
+        let (field, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(field)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        CPU::new()
+    }
+}
+
 /// INSN_LUT1 is an instruction lookup table; used for decoding an 
 /// instruction based on its leftmost nibble.
 lazy_static! {
@@ -1,11 +1,12 @@
 
 use super::*;
+use stack::DEFAULT_STACK_DEPTH;
 
 /// Tests all instructions except those doing I/O.
 
 #[test]
 fn test_jump(){
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x1055
     // Jump to 0x55
@@ -17,7 +18,7 @@ fn test_jump(){
 
 #[test]
 fn test_call() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x2134
     // Call subroutine at 0x134
@@ -25,14 +26,15 @@ fn test_call() {
     c.mem[1] = 0x34;
     c.execute_insn();
     assert_eq!(c.pc, 0x134);
-    assert_eq!(c.sp, SP_BOTTOM + 2);
-    assert_eq!(c.mem[c.sp], 0x0);
-    assert_eq!(c.mem[c.sp + 1], 0x2);
+    assert_eq!(c.return_addresses(), vec![0x2]);
+    // The stack is in memory at 0xea0.
+    assert_eq!(c.mem[0xea0], 0x0);
+    assert_eq!(c.mem[0xea1], 0x2);
 }
 
 #[test]
 fn test_ret() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x2134
     // Call subroutine at 0x134
@@ -46,13 +48,13 @@ fn test_ret() {
     c.execute_insn(); // call 0x134   
     c.execute_insn(); // ret
 
-    assert_eq!(c.sp, SP_BOTTOM);
+    assert!(c.return_addresses().is_empty());
     assert_eq!(c.pc, 0x2);    
 }
 
 #[test]
 fn test1_skip_if_vx_eq_nn() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x3a24
     // Skip next instruction if self.v[0xa] == 0x24
@@ -67,7 +69,7 @@ fn test1_skip_if_vx_eq_nn() {
 
 #[test]
 fn test2_skip_if_vx_eq_nn() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x3a24
     // Skip next instruction if self.v[0xa] == 0x24
@@ -82,7 +84,7 @@ fn test2_skip_if_vx_eq_nn() {
 
 #[test]
 fn test1_skip_if_vx_ne_nn() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x4a24
     // Skip next instruction if self.v[0xa] != 0x24
@@ -97,7 +99,7 @@ fn test1_skip_if_vx_ne_nn() {
 
 #[test]
 fn test2_skip_if_vx_ne_nn() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x4a24
     // Skip next instruction if self.v[0xa] == 0x24
@@ -112,7 +114,7 @@ fn test2_skip_if_vx_ne_nn() {
 
 #[test]
 fn test1_skip_if_vx_eq_vy() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x52b0
     // Skip next instruction if self.v[0x2] == self.v[0xb]
@@ -128,7 +130,7 @@ fn test1_skip_if_vx_eq_vy() {
 
 #[test]
 fn test2_skip_if_vx_eq_vy() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x52b0
     // Skip next instruction if self.v[0x2] == self.v[0xb]
@@ -144,7 +146,7 @@ fn test2_skip_if_vx_eq_vy() {
 
 #[test]
 fn test_set_vx_to_nn() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x6c2b
     // Set self.v[0xc] to 0x2b
@@ -159,7 +161,7 @@ fn test_set_vx_to_nn() {
 
 #[test]
 fn test_add_nn_to_vx() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x7405
     // Add 0x5 to self.v[0x4] without changing carry.
@@ -177,7 +179,7 @@ fn test_add_nn_to_vx() {
 
 #[test]
 fn test_assign_vy_to_vx() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x82b0
     // v[2] = v[0xb]
@@ -195,7 +197,7 @@ fn test_assign_vy_to_vx() {
 
 #[test]
 fn test_assign_vx_or_vy_to_vx() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x85c1
     // v[5] = v[5] | v[0xc]
@@ -212,7 +214,7 @@ fn test_assign_vx_or_vy_to_vx() {
 
 #[test]
 fn test_assign_vx_and_vy_to_vx() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Insruction: 0x85c2
     // v[5] = v[5] & v[0xc] 
@@ -229,7 +231,7 @@ fn test_assign_vx_and_vy_to_vx() {
 
 #[test]
 fn test_assign_vx_xor_vy_to_vx() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x85c3
     // v[5] = v[5] ^ v[0xc]
@@ -246,7 +248,7 @@ fn test_assign_vx_xor_vy_to_vx() {
 
 #[test]
 fn test1_assign_vx_plus_vy_to_vx() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x85c4
     // v[5] = v[5] + v[0xc]
@@ -264,7 +266,7 @@ fn test1_assign_vx_plus_vy_to_vx() {
 
 #[test]
 fn test2_assign_vx_plus_vy_to_vx() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x85c4
     // v[5] = v[5] + v[0xc]
@@ -282,7 +284,7 @@ fn test2_assign_vx_plus_vy_to_vx() {
 
 #[test] 
 fn test1_assign_vx_minus_vy_to_vx() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x89d5
     // v[9] = v[9] - v[0xd]
@@ -300,7 +302,7 @@ fn test1_assign_vx_minus_vy_to_vx() {
 
 #[test] 
 fn test2_assign_vx_minus_vy_to_vx() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x89d5
     // v[9] = v[9] - v[0xd]
@@ -318,7 +320,7 @@ fn test2_assign_vx_minus_vy_to_vx() {
 
 #[test]
 fn test_shr_vx() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x8706
     // v[7] = v[7] >> 1
@@ -335,7 +337,7 @@ fn test_shr_vx() {
 
 #[test]
 fn test1_assign_vy_minus_vx_to_vx() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x89e7
     // v[9] = v[0xe] - v[0x9]
@@ -354,7 +356,7 @@ fn test1_assign_vy_minus_vx_to_vx() {
 
 #[test]
 fn test2_assign_vy_minus_vx_to_vx() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x89e7
     // v[9] = v[0xe] - v[0x9]
@@ -373,7 +375,7 @@ fn test2_assign_vy_minus_vx_to_vx() {
 
 #[test]
 fn test_shl_vx() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x870e
     // v[7] = v[7] << 1
@@ -389,7 +391,7 @@ fn test_shl_vx() {
 
 #[test]
 fn test1_skip_if_vx_ne_vy() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x9560
     // skip if v[5] != v[6]
@@ -405,7 +407,7 @@ fn test1_skip_if_vx_ne_vy() {
 
 #[test]
 fn test2_skip_if_vx_ne_vy() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0x9560
     // skip if v[5] != v[6]
@@ -421,7 +423,7 @@ fn test2_skip_if_vx_ne_vy() {
 
 #[test]
 fn test_assign_address_to_ireg() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0xa123
     // i = 0x123
@@ -436,7 +438,7 @@ fn test_assign_address_to_ireg() {
 
 #[test]
 fn test_jmp_to_address_plus_v0() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0xb123
     // pc = 0x123 + v[0]
@@ -451,7 +453,7 @@ fn test_jmp_to_address_plus_v0() {
 
 #[test]
 fn test_assign_rand_bitand_const_to_vx() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0xc75a
     // v[7] = rand() & 0x5a
@@ -468,7 +470,7 @@ fn test_assign_rand_bitand_const_to_vx() {
 
 #[test]
 fn test_assign_i_plus_vx_to_i() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0xf31e
     // i += v[3]
@@ -485,7 +487,7 @@ fn test_assign_i_plus_vx_to_i() {
 
 #[test]
 fn test_store_bcd_of_vx_to_mem() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0xf133
     
@@ -503,7 +505,7 @@ fn test_store_bcd_of_vx_to_mem() {
 
 #[test]
 fn test_store_v0_to_vx_to_mem() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0xff55
     // Store v[0] to v[0xf] to mem,
@@ -525,7 +527,7 @@ fn test_store_v0_to_vx_to_mem() {
 
 #[test]
 fn test_fill_v0_to_vx_from_mem() {
-    let mut c = CPU::new(None);
+    let mut c = CPU::new();
     c.pc = 0;
     // Instruction: 0xff65
     // Store from c.mem[i], c.mem[i+1], ..., c.mem[i+0xf]
@@ -543,4 +545,514 @@ fn test_fill_v0_to_vx_from_mem() {
         assert_eq!(c.v[i], i as u8);
     }
     assert_eq!(c.pc, 2);
-}
\ No newline at end of file
+}
+#[test]
+fn test_draw_sprite() {
+    let mut c = CPU::new();
+    c.pc = 0;
+    // Instruction: 0xd122
+    // Draw the 2 row sprite at c.mem[c.i] at (v[1], v[2])
+
+    c.i = 10;
+    c.mem[10] = 0x80;
+    c.mem[11] = 0x40;
+    c.v[1] = 3;
+    c.v[2] = 4;
+    c.mem[0] = 0xd1;
+    c.mem[1] = 0x22;
+
+    c.execute_insn();
+    assert_eq!(c.display.get(3, 4), 1);
+    assert_eq!(c.display.get(4, 5), 1);
+    assert_eq!(c.display.get(4, 4), 0);
+    assert_eq!(c.v[0xf], 0);
+    assert_eq!(c.pc, 2);
+
+    // Drawing the same sprite again erases it and sets v[f].
+    c.pc = 0;
+    c.execute_insn();
+    assert_eq!(c.display.get(3, 4), 0);
+    assert_eq!(c.display.get(4, 5), 0);
+    assert_eq!(c.v[0xf], 1);
+}
+
+#[test]
+fn test_clear_screen() {
+    let mut c = CPU::new();
+    c.pc = 0;
+    // Instruction: 0x00e0
+
+    c.display.set(1, 1, 1);
+    c.mem[0] = 0x00;
+    c.mem[1] = 0xe0;
+
+    c.execute_insn();
+    assert_eq!(c.display.get(1, 1), 0);
+    assert_eq!(c.pc, 2);
+}
+
+#[test]
+fn test_set_ireg_to_sprite_address() {
+    let mut c = CPU::new();
+    c.load_font(font::FontStyle::Vip.data(), 0x50);
+    c.pc = 0x300;
+    // Instruction: 0xf429
+    // Set i to the address of the sprite for digit v[4].
+
+    c.v[4] = 0xb;
+    c.mem[0x300] = 0xf4;
+    c.mem[0x301] = 0x29;
+
+    c.execute_insn();
+    assert_eq!(c.i, 0x50 + 0xb * 5);
+    assert_eq!(c.mem[c.i], 0xf0);
+    assert_eq!(c.mem[c.i + 1], 0x50);
+    assert_eq!(c.pc, 0x302);
+}
+
+#[test]
+fn test1_skip_if_key_eq_vx() {
+    let mut c = CPU::new();
+    c.pc = 0;
+    // Instruction: 0xe39e
+    // Skip next instruction if key v[3] is pressed.
+
+    c.v[3] = 0xa;
+    c.keys[0xa] = true;
+    c.mem[0] = 0xe3;
+    c.mem[1] = 0x9e;
+
+    c.execute_insn();
+    assert_eq!(c.pc, 4);
+}
+
+#[test]
+fn test2_skip_if_key_eq_vx() {
+    let mut c = CPU::new();
+    c.pc = 0;
+    // Instruction: 0xe39e
+
+    c.v[3] = 0xa;
+    c.keys[0xb] = true;
+    c.mem[0] = 0xe3;
+    c.mem[1] = 0x9e;
+
+    c.execute_insn();
+    assert_eq!(c.pc, 2);
+}
+
+#[test]
+fn test1_skip_if_key_ne_vx() {
+    let mut c = CPU::new();
+    c.pc = 0;
+    // Instruction: 0xe3a1
+    // Skip next instruction if key v[3] is not pressed.
+
+    c.v[3] = 0xa;
+    c.mem[0] = 0xe3;
+    c.mem[1] = 0xa1;
+
+    c.execute_insn();
+    assert_eq!(c.pc, 4);
+}
+
+#[test]
+fn test2_skip_if_key_ne_vx() {
+    let mut c = CPU::new();
+    c.pc = 0;
+    // Instruction: 0xe3a1
+
+    c.v[3] = 0xa;
+    c.keys[0xa] = true;
+    c.mem[0] = 0xe3;
+    c.mem[1] = 0xa1;
+
+    c.execute_insn();
+    assert_eq!(c.pc, 2);
+}
+
+#[test]
+fn test_get_key() {
+    let mut c = CPU::new();
+    c.pc = 0;
+    // Instruction: 0xf50a
+    // Wait for a key press, store the key in v[5].
+
+    c.mem[0] = 0xf5;
+    c.mem[1] = 0x0a;
+
+    // No key pressed: keep waiting.
+    c.execute_insn();
+    assert_eq!(c.pc, 0);
+
+    c.keys[0x7] = true;
+    c.execute_insn();
+    assert_eq!(c.v[5], 0x7);
+    assert_eq!(c.pc, 2);
+}
+
+#[test]
+fn test_quirk_shift_uses_vy() {
+    let mut c = CPU::new();
+    c.set_quirks(Quirks { shift_uses_vy: true, ..Quirks::default() });
+    c.pc = 0;
+    // Instruction: 0x8126
+    // v[1] = v[2] >> 1
+
+    c.v[1] = 0xff;
+    c.v[2] = 0x05;
+    c.mem[0] = 0x81;
+    c.mem[1] = 0x26;
+
+    c.execute_insn();
+    assert_eq!(c.v[1], 0x02);
+    assert_eq!(c.v[0xf], 1);
+}
+
+#[test]
+fn test_quirk_load_store_increments_i() {
+    let mut c = CPU::new();
+    c.set_quirks(Quirks { load_store_increments_i: true, ..Quirks::default() });
+    c.pc = 0;
+    // Instruction: 0xf255
+    // Store v[0] to v[2] at mem[i], leaving i past the end.
+
+    c.i = 0x300;
+    c.mem[0] = 0xf2;
+    c.mem[1] = 0x55;
+
+    c.execute_insn();
+    assert_eq!(c.i, 0x303);
+}
+
+#[test]
+fn test_quirk_logic_resets_vf() {
+    let mut c = CPU::new();
+    c.set_quirks(Quirks { logic_resets_vf: true, ..Quirks::default() });
+    c.pc = 0;
+    // Instruction: 0x8121
+    // v[1] = v[1] | v[2]
+
+    c.v[0xf] = 1;
+    c.mem[0] = 0x81;
+    c.mem[1] = 0x21;
+
+    c.execute_insn();
+    assert_eq!(c.v[0xf], 0);
+}
+
+#[test]
+fn test_quirk_jump_uses_vx() {
+    let mut c = CPU::new();
+    c.set_quirks(Quirks { jump_uses_vx: true, ..Quirks::default() });
+    c.pc = 0;
+    // Instruction: 0xb320
+    // Jump to 0x320 + v[3]
+
+    c.v[0] = 0x10;
+    c.v[3] = 0x04;
+    c.mem[0] = 0xb3;
+    c.mem[1] = 0x20;
+
+    c.execute_insn();
+    assert_eq!(c.pc, 0x324);
+}
+
+#[test]
+fn test_bounds_memory() {
+    // Instruction: 0xf255
+    // Store v[0] to v[2] at mem[i], i being 2 bytes short
+    // of the end of memory.
+    let setup = |policy| {
+        let mut c = CPU::new();
+        c.set_bounds(Bounds::all(policy));
+        c.pc = 0x300;
+        c.i = MEM_SIZE - 2;
+        c.v[2] = 0x42;
+        c.mem[0x300] = 0xf2;
+        c.mem[0x301] = 0x55;
+        c.execute_insn();
+        c
+    };
+
+    let c = setup(BoundsPolicy::Wrap);
+    assert_eq!(c.mem[0], 0x42);
+    assert_eq!(c.fault(), None);
+
+    let c = setup(BoundsPolicy::Trap);
+    assert_eq!(c.fault(), Some(Fault { kind: FaultKind::Memory(MEM_SIZE), pc: 0x300, opcode: Some(0xf255) }));
+    assert_eq!(c.mem[MEM_SIZE - 2], 0);
+    assert_eq!(c.pc, 0x300);
+}
+
+#[test]
+fn test_bounds_add_to_i() {
+    // Instruction: 0xf11e
+    // i += v[1], going past the end of memory.
+    for &(policy, i) in &[(BoundsPolicy::Wrap, 0x00f), (BoundsPolicy::Vip, 0x100f)] {
+        let mut c = CPU::new();
+        c.set_bounds(Bounds::all(policy));
+        c.pc = 0;
+        c.i = 0xff0;
+        c.v[1] = 0x1f;
+        c.mem[0] = 0xf1;
+        c.mem[1] = 0x1e;
+        c.execute_insn();
+        assert_eq!(c.i, i);
+    }
+}
+
+#[test]
+fn test_bounds_add_to_i_stays_16_bit() {
+    // Instruction: 0xf11e, repeated: I never grows past 16 bits.
+    for &policy in &[BoundsPolicy::Trap, BoundsPolicy::Vip] {
+        let mut c = CPU::new();
+        c.set_bounds(Bounds::all(policy));
+        c.pc = 0;
+        c.i = 0xfff0;
+        c.v[1] = 0x1f;
+        c.mem[0] = 0xf1;
+        c.mem[1] = 0x1e;
+        c.execute_insn();
+        assert_eq!(c.i, 0x000f);
+        assert!(c.fault().is_none());
+    }
+}
+
+#[test]
+fn test_bounds_stack() {
+    // Instruction: 0x2000
+    // Call 0x000: recurses until the stack is full.
+    let recurse = |policy, calls| {
+        let mut c = CPU::new();
+        c.set_bounds(Bounds::all(policy));
+        c.pc = 0;
+        c.mem[0] = 0x20;
+        c.mem[1] = 0x00;
+        for _ in 0..calls {
+            c.execute_insn();
+        }
+        c
+    };
+
+    let c = recurse(BoundsPolicy::Trap, 25);
+    assert_eq!(c.fault().map(|f| f.kind), Some(FaultKind::StackOverflow));
+    assert_eq!(c.return_addresses().len(), 24);
+
+    let c = recurse(BoundsPolicy::Wrap, 25);
+    assert_eq!(c.fault(), None);
+    assert_eq!(c.return_addresses().len(), 1);
+
+    let mut c = CPU::new();
+    c.pc = 0;
+    // Instruction: 0x00ee with nothing on the stack.
+    c.mem[0] = 0x00;
+    c.mem[1] = 0xee;
+    c.execute_insn();
+    assert_eq!(c.fault(), Some(Fault { kind: FaultKind::StackUnderflow, pc: 0, opcode: Some(0x00ee) }));
+}
+
+#[test]
+fn test_stack_model() {
+    // 0x300: call 0x400; 0x400: call 0x500.
+    let mut c = CPU::new();
+    c.set_stack_model(StackModel::Array { depth: 16 });
+    c.pc = 0x300;
+    c.mem[0x300] = 0x24;
+    c.mem[0x301] = 0x00;
+    c.mem[0x400] = 0x25;
+    c.mem[0x401] = 0x00;
+    c.execute_insn();
+    c.execute_insn();
+    assert_eq!(c.pc, 0x500);
+    assert_eq!(c.return_addresses(), vec![0x302, 0x402]);
+    // Nothing is stored in memory.
+    assert_eq!(c.mem[0xea0], 0);
+
+    // The model survives a reset.
+    c.reset();
+    assert!(c.return_addresses().is_empty());
+    assert_eq!(c.stack.model(), StackModel::Array { depth: 16 });
+}
+
+#[test]
+fn test_bounds_pc() {
+    let mut c = CPU::new();
+    c.pc = MEM_SIZE;
+    c.execute_insn();
+    assert_eq!(c.fault(), Some(Fault { kind: FaultKind::Pc, pc: MEM_SIZE, opcode: None }));
+
+    let mut c = CPU::new();
+    c.set_bounds(Bounds::all(BoundsPolicy::Wrap));
+    c.pc = MEM_SIZE;
+    // Instruction: 0x6105
+    c.mem[0] = 0x61;
+    c.mem[1] = 0x05;
+    c.execute_insn();
+    assert_eq!(c.v[1], 5);
+    assert_eq!(c.pc, 2);
+}
+
+#[test]
+fn test_opcode_policy() {
+    // Instruction: 0x0123 (SYS), then 0x8128 (unknown)
+    let setup = |policy| {
+        let mut c = CPU::new();
+        c.set_opcode_policy(policy);
+        c.pc = 0;
+        c.mem[0] = 0x01;
+        c.mem[1] = 0x23;
+        c.mem[2] = 0x81;
+        c.mem[3] = 0x28;
+        c
+    };
+
+    let mut c = setup(OpcodePolicy::Ignore);
+    c.execute_insn();
+    c.execute_insn();
+    assert_eq!(c.pc, 4);
+    assert_eq!(c.fault(), None);
+
+    let mut c = setup(OpcodePolicy::Halt);
+    c.execute_insn();
+    assert_eq!(c.fault(), Some(Fault { kind: FaultKind::MachineCode, pc: 0, opcode: Some(0x0123) }));
+    c.pc = 2;
+    c.fault = None;
+    c.execute_insn();
+    assert_eq!(c.fault(), Some(Fault { kind: FaultKind::UnknownOpcode, pc: 2, opcode: Some(0x8128) }));
+}
+
+#[test]
+fn test_opcode_handler() {
+    let mut c = CPU::new();
+    c.set_opcode_policy(OpcodePolicy::Halt);
+    // Handle SYS 0x0nnn by setting v[0] to nn.
+    c.set_opcode_handler(Some(Box::new(|c: &mut CPU, op: u16| {
+        if op >> 12 != 0 {
+            return false;
+        }
+        c.v[0] = (op & 0xff) as u8;
+        true
+    })));
+    c.pc = 0;
+    c.mem[0] = 0x01;
+    c.mem[1] = 0x23;
+    c.mem[2] = 0xe1;
+    c.mem[3] = 0x00;
+
+    c.execute_insn();
+    assert_eq!(c.v[0], 0x23);
+    assert_eq!(c.pc, 2);
+    // Not handled: the policy applies.
+    c.execute_insn();
+    assert_eq!(c.fault().map(|f| f.kind), Some(FaultKind::UnknownOpcode));
+}
+
+#[test]
+fn test_save_state() {
+    let mut c = CPU::new();
+    c.load_bytes(&[0x60, 0x05, 0xf0, 0x29, 0xd1, 0x15, 0x22, 0x00], PC_START);
+    for _ in 0..3 {
+        c.execute_insn();
+    }
+    c.delay = 7;
+    c.keys[0xa] = true;
+    let state = c.save_state();
+
+    let mut d = CPU::new();
+    d.load_state(&state).unwrap();
+    assert_eq!(d.pc, c.pc);
+    assert_eq!(d.i, c.i);
+    assert_eq!(d.v, c.v);
+    assert_eq!(d.delay, 7);
+    assert!(d.keys[0xa]);
+    assert!(d.display().rows().eq(c.display().rows()));
+    assert_eq!(d.save_state(), state);
+
+    // The call is made after the state is restored.
+    d.execute_insn();
+    assert_eq!(d.return_addresses(), vec![PC_START + 8]);
+}
+
+#[test]
+fn test_load_bad_state() {
+    let mut c = CPU::new();
+    let state = c.save_state();
+    let mut bad = state.clone();
+    bad[0] = b'X';
+    assert!(c.load_state(&bad).is_err());
+    assert!(c.load_state(&state[.. state.len() - 1]).is_err());
+    let mut long = state.clone();
+    long.push(0);
+    assert!(c.load_state(&long).is_err());
+    assert!(c.load_state(&state).is_ok());
+}
+
+#[test]
+fn test_load_corrupted_state() {
+    let mut c = CPU::new();
+    let state = c.save_state();
+    // The stack top follows memory, registers, I, PC, the font
+    // base, timers and keys.
+    let top = STATE_MAGIC.len() + 1 + MEM_SIZE + NUM_REGS + 12 + 2 + 2;
+    for bad_top in &[DEFAULT_STACK_DEPTH as i32 + 1, -1, i32::MAX, i32::MIN] {
+        let mut bad = state.clone();
+        bad[top .. top + 4].copy_from_slice(&bad_top.to_be_bytes());
+        assert!(c.load_state(&bad).is_err());
+    }
+    // I and the PC are 16 bits wide.
+    let regs = STATE_MAGIC.len() + 1 + MEM_SIZE + NUM_REGS;
+    for field in &[regs, regs + 4] {
+        let mut bad = state.clone();
+        bad[*field .. *field + 4].copy_from_slice(&0x10000u32.to_be_bytes());
+        assert!(c.load_state(&bad).is_err());
+    }
+    // Pixels are either 0 or 1.
+    let mut bad = state.clone();
+    *bad.last_mut().unwrap() = 2;
+    assert!(c.load_state(&bad).is_err());
+
+    c.load_bytes(&[0x22, 0x00], PC_START);
+    c.execute_insn();
+    assert!(c.load_state(&state).is_ok());
+    assert_eq!(c.return_addresses(), vec![]);
+}
+
+#[test]
+fn test_log_writes() {
+    let mut c = CPU::new();
+    c.pc = 0;
+    c.i = 0x300;
+    c.v[0] = 123;
+    // Instructions: 0xf033, 0xf155
+    c.mem[0..4].copy_from_slice(&[0xf0, 0x33, 0xf1, 0x55]);
+    c.execute_insn();
+    assert_eq!(c.take_writes(), vec![]);
+
+    c.log_writes(true);
+    c.execute_insn();
+    assert_eq!(c.take_writes(), vec![0x300, 0x301]);
+    assert_eq!(c.mem()[0x300], 123);
+    assert_eq!(c.take_writes(), vec![]);
+    // Changes from outside the program are not logged.
+    c.mem_mut()[0x400] = 1;
+    assert_eq!(c.take_writes(), vec![]);
+}
+
+#[test]
+fn test_seed_rng() {
+    // Instructions: 0xc0ff, 0xc1ff, 0xc2ff, 0xc3ff
+    let rand_bytes = |seed| {
+        let mut c = CPU::new();
+        c.seed_rng(seed);
+        c.pc = 0;
+        c.mem[0..8].copy_from_slice(&[0xc0, 0xff, 0xc1, 0xff, 0xc2, 0xff, 0xc3, 0xff]);
+        for _ in 0..4 {
+            c.execute_insn();
+        }
+        c.v[0..4].to_vec()
+    };
+    assert_eq!(rand_bytes(1), rand_bytes(1));
+    assert_ne!(rand_bytes(1), rand_bytes(2));
+    assert_ne!(rand_bytes(0), vec![0xff; 4]);
+}
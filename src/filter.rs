@@ -0,0 +1,156 @@
+// filter.rs
+
+//! Display filters that reduce the flicker of CHIP-8 games.
+//!
+//! CHIP-8 programs move a sprite by erasing it (Xoring it onto
+//! the screen a second time) and drawing it at the new place.
+//! A frame is often captured in between, so the sprite seems to
+//! flicker. The filters here combine several frames to hide this.
+
+use std::collections::VecDeque;
+
+use framebuffer::{self, Framebuffer};
+use chip8::FRAMES_PER_SECOND;
+
+/// Brightness of a pixel which is fully ON.
+pub const MAX_LEVEL: u8 = 255;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Filter {
+    /// A pixel which is turned OFF fades out over `decay_ms'
+    /// milliseconds, like the phosphor of a CRT.
+    Persistence { decay_ms: u32 },
+    /// A pixel is ON if it was ON in any of the last `frames'
+    /// frames.
+    Or { frames: usize },
+    /// Weighted average of the last few frames. weights[0]
+    /// applies to the newest frame, weights[1] to the one
+    /// before it and so on.
+    Blend { weights: Vec<f32> },
+}
+
+impl Filter {
+    /// Build a filter from its name ("persistence", "or" or
+    /// "blend") and the command line parameters.
+    pub fn from_name(name: &str, decay_ms: u32, frames: usize,
+                     weights: &[f32]) -> Result<Filter, String> {
+        match name {
+            "persistence" => Ok(Filter::Persistence { decay_ms }),
+            "or" => Ok(Filter::Or { frames: frames.max(1) }),
+            "blend" => {
+                if weights.is_empty() || !weights.iter().all(|w| w.is_finite() && *w > 0.0) {
+                    return Err(String::from("blend weights must be positive numbers"));
+                }
+                Ok(Filter::Blend { weights: weights.to_vec() })
+            },
+            _ => Err(format!("unknown filter: {}", name)),
+        }
+    }
+
+    /// Number of past frames (including the current one)
+    /// the filter looks at.
+    fn history_len(&self) -> usize {
+        match *self {
+            Filter::Persistence { .. } => 1,
+            Filter::Or { frames } => frames,
+            Filter::Blend { ref weights } => weights.len(),
+        }
+    }
+}
+
+/// Applies a `Filter' to successive frames.
+pub struct DisplayFilter {
+    filter: Filter,
+    /// A disabled filter passes frames through unchanged.
+    enabled: bool,
+    /// The most recent frames, newest first.
+    history: VecDeque<Vec<u8>>,
+    /// Current brightness of each pixel, from 0.0 to 1.0.
+    /// Used by the persistence filter.
+    levels: Vec<f32>,
+    /// Brightness of each pixel, from 0 to MAX_LEVEL, as
+    /// computed by the last call to `apply'.
+    output: Vec<u8>,
+}
+
+impl DisplayFilter {
+    pub fn new(filter: Filter) -> Self {
+        let npixels = framebuffer::WIDTH * framebuffer::HEIGHT;
+        DisplayFilter {
+            filter,
+            enabled: true,
+            history: VecDeque::new(),
+            levels: vec![0.0; npixels],
+            output: vec![0; npixels],
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Turn the filter on or off. The history is cleared, so that
+    /// a filter which is switched on again does not show stale frames.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.history.clear();
+        for l in self.levels.iter_mut() {
+            *l = 0.0;
+        }
+    }
+
+    /// Feed the next frame to the filter. Returns the brightness
+    /// of each pixel (row by row), from 0 to MAX_LEVEL. Should be
+    /// called exactly once per frame.
+    pub fn apply(&mut self, fb: &Framebuffer) -> &[u8] {
+        let frame: Vec<u8> = fb.rows().flat_map(|row| row.iter().cloned()).collect();
+        if !self.enabled {
+            for (out, p) in self.output.iter_mut().zip(frame.iter()) {
+                *out = if *p != 0 { MAX_LEVEL } else { 0 };
+            }
+            return &self.output;
+        }
+
+        self.history.push_front(frame);
+        self.history.truncate(self.filter.history_len());
+
+        match self.filter {
+            Filter::Persistence { decay_ms } => {
+                // Brightness lost by an OFF pixel in one frame.
+                let step = if decay_ms == 0 { 1.0 } else {
+                    1000.0 / (FRAMES_PER_SECOND as f32 * decay_ms as f32)
+                };
+                for (index, p) in self.history[0].iter().enumerate() {
+                    let level = &mut self.levels[index];
+                    *level = if *p != 0 { 1.0 } else { (*level - step).max(0.0) };
+                    self.output[index] = (*level * f32::from(MAX_LEVEL)).round() as u8;
+                }
+            },
+            Filter::Or { .. } => {
+                for index in 0..self.output.len() {
+                    let on = self.history.iter().any(|f| f[index] != 0);
+                    self.output[index] = if on { MAX_LEVEL } else { 0 };
+                }
+            },
+            Filter::Blend { ref weights } => {
+                // Normalize using the weights of the frames seen so
+                // far, so that the first few frames are not dim.
+                let total: f32 = weights.iter().take(self.history.len()).sum();
+                for index in 0..self.output.len() {
+                    let sum: f32 = self.history.iter()
+                                    .zip(weights.iter())
+                                    .filter(|&(f, _)| f[index] != 0)
+                                    .map(|(_, w)| *w)
+                                    .sum();
+                    let level = if total > 0.0 { (sum / total).min(1.0) } else { 0.0 };
+                    self.output[index] = (level * f32::from(MAX_LEVEL)).round() as u8;
+                }
+            },
+        }
+        &self.output
+    }
+}
+
+#[cfg(test)]
+#[path="./filter_test.rs"]
+mod filter_test;
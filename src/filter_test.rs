@@ -0,0 +1,69 @@
+
+use super::*;
+
+/// Index of pixel (x, y) in the filter output.
+fn index(x: usize, y: usize) -> usize {
+    y * framebuffer::WIDTH + x
+}
+
+#[test]
+fn test_disabled_filter() {
+    let mut f = DisplayFilter::new(Filter::Or { frames: 3 });
+    f.set_enabled(false);
+    let mut fb = Framebuffer::new();
+    fb.set(1, 2, 1);
+    assert_eq!(f.apply(&fb)[index(1, 2)], MAX_LEVEL);
+    fb.clear();
+    assert_eq!(f.apply(&fb)[index(1, 2)], 0);
+}
+
+#[test]
+fn test_or_filter() {
+    let mut f = DisplayFilter::new(Filter::Or { frames: 2 });
+    let mut fb = Framebuffer::new();
+    fb.set(1, 2, 1);
+    assert_eq!(f.apply(&fb)[index(1, 2)], MAX_LEVEL);
+    fb.clear();
+    // Still ON: it was ON in the previous frame.
+    assert_eq!(f.apply(&fb)[index(1, 2)], MAX_LEVEL);
+    assert_eq!(f.apply(&fb)[index(1, 2)], 0);
+}
+
+#[test]
+fn test_persistence_filter() {
+    // At 60 frames per second, a decay time of 50ms
+    // takes 3 frames.
+    let mut f = DisplayFilter::new(Filter::Persistence { decay_ms: 50 });
+    let mut fb = Framebuffer::new();
+    fb.set(0, 0, 1);
+    assert_eq!(f.apply(&fb)[0], MAX_LEVEL);
+    fb.clear();
+    assert_eq!(f.apply(&fb)[0], 170);
+    assert_eq!(f.apply(&fb)[0], 85);
+    assert_eq!(f.apply(&fb)[0], 0);
+}
+
+#[test]
+fn test_blend_filter() {
+    let mut f = DisplayFilter::new(Filter::Blend { weights: vec![3.0, 1.0] });
+    let mut fb = Framebuffer::new();
+    fb.set(0, 0, 1);
+    assert_eq!(f.apply(&fb)[0], MAX_LEVEL);
+    fb.clear();
+    // ON in the older frame only: 1 / (3 + 1)
+    assert_eq!(f.apply(&fb)[0], 64);
+    assert_eq!(f.apply(&fb)[0], 0);
+}
+
+#[test]
+fn test_filter_from_name() {
+    assert_eq!(Filter::from_name("or", 0, 0, &[]), Ok(Filter::Or { frames: 1 }));
+    assert_eq!(Filter::from_name("persistence", 80, 0, &[]),
+               Ok(Filter::Persistence { decay_ms: 80 }));
+    assert!(Filter::from_name("blend", 0, 0, &[]).is_err());
+    assert!(Filter::from_name("blend", 0, 0, &[1.0, 0.5]).is_ok());
+    for bad in &[f32::NAN, f32::INFINITY, -1.0, 0.0] {
+        assert!(Filter::from_name("blend", 0, 0, &[1.0, *bad]).is_err());
+    }
+    assert!(Filter::from_name("blur", 0, 0, &[]).is_err());
+}
@@ -0,0 +1,204 @@
+// font.rs
+
+//! Built-in hex digit fonts. FX29 points I at the sprite for
+//! a digit; these fonts provide the sprites without the need
+//! for an external font file.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::str::FromStr;
+
+use cpu;
+
+/// Each hex digit is a sprite 4 pixels wide and 5 rows high,
+/// one byte per row.
+pub const GLYPH_BYTES: usize = 5;
+
+/// Size of a complete font: digits 0 to F.
+pub const FONT_SIZE: usize = 16 * GLYPH_BYTES;
+
+/// Most interpreters load the font at 0x000; some modern
+/// ones use 0x050 instead.
+pub const DEFAULT_FONT_BASE: usize = 0x000;
+
+/// The font used by most emulators (and found in most
+/// CHIP-8 documentation).
+const STANDARD_FONT: [u8; FONT_SIZE] = [
+    0xf0, 0x90, 0x90, 0x90, 0xf0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xf0, 0x10, 0xf0, 0x80, 0xf0, // 2
+    0xf0, 0x10, 0xf0, 0x10, 0xf0, // 3
+    0x90, 0x90, 0xf0, 0x10, 0x10, // 4
+    0xf0, 0x80, 0xf0, 0x10, 0xf0, // 5
+    0xf0, 0x80, 0xf0, 0x90, 0xf0, // 6
+    0xf0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xf0, 0x90, 0xf0, 0x90, 0xf0, // 8
+    0xf0, 0x90, 0xf0, 0x10, 0xf0, // 9
+    0xf0, 0x90, 0xf0, 0x90, 0x90, // A
+    0xe0, 0x90, 0xe0, 0x90, 0xe0, // B
+    0xf0, 0x80, 0x80, 0x80, 0xf0, // C
+    0xe0, 0x90, 0x90, 0x90, 0xe0, // D
+    0xf0, 0x80, 0xf0, 0x80, 0xf0, // E
+    0xf0, 0x80, 0xf0, 0x80, 0x80, // F
+];
+
+/// The font in the ROM of the original COSMAC VIP interpreter.
+const VIP_FONT: [u8; FONT_SIZE] = [
+    0xf0, 0x90, 0x90, 0x90, 0xf0, // 0
+    0x60, 0x20, 0x20, 0x20, 0x70, // 1
+    0xf0, 0x10, 0xf0, 0x80, 0xf0, // 2
+    0xf0, 0x10, 0xf0, 0x10, 0xf0, // 3
+    0xa0, 0xa0, 0xf0, 0x20, 0x20, // 4
+    0xf0, 0x80, 0xf0, 0x10, 0xf0, // 5
+    0xf0, 0x80, 0xf0, 0x90, 0xf0, // 6
+    0xf0, 0x10, 0x10, 0x10, 0x10, // 7
+    0xf0, 0x90, 0xf0, 0x90, 0xf0, // 8
+    0xf0, 0x90, 0xf0, 0x10, 0xf0, // 9
+    0xf0, 0x90, 0xf0, 0x90, 0x90, // A
+    0xf0, 0x50, 0x70, 0x50, 0xf0, // B
+    0xf0, 0x80, 0x80, 0x80, 0xf0, // C
+    0xf0, 0x50, 0x50, 0x50, 0xf0, // D
+    0xf0, 0x80, 0xf0, 0x80, 0xf0, // E
+    0xf0, 0x80, 0xf0, 0x80, 0x80, // F
+];
+
+/// The DREAM 6800's font: 3 pixels wide.
+const DREAM_6800_FONT: [u8; FONT_SIZE] = [
+    0xe0, 0xa0, 0xa0, 0xa0, 0xe0, // 0
+    0x40, 0x40, 0x40, 0x40, 0x40, // 1
+    0xe0, 0x20, 0xe0, 0x80, 0xe0, // 2
+    0xe0, 0x20, 0xe0, 0x20, 0xe0, // 3
+    0x80, 0xa0, 0xa0, 0xe0, 0x20, // 4
+    0xe0, 0x80, 0xe0, 0x20, 0xe0, // 5
+    0xe0, 0x80, 0xe0, 0xa0, 0xe0, // 6
+    0xe0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xe0, 0xa0, 0xe0, 0xa0, 0xe0, // 8
+    0xe0, 0xa0, 0xe0, 0x20, 0xe0, // 9
+    0xe0, 0xa0, 0xe0, 0xa0, 0xa0, // A
+    0xc0, 0xa0, 0xe0, 0xa0, 0xc0, // B
+    0xe0, 0x80, 0x80, 0x80, 0xe0, // C
+    0xc0, 0xa0, 0xa0, 0xa0, 0xc0, // D
+    0xe0, 0x80, 0xe0, 0x80, 0xe0, // E
+    0xe0, 0x80, 0xc0, 0x80, 0x80, // F
+];
+
+/// The ETI-660's font: 3 pixels wide.
+const ETI_660_FONT: [u8; FONT_SIZE] = [
+    0xe0, 0xa0, 0xa0, 0xa0, 0xe0, // 0
+    0x20, 0x20, 0x20, 0x20, 0x20, // 1
+    0xe0, 0x20, 0xe0, 0x80, 0xe0, // 2
+    0xe0, 0x20, 0xe0, 0x20, 0xe0, // 3
+    0xa0, 0xa0, 0xe0, 0x20, 0x20, // 4
+    0xe0, 0x80, 0xe0, 0x20, 0xe0, // 5
+    0xe0, 0x80, 0xe0, 0xa0, 0xe0, // 6
+    0xe0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xe0, 0xa0, 0xe0, 0xa0, 0xe0, // 8
+    0xe0, 0xa0, 0xe0, 0x20, 0xe0, // 9
+    0xe0, 0xa0, 0xe0, 0xa0, 0xa0, // A
+    0x80, 0x80, 0xe0, 0xa0, 0xe0, // B
+    0xe0, 0x80, 0x80, 0x80, 0xe0, // C
+    0x20, 0x20, 0xe0, 0xa0, 0xe0, // D
+    0xe0, 0x80, 0xe0, 0x80, 0xe0, // E
+    0xe0, 0x80, 0xc0, 0x80, 0x80, // F
+];
+
+/// The font of the FISH 'N' CHIPS interpreter, with
+/// rounded digits.
+const FISH_N_CHIPS_FONT: [u8; FONT_SIZE] = [
+    0x60, 0xa0, 0xa0, 0xa0, 0xc0, // 0
+    0x40, 0xc0, 0x40, 0x40, 0xe0, // 1
+    0xc0, 0x20, 0x40, 0x80, 0xe0, // 2
+    0xc0, 0x20, 0x40, 0x20, 0xc0, // 3
+    0x20, 0xa0, 0xe0, 0x20, 0x20, // 4
+    0xe0, 0x80, 0xc0, 0x20, 0xc0, // 5
+    0x40, 0x80, 0xc0, 0xa0, 0x40, // 6
+    0xe0, 0x20, 0x60, 0x40, 0x40, // 7
+    0x40, 0xa0, 0x40, 0xa0, 0x40, // 8
+    0x40, 0xa0, 0x60, 0x20, 0x40, // 9
+    0x40, 0xa0, 0xe0, 0xa0, 0xa0, // A
+    0xc0, 0xa0, 0xc0, 0xa0, 0xc0, // B
+    0x60, 0x80, 0x80, 0x80, 0x60, // C
+    0xc0, 0xa0, 0xa0, 0xa0, 0xc0, // D
+    0xe0, 0x80, 0xc0, 0x80, 0xe0, // E
+    0xe0, 0x80, 0xc0, 0x80, 0x80, // F
+];
+
+/// The built-in fonts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FontStyle {
+    Standard,
+    Vip,
+    Dream6800,
+    Eti660,
+    FishNChips,
+}
+
+impl FontStyle {
+    /// The sprite data for digits 0 to F.
+    pub fn data(&self) -> &'static [u8] {
+        match *self {
+            FontStyle::Standard => &STANDARD_FONT,
+            FontStyle::Vip => &VIP_FONT,
+            FontStyle::Dream6800 => &DREAM_6800_FONT,
+            FontStyle::Eti660 => &ETI_660_FONT,
+            FontStyle::FishNChips => &FISH_N_CHIPS_FONT,
+        }
+    }
+}
+
+impl FromStr for FontStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FontStyle, String> {
+        match s {
+            "standard" => Ok(FontStyle::Standard),
+            "vip" => Ok(FontStyle::Vip),
+            "dream6800" => Ok(FontStyle::Dream6800),
+            "eti660" => Ok(FontStyle::Eti660),
+            "fishnchips" => Ok(FontStyle::FishNChips),
+            _ => Err(format!("unknown font: {}", s)),
+        }
+    }
+}
+
+/// Parse a font base address: a hex number, with or
+/// without a leading "0x".
+pub fn parse_base(s: &str) -> Result<usize, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    let base = usize::from_str_radix(digits, 16)
+                .map_err(|_| format!("invalid font address: {}", s))?;
+    if base.checked_add(FONT_SIZE).is_none_or(|end| end > cpu::PC_START) {
+        return Err(format!("font address {:#05x} leaves no room for the font below {:#05x}",
+                           base, cpu::PC_START));
+    }
+    Ok(base)
+}
+
+/// Check that font data loaded at `base' holds all 16 digits
+/// and ends below the start of the program. Fonts may be
+/// longer than FONT_SIZE (eg: with large SUPER-CHIP digits).
+pub fn check(data: &[u8], base: usize) -> Result<(), String> {
+    if data.len() < FONT_SIZE {
+        return Err(format!("font is {} bytes long, expected at least {}",
+                           data.len(), FONT_SIZE));
+    }
+    if base + data.len() > cpu::PC_START {
+        return Err(format!("font of {} bytes at {:#05x} overlaps the program at {:#05x}",
+                           data.len(), base, cpu::PC_START));
+    }
+    Ok(())
+}
+
+/// Read a font file to be loaded at `base'.
+pub fn from_file(filename: &str, base: usize) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    File::open(filename)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e| format!("{}: {}", filename, e))?;
+    check(&data, base).map_err(|e| format!("{}: {}", filename, e))?;
+    Ok(data)
+}
+
+#[cfg(test)]
+#[path="./font_test.rs"]
+mod font_test;
@@ -0,0 +1,33 @@
+
+use super::*;
+
+#[test]
+fn test_font_style() {
+    assert_eq!("vip".parse(), Ok(FontStyle::Vip));
+    assert_eq!("fishnchips".parse(), Ok(FontStyle::FishNChips));
+    assert!("comic".parse::<FontStyle>().is_err());
+
+    for style in &[FontStyle::Standard, FontStyle::Vip, FontStyle::Dream6800,
+                   FontStyle::Eti660, FontStyle::FishNChips] {
+        assert_eq!(style.data().len(), FONT_SIZE);
+    }
+}
+
+#[test]
+fn test_parse_base() {
+    assert_eq!(parse_base("0x050"), Ok(0x50));
+    assert_eq!(parse_base("0"), Ok(0));
+    assert!(parse_base("0x1c0").is_err());
+    assert!(parse_base("zz").is_err());
+    assert!(parse_base("ffffffffffffffff").is_err());
+}
+
+#[test]
+fn test_check() {
+    assert!(check(&[0; FONT_SIZE], 0x50).is_ok());
+    assert!(check(&[0; FONT_SIZE - 1], 0).is_err());
+    // A SUPER-CHIP font with large digits fits at 0x000 ...
+    assert!(check(&[0; 240], 0).is_ok());
+    // ... but not too close to the program.
+    assert!(check(&[0; 240], 0x150).is_err());
+}
@@ -0,0 +1,119 @@
+// framebuffer.rs
+
+use std::cmp;
+use std::slice::Chunks;
+
+/// Width of the CHIP-8 display in pixels.
+pub const WIDTH: usize = 64;
+
+/// Height of the CHIP-8 display in pixels.
+pub const HEIGHT: usize = 32;
+
+/// The CHIP-8 display as seen by the virtual machine.
+///
+/// Each pixel is stored as one byte: 0 for OFF and 1 for ON.
+/// This is the only copy of the display state; frontends
+/// (like the SDL `Screen') simply render it.
+pub struct Framebuffer {
+    pixels: [u8; WIDTH * HEIGHT],
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Framebuffer {
+            pixels: [0; WIDTH * HEIGHT],
+        }
+    }
+
+    /// Turn OFF all the pixels.
+    pub fn clear(&mut self) {
+        for p in self.pixels.iter_mut() {
+            *p = 0;
+        }
+    }
+
+    /// Return the value (0 or 1) of the pixel at (x, y).
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * WIDTH + x]
+    }
+
+    /// Set the pixel at (x, y) to `val' (0 or 1).
+    pub fn set(&mut self, x: usize, y: usize, val: u8) {
+        self.pixels[y * WIDTH + x] = val;
+    }
+
+    /// Xor a row of a sprite onto the display at (x, y).
+    ///
+    /// Each bit of `val', starting from the leftmost one,
+    /// is Xored with the pixels at (x, y), (x+1, y) etc.
+    /// Coordinates beyond the edges of the display wrap
+    /// around to the other side.
+    ///
+    /// Returns true if any pixel was flipped from ON to OFF.
+    pub fn xor_row(&mut self, x: usize, y: usize, val: u8) -> bool {
+        let mut flipped = false;
+        let y = y % HEIGHT;
+        for i in 0..8 {
+            let _x = (x + i) % WIDTH;
+            let current = self.get(_x, y);
+            let new = current ^ ((val >> (7 - i)) & 1);
+            if (current == 1) && (new == 0) {
+                flipped = true;
+            }
+            self.set(_x, y, new);
+        }
+        flipped
+    }
+
+    /// Xor a sprite onto the display with its top left corner
+    /// at (x, y). Each byte of `sprite' is one row.
+    ///
+    /// Returns true if any pixel was flipped from ON to OFF
+    /// (ie, there was a collision).
+    pub fn xor_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        let mut flipped = false;
+        for (y_index, val) in sprite.iter().enumerate() {
+            if self.xor_row(x, y + y_index, *val) {
+                flipped = true;
+            }
+        }
+        flipped
+    }
+
+    /// Like xor_sprite, but pixels beyond the right and bottom
+    /// edges are dropped instead of wrapping around. Only the
+    /// starting position wraps.
+    pub fn xor_sprite_clipped(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        let (x, y) = (x % WIDTH, y % HEIGHT);
+        let mut flipped = false;
+        for (y_index, val) in sprite.iter().take(HEIGHT - y).enumerate() {
+            for i in 0..cmp::min(8, WIDTH - x) {
+                if (val >> (7 - i)) & 1 == 0 {
+                    continue;
+                }
+                let current = self.get(x + i, y + y_index);
+                if current == 1 {
+                    flipped = true;
+                }
+                self.set(x + i, y + y_index, current ^ 1);
+            }
+        }
+        flipped
+    }
+
+    /// Iterate over the rows of the display, top to bottom.
+    /// Each row is a slice of WIDTH pixels.
+    pub fn rows(&self) -> Chunks<'_, u8> {
+        self.pixels.chunks(WIDTH)
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Framebuffer::new()
+    }
+}
+
+#[cfg(test)]
+#[path="./framebuffer_test.rs"]
+mod framebuffer_test;
@@ -0,0 +1,73 @@
+
+use super::*;
+
+#[test]
+fn test_xor_sprite() {
+    let mut f = Framebuffer::new();
+    // Draw the pattern 10100000 at (2, 3).
+    let flipped = f.xor_sprite(2, 3, &[0xa0]);
+    assert!(!flipped);
+    assert_eq!(f.get(2, 3), 1);
+    assert_eq!(f.get(3, 3), 0);
+    assert_eq!(f.get(4, 3), 1);
+}
+
+#[test]
+fn test_xor_sprite_collision() {
+    let mut f = Framebuffer::new();
+    f.xor_sprite(0, 0, &[0xff, 0xff]);
+    // Drawing the same sprite again erases it and
+    // reports a collision.
+    let flipped = f.xor_sprite(0, 0, &[0xff, 0xff]);
+    assert!(flipped);
+    for x in 0..8 {
+        assert_eq!(f.get(x, 0), 0);
+        assert_eq!(f.get(x, 1), 0);
+    }
+}
+
+#[test]
+fn test_xor_sprite_wraps() {
+    let mut f = Framebuffer::new();
+    // Sprite starting 4 pixels from the right edge and
+    // on the last row.
+    f.xor_sprite(WIDTH - 4, HEIGHT - 1, &[0xff, 0x80]);
+    assert_eq!(f.get(WIDTH - 1, HEIGHT - 1), 1);
+    assert_eq!(f.get(0, HEIGHT - 1), 1);
+    assert_eq!(f.get(3, HEIGHT - 1), 1);
+    assert_eq!(f.get(4, HEIGHT - 1), 0);
+    assert_eq!(f.get(WIDTH - 4, 0), 1);
+}
+
+#[test]
+fn test_xor_sprite_clipped() {
+    let mut f = Framebuffer::new();
+    f.xor_sprite_clipped(WIDTH - 4, HEIGHT - 1, &[0xff, 0x80]);
+    assert_eq!(f.get(WIDTH - 1, HEIGHT - 1), 1);
+    assert_eq!(f.get(0, HEIGHT - 1), 0);
+    assert_eq!(f.get(WIDTH - 4, 0), 0);
+
+    // The starting position still wraps.
+    let flipped = f.xor_sprite_clipped(WIDTH + WIDTH - 4, HEIGHT - 1, &[0xf0]);
+    assert!(flipped);
+    assert_eq!(f.get(WIDTH - 1, HEIGHT - 1), 0);
+}
+
+#[test]
+fn test_clear() {
+    let mut f = Framebuffer::new();
+    f.set(10, 10, 1);
+    f.clear();
+    assert!(f.rows().all(|row| row.iter().all(|p| *p == 0)));
+}
+
+#[test]
+fn test_rows() {
+    let mut f = Framebuffer::new();
+    f.set(5, 1, 1);
+    let rows: Vec<&[u8]> = f.rows().collect();
+    assert_eq!(rows.len(), HEIGHT);
+    assert_eq!(rows[1].len(), WIDTH);
+    assert_eq!(rows[1][5], 1);
+    assert_eq!(rows[0][5], 0);
+}
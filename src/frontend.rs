@@ -0,0 +1,35 @@
+// frontend.rs
+
+//! What the emulator needs from a user interface: somewhere
+//! to show the display and a way to read the keys. The SDL
+//! `Screen' and the `Terminal' are frontends.
+
+use chip8::Command;
+use cpu::NUM_KEYS;
+use framebuffer::Framebuffer;
+use palette::Palette;
+
+pub trait Frontend {
+    /// Handle all the pending input without blocking. Updates
+    /// the state of the CHIP-8 keys and acts on the hotkeys
+    /// concerning the frontend itself. Returns the commands
+    /// for the emulator. Called once per frame.
+    fn poll_events(&mut self) -> Vec<Command>;
+
+    /// Return the state of the CHIP-8 keys; true if pressed.
+    /// The key states are updated by `poll_events'.
+    fn keys(&self) -> [bool; NUM_KEYS];
+
+    /// Show the display. Called once per frame.
+    fn present(&mut self, fb: &Framebuffer);
+
+    /// Show a message over the display for a short while.
+    fn show_message(&mut self, text: &str);
+
+    /// Show a status line (eg: "PAUSED") until it is changed;
+    /// None removes it.
+    fn set_status(&mut self, text: Option<&str>);
+
+    /// The colors currently used for drawing the pixels.
+    fn palette(&self) -> &Palette;
+}
@@ -0,0 +1,220 @@
+// gamepad.rs
+
+use std::collections::HashSet;
+
+use sdl2::GameControllerSubsystem;
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+
+use keymap::Keymap;
+
+/// Keymap host key names which refer to game controller inputs
+/// have the form "Pad<N> <input>" or "Pad <input>", N being the
+/// player number (1 for the first controller connected, 2 for
+/// the second one and so on). Without N, the binding applies to
+/// all controllers.
+///
+/// <input> is an SDL button name (a, b, x, y, back, start,
+/// leftshoulder, dpup, dpleft ...) or an SDL axis name (leftx,
+/// lefty, rightx, righty, triggerleft, triggerright) followed by
+/// '-' or '+' for the direction. Eg: "Pad1 dpup", "Pad2 lefty-".
+const PAD_PREFIX: &str = "pad";
+
+/// An axis pushed beyond this value, in either direction,
+/// counts as a key press.
+const AXIS_THRESHOLD: i16 = 16384;
+
+/// A button, or one direction of an axis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Control {
+    Button(Button),
+    /// The bool is true for the positive direction.
+    Axis(Axis, bool),
+}
+
+struct Binding {
+    /// None if the binding applies to every controller.
+    player: Option<usize>,
+    control: Control,
+    key: u8,
+}
+
+/// The parts of a controller input name: the player number,
+/// the SDL name of the button or axis and, for axes, the
+/// direction (true for '+').
+#[derive(Debug, PartialEq)]
+pub struct PadName {
+    pub player: Option<usize>,
+    pub input: String,
+    pub positive: Option<bool>,
+}
+
+/// Return true if `name' refers to a game controller input
+/// rather than a keyboard key.
+pub fn is_pad_name(name: &str) -> bool {
+    let name = name.trim();
+    if !name.get(..PAD_PREFIX.len()).is_some_and(|p| p.eq_ignore_ascii_case(PAD_PREFIX)) {
+        return false;
+    }
+    let rest = name[PAD_PREFIX.len()..].trim_start_matches(|c: char| c.is_ascii_digit());
+    rest.starts_with(' ')
+}
+
+/// Split a controller input name into its parts.
+pub fn parse_pad_name(name: &str) -> Result<PadName, String> {
+    if !is_pad_name(name) {
+        return Err(format!("not a controller input: {}", name));
+    }
+    let name = name.trim();
+    let rest = &name[PAD_PREFIX.len()..];
+    let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+    let player = if digits.is_empty() {
+        None
+    } else {
+        match digits.parse() {
+            Ok(n) if n > 0 => Some(n),
+            _ => return Err(format!("invalid player number: {}", name)),
+        }
+    };
+    let input = rest[digits.len()..].trim().to_lowercase();
+    let (input, positive) = if input.ends_with('+') {
+        (input[.. input.len() - 1].to_string(), Some(true))
+    } else if input.ends_with('-') {
+        (input[.. input.len() - 1].to_string(), Some(false))
+    } else {
+        (input, None)
+    };
+    if input.is_empty() {
+        return Err(format!("missing controller input: {}", name));
+    }
+    Ok(PadName { player, input, positive })
+}
+
+/// Tracks the connected game controllers and turns their
+/// buttons and sticks into CHIP-8 key presses.
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    /// Open controllers. A controller in slot i belongs to
+    /// player i + 1. Slots are reused when a controller is
+    /// unplugged and another one is plugged in.
+    players: Vec<Option<GameController>>,
+    bindings: Vec<Binding>,
+    /// Inputs being held: (controller instance id, control).
+    held: HashSet<(i32, Control)>,
+}
+
+impl Gamepads {
+    /// Controllers themselves are opened as SDL reports them
+    /// (including the ones connected at startup) through
+    /// `handle_event'.
+    pub fn new(subsystem: GameControllerSubsystem, keymap: &Keymap) -> Gamepads {
+        let mut bindings = Vec::new();
+        for &(ref name, key) in keymap.bindings() {
+            if !is_pad_name(name) {
+                continue;
+            }
+            match Gamepads::resolve(name) {
+                Ok((player, control)) => bindings.push(Binding { player, control, key }),
+                Err(e) => eprintln!("warning: keymap: {}", e),
+            }
+        }
+        Gamepads {
+            subsystem,
+            players: Vec::new(),
+            bindings,
+            held: HashSet::new(),
+        }
+    }
+
+    /// Look up the SDL button or axis in a controller input name.
+    fn resolve(name: &str) -> Result<(Option<usize>, Control), String> {
+        let p = parse_pad_name(name)?;
+        let control = match p.positive {
+            Some(positive) => Axis::from_string(&p.input).map(|a| Control::Axis(a, positive)),
+            None => Button::from_string(&p.input).map(Control::Button),
+        };
+        control.map(|c| (p.player, c))
+               .ok_or_else(|| format!("unknown controller input: {}", name))
+    }
+
+    /// Return the player number of the controller with
+    /// the given instance id.
+    fn player_of(&self, which: i32) -> Option<usize> {
+        self.players.iter()
+            .position(|c| c.as_ref().is_some_and(|c| c.instance_id() == which))
+            .map(|index| index + 1)
+    }
+
+    /// Record that `control' on controller `which' was pressed or
+    /// released, updating the press counts in `keys'.
+    fn set_held(&mut self, which: i32, control: Control, pressed: bool, keys: &mut [u32; 16]) {
+        let changed = if pressed {
+            self.held.insert((which, control))
+        } else {
+            self.held.remove(&(which, control))
+        };
+        if !changed {
+            return;
+        }
+        let player = self.player_of(which);
+        for b in self.bindings.iter() {
+            if b.control == control && (b.player.is_none() || b.player == player) {
+                let n = &mut keys[usize::from(b.key)];
+                *n = if pressed { *n + 1 } else { n.saturating_sub(1) };
+            }
+        }
+    }
+
+    fn add_controller(&mut self, index: u32) {
+        match self.subsystem.open(index) {
+            Ok(c) => {
+                let slot = match self.players.iter().position(|c| c.is_none()) {
+                    Some(slot) => slot,
+                    None => { self.players.push(None); self.players.len() - 1 },
+                };
+                eprintln!("controller connected: {} (player {})", c.name(), slot + 1);
+                self.players[slot] = Some(c);
+            },
+            Err(e) => eprintln!("warning: unable to open controller: {}", e),
+        }
+    }
+
+    fn remove_controller(&mut self, which: i32, keys: &mut [u32; 16]) {
+        let held: Vec<Control> = self.held.iter()
+                                    .filter(|h| h.0 == which)
+                                    .map(|h| h.1)
+                                    .collect();
+        for control in held {
+            self.set_held(which, control, false, keys);
+        }
+        if let Some(player) = self.player_of(which) {
+            eprintln!("controller disconnected (player {})", player);
+            self.players[player - 1] = None;
+        }
+    }
+
+    /// Handle a controller related event; other events are
+    /// ignored. `keys' holds, for each CHIP-8 key, the number
+    /// of inputs mapped to it which are being held down.
+    pub fn handle_event(&mut self, e: &Event, keys: &mut [u32; 16]) {
+        match *e {
+            Event::ControllerDeviceAdded { which, .. } => self.add_controller(which),
+            Event::ControllerDeviceRemoved { which, .. } => self.remove_controller(which, keys),
+            Event::ControllerButtonDown { which, button, .. } => {
+                self.set_held(which, Control::Button(button), true, keys);
+            },
+            Event::ControllerButtonUp { which, button, .. } => {
+                self.set_held(which, Control::Button(button), false, keys);
+            },
+            Event::ControllerAxisMotion { which, axis, value, .. } => {
+                self.set_held(which, Control::Axis(axis, true), value >= AXIS_THRESHOLD, keys);
+                self.set_held(which, Control::Axis(axis, false), value <= -AXIS_THRESHOLD, keys);
+            },
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+#[path="./gamepad_test.rs"]
+mod gamepad_test;
@@ -0,0 +1,30 @@
+
+use super::*;
+
+#[test]
+fn test_is_pad_name() {
+    assert!(is_pad_name("Pad1 dpup"));
+    assert!(is_pad_name("pad lefty-"));
+    assert!(is_pad_name("PAD12 a"));
+    assert!(!is_pad_name("PageUp"));
+    assert!(!is_pad_name("Keypad 8"));
+    assert!(!is_pad_name("Pad"));
+    assert!(!is_pad_name("Q"));
+    // Non-ASCII key names, cut inside a character.
+    assert!(!is_pad_name("Ää"));
+    assert!(!is_pad_name("P€ 1"));
+    assert!(parse_pad_name("Ää").is_err());
+}
+
+#[test]
+fn test_parse_pad_name() {
+    assert_eq!(parse_pad_name("Pad1 DPUp"),
+               Ok(PadName { player: Some(1), input: String::from("dpup"), positive: None }));
+    assert_eq!(parse_pad_name("Pad lefty-"),
+               Ok(PadName { player: None, input: String::from("lefty"), positive: Some(false) }));
+    assert_eq!(parse_pad_name("Pad2 rightx+"),
+               Ok(PadName { player: Some(2), input: String::from("rightx"), positive: Some(true) }));
+    assert!(parse_pad_name("Pad0 a").is_err());
+    assert!(parse_pad_name("Pad1 +").is_err());
+    assert!(parse_pad_name("Space").is_err());
+}
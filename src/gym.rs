@@ -0,0 +1,400 @@
+// gym.rs
+
+//! A Gym-style environment for reinforcement learning: the
+//! agent presses keys, the game runs for a few frames, and
+//! the agent is rewarded according to what the game keeps in
+//! memory, eg: its score.
+//!
+//! What counts as reward and when an episode ends is given per
+//! ROM by a `Spec', written like this for PONG, which keeps
+//! the scores of the two players (stored by FX33) at 0x2f3
+//! and 0x2f4:
+//!
+//! ```text
+//! reward = byte@0x2f3
+//! reward = -byte@0x2f4
+//! done = byte@0x2f3 >= 9
+//! done = byte@0x2f4 >= 9
+//! ```
+//!
+//! Each reward term is a value in memory, with an optional
+//! weight ("2*bcd@0x300", "-byte@0x2f4"); the reward for a
+//! step is the weighted sum of the changes of these values.
+//! Values are read as a byte ("byte@addr"), a big-endian
+//! 16-bit word ("word@addr") or three BCD digits as stored by
+//! FX33 ("bcd@addr"). An episode ends when any of the `done'
+//! conditions holds, when the CPU faults, or after
+//! `max_frames' frames.
+
+use std::str::FromStr;
+use std::thread;
+
+use cpu::{self, CPU, MEM_SIZE, NUM_KEYS};
+use font::{self, FontStyle};
+use framebuffer::{WIDTH, HEIGHT};
+use quirks::Quirks;
+
+/// The keys held down during a step: bit k is set if key k
+/// is pressed.
+pub type Action = u16;
+
+/// A number kept by the game in memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    Byte(usize),
+    /// Big-endian, as CHIP-8 stores addresses.
+    Word(usize),
+    /// Three decimal digits, one per byte, as stored by FX33.
+    Bcd(usize),
+}
+
+impl Value {
+    fn size(&self) -> usize {
+        match *self {
+            Value::Byte(_) => 1,
+            Value::Word(_) => 2,
+            Value::Bcd(_) => 3,
+        }
+    }
+
+    pub fn read(&self, mem: &[u8]) -> i64 {
+        match *self {
+            Value::Byte(a) => i64::from(mem[a]),
+            Value::Word(a) => i64::from(mem[a]) << 8 | i64::from(mem[a + 1]),
+            Value::Bcd(a) => mem[a .. a + 3].iter().fold(0, |n, d| n * 10 + i64::from(*d)),
+        }
+    }
+}
+
+impl FromStr for Value {
+    type Err = String;
+
+    /// Parse "byte@addr", "word@addr" or "bcd@addr"; the
+    /// address is in hex, with or without "0x".
+    fn from_str(s: &str) -> Result<Value, String> {
+        let err = || format!("invalid value: {} (expected eg: byte@0x2f0)", s);
+        let mut parts = s.trim().splitn(2, '@');
+        let kind = parts.next().unwrap_or("");
+        let addr = parts.next().ok_or_else(err)?;
+        let addr = addr.trim_start_matches("0x").trim_start_matches("0X");
+        let addr = usize::from_str_radix(addr, 16).map_err(|_| err())?;
+        let value = match kind {
+            "byte" => Value::Byte(addr),
+            "word" => Value::Word(addr),
+            "bcd" => Value::Bcd(addr),
+            _ => return Err(err()),
+        };
+        if addr >= MEM_SIZE || addr + value.size() > MEM_SIZE {
+            return Err(format!("address out of range: {}", s));
+        }
+        Ok(value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A test of a value against a constant, eg: "byte@0x2f3 >= 9".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Condition {
+    pub value: Value,
+    pub comparison: Comparison,
+    pub operand: i64,
+}
+
+impl Condition {
+    pub fn holds(&self, mem: &[u8]) -> bool {
+        let v = self.value.read(mem);
+        match self.comparison {
+            Comparison::Eq => v == self.operand,
+            Comparison::Ne => v != self.operand,
+            Comparison::Lt => v < self.operand,
+            Comparison::Le => v <= self.operand,
+            Comparison::Gt => v > self.operand,
+            Comparison::Ge => v >= self.operand,
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Condition, String> {
+        // Two character operators first, so that "<=" is not
+        // taken for "<".
+        let ops = [("==", Comparison::Eq), ("!=", Comparison::Ne),
+                   ("<=", Comparison::Le), (">=", Comparison::Ge),
+                   ("<", Comparison::Lt), (">", Comparison::Gt)];
+        for &(op, comparison) in ops.iter() {
+            if let Some(n) = s.find(op) {
+                let value = s[..n].parse()?;
+                let operand = s[n + op.len() ..].trim();
+                let operand = operand.parse().map_err(|_| format!("invalid number: {}", operand))?;
+                return Ok(Condition { value, comparison, operand });
+            }
+        }
+        Err(format!("invalid condition: {} (expected eg: byte@0x2f3 >= 9)", s))
+    }
+}
+
+/// What counts as reward, and when an episode ends, for a ROM.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Spec {
+    /// Weighted values; the reward is the weighted sum of
+    /// their changes.
+    pub reward: Vec<(f64, Value)>,
+    /// The episode ends when any of these holds.
+    pub done: Vec<Condition>,
+    /// The episode ends after these many frames.
+    pub max_frames: Option<u64>,
+}
+
+/// Parse a reward term: a value, with an optional weight
+/// ("2*bcd@0x300") or minus sign ("-byte@0x2f4").
+fn parse_term(s: &str) -> Result<(f64, Value), String> {
+    let s = s.trim();
+    if let Some(n) = s.find('*') {
+        let weight = s[..n].trim();
+        let weight = weight.parse().map_err(|_| format!("invalid weight: {}", weight))?;
+        Ok((weight, s[n + 1 ..].parse()?))
+    } else if let Some(rest) = s.strip_prefix('-') {
+        Ok((-1.0, rest.parse()?))
+    } else {
+        Ok((1.0, s.parse()?))
+    }
+}
+
+impl Spec {
+    /// Parse a spec: "reward = term", "done = condition" and
+    /// "max_frames = n" lines (see the module documentation).
+    /// Blank lines and lines starting with '#' are ignored.
+    pub fn parse(text: &str) -> Result<Spec, String> {
+        let mut spec = Spec::default();
+        for line in text.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let mut parts = line.splitn(2, '=');
+            let (key, val) = match (parts.next(), parts.next()) {
+                (Some(k), Some(v)) => (k.trim(), v.trim()),
+                _ => return Err(format!("invalid line in spec: {}", line)),
+            };
+            match key {
+                "reward" => spec.reward.push(parse_term(val)?),
+                "done" => spec.done.push(val.parse()?),
+                "max_frames" => spec.max_frames = Some(val.parse().map_err(|_| format!("invalid number: {}", val))?),
+                _ => return Err(format!("unknown key in spec: {}", key)),
+            }
+        }
+        Ok(spec)
+    }
+}
+
+/// Settings for an environment.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The ROM image.
+    pub game: Vec<u8>,
+    pub quirks: Quirks,
+    pub insns_per_frame: u32,
+    /// Number of frames run per step, with the same keys held.
+    pub frame_skip: u32,
+    pub spec: Spec,
+    /// Include memory in the observations.
+    pub observe_ram: bool,
+}
+
+impl Config {
+    /// A configuration for `game', with the usual speed, no
+    /// quirks and no frame skipping.
+    pub fn new(game: Vec<u8>, spec: Spec) -> Config {
+        Config {
+            game,
+            quirks: Quirks::default(),
+            insns_per_frame: 10,
+            frame_skip: 1,
+            spec,
+            observe_ram: false,
+        }
+    }
+}
+
+/// The size of the `pixels' of an observation.
+pub const OBSERVATION_SIZE: usize = WIDTH * HEIGHT;
+
+/// What the agent sees after a step.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Observation {
+    /// The display, row by row: OBSERVATION_SIZE pixel
+    /// values, 0 for off.
+    pub pixels: Vec<u8>,
+    /// Memory, if `observe_ram' is set.
+    pub ram: Option<Vec<u8>>,
+}
+
+/// One game, played one step at a time.
+pub struct Env {
+    config: Config,
+    cpu: CPU,
+    /// The reward values at the end of the last step.
+    values: Vec<i64>,
+    frames: u64,
+    done: bool,
+}
+
+impl Env {
+    pub fn new(config: Config) -> Result<Env, String> {
+        if config.game.is_empty() || config.game.len() > MEM_SIZE - cpu::PC_START {
+            return Err(format!("invalid ROM size: {} bytes", config.game.len()));
+        }
+        if config.frame_skip == 0 {
+            return Err(String::from("frame skip must be at least 1"));
+        }
+        let mut env = Env { config, cpu: CPU::new(), values: Vec::new(), frames: 0, done: false };
+        env.reset(0);
+        Ok(env)
+    }
+
+    /// Start a new episode. `seed' makes the game's random
+    /// numbers, and so the episode, repeatable.
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        self.cpu.reset();
+        self.cpu.set_quirks(self.config.quirks);
+        self.cpu.seed_rng(seed);
+        self.cpu.load_font(FontStyle::Standard.data(), font::DEFAULT_FONT_BASE);
+        self.cpu.load_bytes(&self.config.game, cpu::PC_START);
+        self.values = self.read_values();
+        self.frames = 0;
+        self.done = false;
+        self.observation()
+    }
+
+    /// Hold the keys in `action' down for `frame_skip' frames.
+    /// Returns what the agent sees, the reward, and whether the
+    /// episode is over; once it is, steps do nothing until the
+    /// next `reset'.
+    pub fn step(&mut self, action: Action) -> (Observation, f64, bool) {
+        let mut reward = 0.0;
+        if !self.done {
+            let mut keys = [false; NUM_KEYS];
+            for (k, pressed) in keys.iter_mut().enumerate() {
+                *pressed = action & (1 << k) != 0;
+            }
+            self.cpu.set_keys(keys);
+            for _ in 0..self.config.frame_skip {
+                self.cpu.run_frame(self.config.insns_per_frame);
+                self.frames += 1;
+                let values = self.read_values();
+                for (n, &(weight, _)) in self.config.spec.reward.iter().enumerate() {
+                    reward += weight * (values[n] - self.values[n]) as f64;
+                }
+                self.values = values;
+                self.done = self.episode_over();
+                if self.done {
+                    break;
+                }
+            }
+        }
+        (self.observation(), reward, self.done)
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    fn read_values(&self) -> Vec<i64> {
+        self.config.spec.reward.iter().map(|&(_, v)| v.read(self.cpu.mem())).collect()
+    }
+
+    fn episode_over(&self) -> bool {
+        let spec = &self.config.spec;
+        self.cpu.fault().is_some()
+            || spec.max_frames.is_some_and(|n| self.frames >= n)
+            || spec.done.iter().any(|c| c.holds(self.cpu.mem()))
+    }
+
+    fn observation(&self) -> Observation {
+        let pixels = self.cpu.display().rows().flat_map(|row| row.iter().cloned()).collect();
+        let ram = if self.config.observe_ram { Some(self.cpu.mem().to_vec()) } else { None };
+        Observation { pixels, ram }
+    }
+}
+
+/// Copies of a game, stepped together, in parallel across
+/// threads. An episode which ends is started again at once
+/// (with a new seed), so every step returns the first
+/// observation of the new episode for it, along with the
+/// reward and `done' of the last step of the old one.
+pub struct VecEnv {
+    envs: Vec<Env>,
+    /// The seed for the next episode of each copy.
+    seeds: Vec<u64>,
+    threads: usize,
+}
+
+impl VecEnv {
+    pub fn new(config: Config, copies: usize) -> Result<VecEnv, String> {
+        if copies == 0 {
+            return Err(String::from("at least one copy is needed"));
+        }
+        let envs = (0..copies).map(|_| Env::new(config.clone())).collect::<Result<Vec<_>, _>>()?;
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Ok(VecEnv { envs, seeds: vec![0; copies], threads })
+    }
+
+    pub fn len(&self) -> usize {
+        self.envs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.envs.is_empty()
+    }
+
+    /// Start new episodes; copy n is seeded with `seed' + n,
+    /// and its later episodes with `seed' + n + k * len().
+    pub fn reset(&mut self, seed: u64) -> Vec<Observation> {
+        let copies = self.envs.len() as u64;
+        self.envs.iter_mut().zip(self.seeds.iter_mut()).enumerate().map(|(n, (env, next))| {
+            let seed = seed.wrapping_add(n as u64);
+            *next = seed.wrapping_add(copies);
+            env.reset(seed)
+        }).collect()
+    }
+
+    /// Step copy n with `actions[n]'.
+    pub fn step(&mut self, actions: &[Action]) -> Vec<(Observation, f64, bool)> {
+        assert_eq!(actions.len(), self.envs.len(), "one action per copy is needed");
+        let copies = self.envs.len() as u64;
+        let chunk = self.envs.len().div_ceil(self.threads);
+        thread::scope(|scope| {
+            let handles: Vec<_> = self.envs.chunks_mut(chunk)
+                .zip(self.seeds.chunks_mut(chunk))
+                .zip(actions.chunks(chunk))
+                .map(|((envs, seeds), actions)| scope.spawn(move || {
+                    envs.iter_mut().zip(seeds.iter_mut()).zip(actions).map(|((env, seed), &action)| {
+                        let (mut observation, reward, done) = env.step(action);
+                        if done {
+                            observation = env.reset(*seed);
+                            *seed = seed.wrapping_add(copies);
+                        }
+                        (observation, reward, done)
+                    }).collect::<Vec<_>>()
+                }))
+                .collect();
+            handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+        })
+    }
+
+    /// The copies, eg: to look at their CPUs.
+    pub fn envs(&self) -> &[Env] {
+        &self.envs
+    }
+}
+
+#[cfg(test)]
+#[path="./gym_test.rs"]
+mod gym_test;
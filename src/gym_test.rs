@@ -0,0 +1,147 @@
+
+use super::*;
+
+const PONG: &[u8] = include_bytes!("../roms/PONG");
+
+/// The scores of the two PONG players; the game ends at 9.
+const PONG_SPEC: &str = "
+    # Left player, right player.
+    reward = byte@0x2f3
+    reward = -byte@0x2f4
+    done = byte@0x2f3 >= 9
+    done = byte@0x2f4 >= 9
+";
+
+fn pong() -> Config {
+    Config::new(PONG.to_vec(), Spec::parse(PONG_SPEC).unwrap())
+}
+
+/// Actions which move the left paddle up and down (keys 1 and
+/// 4) in a pattern taken from `n'.
+fn action(n: u64) -> Action {
+    if (n / 7).is_multiple_of(2) { 1 << 0x1 } else { 1 << 0x4 }
+}
+
+#[test]
+fn test_value() {
+    let mut mem = [0u8; MEM_SIZE];
+    mem[0x300 .. 0x303].copy_from_slice(&[1, 2, 3]);
+    assert_eq!("byte@0x301".parse(), Ok(Value::Byte(0x301)));
+    assert_eq!(Value::Byte(0x301).read(&mem), 2);
+    assert_eq!("word@300".parse::<Value>().unwrap().read(&mem), 0x102);
+    assert_eq!("bcd@0x300".parse::<Value>().unwrap().read(&mem), 123);
+    assert!("bcd@0xffe".parse::<Value>().is_err());
+    assert!("nibble@0x300".parse::<Value>().is_err());
+    assert!("byte".parse::<Value>().is_err());
+}
+
+#[test]
+fn test_condition() {
+    let mut mem = [0u8; MEM_SIZE];
+    mem[0x300] = 9;
+    let c: Condition = "byte@0x300 >= 9".parse().unwrap();
+    assert_eq!(c.comparison, Comparison::Ge);
+    assert!(c.holds(&mem));
+    assert!(!"byte@0x300<9".parse::<Condition>().unwrap().holds(&mem));
+    assert!("byte@0x300 != 0".parse::<Condition>().unwrap().holds(&mem));
+    assert!("byte@0x300 = 9".parse::<Condition>().is_err());
+    assert!("byte@0x300 == x".parse::<Condition>().is_err());
+}
+
+#[test]
+fn test_spec() {
+    let spec = Spec::parse("reward = 2*bcd@0x300\nreward = -byte@0x2f4\nmax_frames = 100").unwrap();
+    assert_eq!(spec.reward, vec![(2.0, Value::Bcd(0x300)), (-1.0, Value::Byte(0x2f4))]);
+    assert_eq!(spec.max_frames, Some(100));
+    assert!(Spec::parse("score = byte@0x300").is_err());
+    assert!(Spec::parse("reward byte@0x300").is_err());
+}
+
+#[test]
+fn test_invalid_config() {
+    assert!(Env::new(Config::new(Vec::new(), Spec::default())).is_err());
+    let mut config = pong();
+    config.frame_skip = 0;
+    assert!(Env::new(config).is_err());
+}
+
+#[test]
+fn test_pong_episode() {
+    let mut env = Env::new(pong()).unwrap();
+    let observation = env.reset(1);
+    assert_eq!(observation.pixels.len(), OBSERVATION_SIZE);
+    assert_eq!(observation.ram, None);
+
+    let mut total = 0.0;
+    let mut steps = 0;
+    loop {
+        let (_, reward, done) = env.step(action(steps));
+        total += reward;
+        steps += 1;
+        if done {
+            break;
+        }
+        assert!(steps < 100_000, "the game does not end");
+    }
+    let mem = env.cpu().mem();
+    assert!(mem[0x2f3] == 9 || mem[0x2f4] == 9);
+    assert_eq!(total, f64::from(mem[0x2f3]) - f64::from(mem[0x2f4]));
+    assert!(total != 0.0);
+
+    // Nothing happens once the episode is over.
+    let (_, reward, done) = env.step(0);
+    assert_eq!((reward, done), (0.0, true));
+}
+
+#[test]
+fn test_repeatable() {
+    let run = |seed| {
+        let mut env = Env::new(pong()).unwrap();
+        env.reset(seed);
+        (0..300).map(|n| env.step(action(n))).collect::<Vec<_>>()
+    };
+    assert_eq!(run(7), run(7));
+    assert_ne!(run(7), run(8));
+}
+
+#[test]
+fn test_frame_skip_and_ram() {
+    let mut config = pong();
+    config.frame_skip = 4;
+    config.observe_ram = true;
+    config.spec.max_frames = Some(10);
+    let mut env = Env::new(config).unwrap();
+    env.reset(0);
+    let (observation, _, done) = env.step(0);
+    assert_eq!(observation.ram.map(|r| r.len()), Some(MEM_SIZE));
+    assert!(!done);
+    env.step(0);
+    // The episode is cut short after frame 10.
+    assert!(env.step(0).2);
+}
+
+#[test]
+fn test_vec_env() {
+    let mut config = pong();
+    config.spec.max_frames = Some(50);
+    let mut envs = VecEnv::new(config.clone(), 5).unwrap();
+    assert_eq!(envs.len(), 5);
+    let observations = envs.reset(3);
+    assert_eq!(observations.len(), 5);
+
+    let mut single = Env::new(config).unwrap();
+    single.reset(3 + 2);
+    for n in 0..60 {
+        let results = envs.step(&[action(n); 5]);
+        assert_eq!(results.len(), 5);
+        let (observation, reward, done) = single.step(action(n));
+        // Copy 2 is seeded like `single'; it starts again after
+        // its 50 frames.
+        assert_eq!((results[2].1, results[2].2), (reward, done));
+        if done {
+            assert_eq!(results[2].0, single.reset(3 + 2 + 5));
+        } else {
+            assert_eq!(results[2].0, observation);
+        }
+    }
+}
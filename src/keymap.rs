@@ -0,0 +1,141 @@
+// keymap.rs
+
+use std::io::prelude::*;
+use std::fs::File;
+
+/// The layout of the COSMAC VIP hex keypad, row by row,
+/// and the host keys (in the same physical positions on a
+/// PC keyboard) to which it is mapped by default.
+///
+/// ```text
+/// 1 2 3 C        1 2 3 4
+/// 4 5 6 D        Q W E R
+/// 7 8 9 E        A S D F
+/// A 0 B F        Z X C V
+/// ```
+const COSMAC_VIP_LAYOUT: [(u8, &str); 16] = [
+    (0x1, "1"), (0x2, "2"), (0x3, "3"), (0xc, "4"),
+    (0x4, "Q"), (0x5, "W"), (0x6, "E"), (0xd, "R"),
+    (0x7, "A"), (0x8, "S"), (0x9, "D"), (0xe, "F"),
+    (0xa, "Z"), (0x0, "X"), (0xb, "C"), (0xf, "V"),
+];
+
+/// Maps host keys to the 16 CHIP-8 keys.
+///
+/// Host keys are identified by name (eg: "Q", "Space",
+/// "Keypad 8", "Up"); the frontend decides how a name maps to
+/// a physical key. The SDL frontend uses SDL scancode names,
+/// so that the mapping follows key positions rather than the
+/// letters printed on them (AZERTY, QWERTZ, ...), and
+/// names like "Pad1 dpup" for game controllers.
+///
+/// Several host keys can map to the same CHIP-8 key, but a
+/// host key maps to at most one CHIP-8 key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap {
+    bindings: Vec<(String, u8)>,
+}
+
+/// Parse a CHIP-8 key number, a single hex digit.
+fn parse_key(s: &str) -> Result<u8, String> {
+    match u8::from_str_radix(s.trim(), 16) {
+        Ok(k) if k <= 0xf => Ok(k),
+        _ => Err(format!("invalid CHIP-8 key: {}", s)),
+    }
+}
+
+impl Keymap {
+    /// A keymap without any bindings.
+    pub fn empty() -> Keymap {
+        Keymap { bindings: Vec::new() }
+    }
+
+    /// The default keymap: the COSMAC VIP keypad on the left
+    /// side of a PC keyboard.
+    pub fn cosmac_vip() -> Keymap {
+        let mut m = Keymap::empty();
+        for &(key, host) in COSMAC_VIP_LAYOUT.iter() {
+            m.bind(host, key);
+        }
+        m
+    }
+
+    /// Map host key `host' to CHIP-8 key `key', in addition
+    /// to any other host keys already mapped to it.
+    pub fn bind(&mut self, host: &str, key: u8) {
+        let host = host.trim();
+        self.bindings.retain(|b| !b.0.eq_ignore_ascii_case(host));
+        self.bindings.push((String::from(host), key));
+    }
+
+    /// Remove all the host keys mapped to CHIP-8 key `key'.
+    pub fn unbind(&mut self, key: u8) {
+        self.bindings.retain(|b| b.1 != key);
+    }
+
+    /// Return all (host key, CHIP-8 key) pairs.
+    pub fn bindings(&self) -> &[(String, u8)] {
+        &self.bindings
+    }
+
+    /// Apply a binding of the form "5 = W, Up": the host keys
+    /// on the right replace whatever was mapped to the CHIP-8
+    /// key on the left.
+    pub fn apply_spec(&mut self, spec: &str) -> Result<(), String> {
+        let mut parts = spec.splitn(2, '=');
+        let key = parse_key(parts.next().unwrap())?;
+        let hosts = parts.next()
+                      .ok_or_else(|| format!("invalid key binding: {}", spec))?;
+        self.unbind(key);
+        for host in hosts.split(',').map(|h| h.trim()).filter(|h| !h.is_empty()) {
+            self.bind(host, key);
+        }
+        Ok(())
+    }
+
+    /// Apply the bindings in the text of a keymap file.
+    ///
+    /// Each line is a binding in the format accepted by
+    /// `apply_spec'. Lines following a "[NAME]" header apply
+    /// only to the ROM whose file name is NAME (compared without
+    /// regard to case); `rom' is the file name of the ROM being
+    /// run. Blank lines and lines starting with '#' are ignored.
+    pub fn apply_file_text(&mut self, text: &str, rom: &str) -> Result<(), String> {
+        let mut active = true;
+        for (lineno, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                active = line[1 .. line.len() - 1].trim().eq_ignore_ascii_case(rom);
+                continue;
+            }
+            if active {
+                self.apply_spec(line)
+                    .map_err(|e| format!("line {}: {}", lineno + 1, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the bindings in a keymap file. See `apply_file_text'
+    /// for the format.
+    pub fn apply_file(&mut self, filename: &str, rom: &str) -> Result<(), String> {
+        let mut text = String::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("{}: {}", filename, e))?;
+        self.apply_file_text(&text, rom).map_err(|e| format!("{}: {}", filename, e))
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::cosmac_vip()
+    }
+}
+
+#[cfg(test)]
+#[path="./keymap_test.rs"]
+mod keymap_test;
@@ -0,0 +1,63 @@
+
+use super::*;
+
+/// Return the CHIP-8 key mapped to host key `host'.
+fn lookup(m: &Keymap, host: &str) -> Option<u8> {
+    m.bindings().iter()
+        .find(|b| b.0.eq_ignore_ascii_case(host))
+        .map(|b| b.1)
+}
+
+#[test]
+fn test_cosmac_vip_layout() {
+    let m = Keymap::cosmac_vip();
+    assert_eq!(m.bindings().len(), 16);
+    assert_eq!(lookup(&m, "1"), Some(0x1));
+    assert_eq!(lookup(&m, "4"), Some(0xc));
+    assert_eq!(lookup(&m, "x"), Some(0x0));
+    assert_eq!(lookup(&m, "V"), Some(0xf));
+    assert_eq!(lookup(&m, "Space"), None);
+}
+
+#[test]
+fn test_apply_spec() {
+    let mut m = Keymap::cosmac_vip();
+    m.apply_spec("5 = Up, Keypad 8").unwrap();
+    assert_eq!(lookup(&m, "Up"), Some(0x5));
+    assert_eq!(lookup(&m, "Keypad 8"), Some(0x5));
+    // "W" was mapped to 5; it has been replaced.
+    assert_eq!(lookup(&m, "W"), None);
+
+    // A host key maps to only one CHIP-8 key.
+    m.apply_spec("6=Up").unwrap();
+    assert_eq!(lookup(&m, "Up"), Some(0x6));
+    assert_eq!(lookup(&m, "Keypad 8"), Some(0x5));
+
+    assert!(m.apply_spec("g = Up").is_err());
+    assert!(m.apply_spec("Up").is_err());
+}
+
+#[test]
+fn test_apply_file_text() {
+    let text = "# a test keymap\n\
+                1 = Space\n\
+                [PONG]\n\
+                1 = Up\n\
+                [BRIX]\n\
+                4 = Left\n";
+
+    let mut m = Keymap::cosmac_vip();
+    m.apply_file_text(text, "pong").unwrap();
+    assert_eq!(lookup(&m, "Up"), Some(0x1));
+    assert_eq!(lookup(&m, "Space"), None);
+    assert_eq!(lookup(&m, "Left"), None);
+
+    let mut m = Keymap::cosmac_vip();
+    m.apply_file_text(text, "BRIX").unwrap();
+    assert_eq!(lookup(&m, "Space"), Some(0x1));
+    assert_eq!(lookup(&m, "Left"), Some(0x4));
+
+    let mut m = Keymap::cosmac_vip();
+    let e = m.apply_file_text("1 = Space\nz = Q\n", "PONG").unwrap_err();
+    assert!(e.starts_with("line 2:"));
+}
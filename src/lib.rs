@@ -0,0 +1,35 @@
+// lib.rs
+
+//! The CHIP-8 virtual machine. Used by the emulator program,
+//! which adds the frontends, and built as a libretro core
+//! (see `libretro'), as a C library (see `capi') and as a
+//! Python module (see `python').
+
+extern crate rand;
+
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate maplit;
+#[cfg(feature = "python")]
+extern crate numpy;
+// The code made by the PyO3 macros refers to `::core'.
+#[cfg(feature = "python")]
+extern crate core;
+#[cfg(feature = "python")]
+extern crate pyo3;
+
+pub mod cpu;
+pub mod framebuffer;
+pub mod palette;
+pub mod font;
+pub mod quirks;
+pub mod bounds;
+pub mod stack;
+pub mod keymap;
+pub mod sound;
+pub mod libretro;
+pub mod gym;
+pub mod capi;
+#[cfg(feature = "python")]
+pub mod python;
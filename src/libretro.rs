@@ -0,0 +1,522 @@
+// libretro.rs
+
+//! A libretro core, so that the emulator can run inside
+//! RetroArch and other libretro frontends. The `retro_*'
+//! functions are exported from the cdylib built from this
+//! crate; see libretro.h in the libretro API for what they
+//! are expected to do.
+//!
+//! The frontend owns the main loop: it calls `retro_run' once
+//! per frame, and the core calls back to read the input and
+//! to hand over the video and the audio.
+
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::ptr;
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+
+use cpu::{self, CPU};
+use font::{self, FontStyle};
+use framebuffer::{WIDTH, HEIGHT};
+use keymap::Keymap;
+use palette::{self, Palette};
+use quirks::Quirks;
+use sound::{self, Beeper};
+
+const RETRO_API_VERSION: c_uint = 1;
+
+// Environment commands.
+const ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+const ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+const ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+
+const PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const DEVICE_JOYPAD: c_uint = 1;
+const DEVICE_KEYBOARD: c_uint = 3;
+
+const REGION_NTSC: c_uint = 0;
+
+/// The CHIP-8 key for each joypad button, by button id (B, Y,
+/// Select, Start, Up, Down, Left, Right, A, X, L, R, L2, R2,
+/// L3, R3). The directions are the 2-4-6-8 keys that most
+/// games use, and A is the 5 key in their middle.
+const JOYPAD_KEYS: [u8; 16] = [
+    0x0, 0x1, 0xa, 0xb, 0x2, 0x8, 0x4, 0x6,
+    0x5, 0x3, 0x7, 0x9, 0xc, 0xd, 0xe, 0xf,
+];
+
+/// The core options, with their description; the first value
+/// is the default. The themes are added by `variables'.
+const OPTIONS: [(&str, &str, &str); 2] = [
+    ("chip8_quirks", "Quirks", "none|vip|schip"),
+    ("chip8_ipf", "Instructions per frame",
+     "10|15|20|30|50|100|200|500|1000|1|2|3|5|7"),
+];
+
+const THEME_OPTION: &str = "chip8_theme";
+
+#[repr(C)]
+pub struct SystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    pub geometry: GameGeometry,
+    pub timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+/// A core option, given as "description; value|value|..."
+/// by the core, or the current value by the frontend.
+#[repr(C)]
+pub struct Variable {
+    pub key: *const c_char,
+    pub value: *const c_char,
+}
+
+pub type EnvironmentFn = extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type VideoRefreshFn = extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type AudioSampleFn = extern "C" fn(left: i16, right: i16);
+pub type AudioSampleBatchFn = extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type InputPollFn = extern "C" fn();
+pub type InputStateFn = extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+/// The functions given by the frontend.
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample: Option<AudioSampleFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+
+/// The loaded game, if any.
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+/// Return the core options as (key, "description; values")
+/// pairs.
+fn variables() -> Vec<(CString, CString)> {
+    let themes = palette::theme_names().join("|");
+    let mut options: Vec<(&str, &str, &str)> = OPTIONS.to_vec();
+    options.push((THEME_OPTION, "Colors", &themes));
+    options.iter().map(|&(key, desc, values)| {
+        (CString::new(key).unwrap(), CString::new(format!("{}; {}", desc, values)).unwrap())
+    }).collect()
+}
+
+/// Lock a mutex, even if a panic happened while it was held.
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn callbacks() -> Callbacks {
+    *lock(&CALLBACKS)
+}
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match callbacks().environment {
+        Some(f) => f(cmd, data),
+        None => false,
+    }
+}
+
+/// Return the value of a core option, as set in the frontend.
+fn get_variable(key: &CStr) -> Option<String> {
+    let mut var = Variable { key: key.as_ptr(), value: ptr::null() };
+    if !environment(ENVIRONMENT_GET_VARIABLE, &mut var as *mut Variable as *mut c_void)
+        || var.value.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr(var.value) }.to_string_lossy().into_owned())
+}
+
+/// Return the state of the CHIP-8 keys, from the joypad and
+/// from the keyboard (the COSMAC VIP layout, as by default in
+/// the emulator).
+fn read_keys(input_state: InputStateFn) -> [bool; cpu::NUM_KEYS] {
+    let mut keys = [false; cpu::NUM_KEYS];
+    for (id, &key) in JOYPAD_KEYS.iter().enumerate() {
+        if input_state(0, DEVICE_JOYPAD, 0, id as c_uint) != 0 {
+            keys[key as usize] = true;
+        }
+    }
+    // The libretro key codes of letters and digits are their
+    // lower case ASCII codes.
+    for &(ref host, key) in Keymap::cosmac_vip().bindings() {
+        let code = host.to_ascii_lowercase().as_bytes()[0];
+        if input_state(0, DEVICE_KEYBOARD, 0, c_uint::from(code)) != 0 {
+            keys[key as usize] = true;
+        }
+    }
+    keys
+}
+
+struct Core {
+    cpu: CPU,
+    game: Vec<u8>,
+    palette: Palette,
+    insns_per_frame: u32,
+    beeper: Beeper,
+    /// The display in XRGB8888, for the frontend.
+    video: Vec<u32>,
+    /// A frame of sound, in interleaved stereo, for the frontend.
+    audio: Vec<i16>,
+}
+
+impl Core {
+    fn new(game: Vec<u8>) -> Core {
+        let mut core = Core {
+            cpu: CPU::new(),
+            game,
+            palette: Palette::default(),
+            insns_per_frame: 0,
+            beeper: Beeper::new(),
+            video: vec![0; WIDTH * HEIGHT],
+            audio: Vec::with_capacity(sound::SAMPLES_PER_FRAME * 2),
+        };
+        for &(key, _, values) in OPTIONS.iter() {
+            core.set_option(key, values.split('|').next().unwrap());
+        }
+        core.load();
+        core
+    }
+
+    /// Load the font and the game into a reset CPU.
+    fn load(&mut self) {
+        self.cpu.reset();
+        self.cpu.load_font(FontStyle::Standard.data(), font::DEFAULT_FONT_BASE);
+        self.cpu.load_bytes(&self.game, cpu::PC_START);
+    }
+
+    /// Apply a core option. Unknown values are ignored.
+    fn set_option(&mut self, key: &str, value: &str) {
+        match key {
+            "chip8_quirks" => if let Ok(quirks) = Quirks::parse(value) {
+                self.cpu.set_quirks(quirks);
+            },
+            "chip8_ipf" => if let Ok(n) = value.parse() {
+                self.insns_per_frame = n;
+            },
+            THEME_OPTION => if let Some(palette) = Palette::theme(value) {
+                self.palette = palette;
+            },
+            _ => {}
+        }
+    }
+
+    /// Run a frame, with the keys if the frontend reads input,
+    /// leaving its picture in `video' and its sound in `audio'.
+    fn run(&mut self, keys: Option<[bool; cpu::NUM_KEYS]>) {
+        if let Some(keys) = keys {
+            self.cpu.set_keys(keys);
+        }
+        self.cpu.run_frame(self.insns_per_frame);
+
+        // The buffers are handed to the frontend and back; they
+        // are empty if the frontend kept them (see `retro_run').
+        self.video.resize(WIDTH * HEIGHT, 0);
+        let palette = &self.palette;
+        let pixels = self.cpu.display().rows().flat_map(|row| row.iter());
+        for (out, &val) in self.video.iter_mut().zip(pixels) {
+            let (r, g, b) = palette.color(val);
+            *out = u32::from(r) << 16 | u32::from(g) << 8 | u32::from(b);
+        }
+        self.audio.clear();
+        self.audio.extend(self.beeper.next_samples(self.cpu.beeping(), sound::SAMPLES_PER_FRAME)
+                            .flat_map(|s| [s, s]));
+    }
+}
+
+/// Return the core options set in the frontend, as (key, value)
+/// pairs.
+fn option_values() -> Vec<(String, String)> {
+    variables().into_iter()
+        .filter_map(|(key, _)| get_variable(&key).map(|value| (key.to_string_lossy().into_owned(), value)))
+        .collect()
+}
+
+/// Hand a frame of video and (stereo) audio to the frontend.
+fn output(cb: &Callbacks, video: &[u32], audio: &[i16]) {
+    if let Some(video_refresh) = cb.video_refresh {
+        video_refresh(video.as_ptr() as *const c_void, WIDTH as c_uint, HEIGHT as c_uint, WIDTH * 4);
+    }
+    let frames = audio.len() / 2;
+    if let Some(audio_sample_batch) = cb.audio_sample_batch {
+        let mut done = 0;
+        while done < frames {
+            let n = audio_sample_batch(audio[done * 2 ..].as_ptr(), frames - done);
+            if n == 0 {
+                break;
+            }
+            done += n;
+        }
+    } else if let Some(audio_sample) = cb.audio_sample {
+        for s in audio.chunks(2) {
+            audio_sample(s[0], s[1]);
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: Option<EnvironmentFn>) {
+    lock(&CALLBACKS).environment = cb;
+    let variables = variables();
+    let mut vars: Vec<Variable> = variables.iter().map(|(key, value)| {
+        Variable { key: key.as_ptr(), value: value.as_ptr() }
+    }).collect();
+    vars.push(Variable { key: ptr::null(), value: ptr::null() });
+    environment(ENVIRONMENT_SET_VARIABLES, vars.as_mut_ptr() as *mut c_void);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: Option<VideoRefreshFn>) {
+    lock(&CALLBACKS).video_refresh = cb;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(cb: Option<AudioSampleFn>) {
+    lock(&CALLBACKS).audio_sample = cb;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: Option<AudioSampleBatchFn>) {
+    lock(&CALLBACKS).audio_sample_batch = cb;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: Option<InputPollFn>) {
+    lock(&CALLBACKS).input_poll = cb;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: Option<InputStateFn>) {
+    lock(&CALLBACKS).input_state = cb;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *lock(&CORE) = None;
+}
+
+/// # Safety
+///
+/// `info' must point to a `SystemInfo'.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: b"CHIP-8\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"ch8|c8|rom\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+///
+/// `info' must point to a `SystemAvInfo'.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+        },
+        timing: SystemTiming {
+            fps: f64::from(cpu::TIMER_HZ),
+            sample_rate: f64::from(sound::SAMPLE_RATE),
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(ref mut core) = *lock(&CORE) {
+        core.load();
+    }
+}
+
+/// The frontend is never called with CORE locked, as it may
+/// call back into the core.
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let cb = callbacks();
+    if let Some(input_poll) = cb.input_poll {
+        input_poll();
+    }
+    let mut updated = false;
+    let updated = environment(ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void)
+        && updated;
+    let options = if updated { option_values() } else { Vec::new() };
+    let keys = cb.input_state.map(read_keys);
+    let (video, audio) = match *lock(&CORE) {
+        Some(ref mut core) => {
+            for (key, value) in &options {
+                core.set_option(key, value);
+            }
+            core.run(keys);
+            (mem::take(&mut core.video), mem::take(&mut core.audio))
+        },
+        None => return,
+    };
+    output(&cb, &video, &audio);
+    // Give the buffers back, to be used again next frame.
+    if let Some(ref mut core) = *lock(&CORE) {
+        core.video = video;
+        core.audio = audio;
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    match *lock(&CORE) {
+        Some(ref core) => core.cpu.save_state().len(),
+        None => 0,
+    }
+}
+
+/// # Safety
+///
+/// `data' must point to `size' writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    match *lock(&CORE) {
+        Some(ref core) => {
+            let state = core.cpu.save_state();
+            if state.len() > size {
+                return false;
+            }
+            ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+            true
+        }
+        None => false,
+    }
+}
+
+/// # Safety
+///
+/// `data' must point to `size' readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    match *lock(&CORE) {
+        Some(ref mut core) => {
+            let state = slice::from_raw_parts(data as *const u8, size);
+            core.cpu.load_state(state).is_ok()
+        }
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+///
+/// `game' must be null or point to a `GameInfo' whose `data'
+/// holds `size' bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let game = &*game;
+    if game.size == 0 || game.size > cpu::MEM_SIZE - cpu::PC_START {
+        return false;
+    }
+    let mut format = PIXEL_FORMAT_XRGB8888;
+    if !environment(ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+        return false;
+    }
+    let data = slice::from_raw_parts(game.data as *const u8, game.size).to_vec();
+    let mut core = Core::new(data);
+    for (key, value) in option_values() {
+        core.set_option(&key, &value);
+    }
+    *lock(&CORE) = Some(core);
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const GameInfo,
+                                          _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *lock(&CORE) = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
@@ -1,34 +1,232 @@
-
-
-mod cpu;
-mod screen;
-mod chip8;
-
+extern crate chip8_emu;
+extern crate crossterm;
+extern crate gif;
+extern crate png;
 extern crate rand;
+extern crate rhai;
 extern crate sdl2;
+#[macro_use]
+extern crate serde_json;
+extern crate sha1;
 extern crate structopt;
 
 #[macro_use]
 extern crate structopt_derive;
 
-#[macro_use]
-extern crate lazy_static;
-#[macro_use]
-extern crate maplit;
+use chip8_emu::{cpu, framebuffer, palette, font, quirks, bounds, stack, sound, keymap};
+
+mod screen;
+mod chip8;
+mod filter;
+mod gamepad;
+mod osd;
+mod romdb;
+mod rom;
+mod screenshot;
+mod record;
+mod frontend;
+mod terminal;
+mod script;
+mod control;
+mod netplay;
 
+use std::fs;
+use std::process;
 use structopt::StructOpt;
+use palette::Palette;
+use filter::Filter;
+use font::FontStyle;
+use keymap::Keymap;
+use quirks::Quirks;
+use bounds::Bounds;
+use romdb::{RomDb, RomInfo};
+use rom::Platform;
+use script::Script;
+use control::Control;
+use std::path::Path;
 
 #[derive(StructOpt, Debug)]
 struct Opt {
-    #[structopt(long = "fontfile", help = "Name of the file containing fonts")]
-    font_file: String,
+    #[structopt(long = "fontfile", help = "Name of a file containing fonts. Overrides --font")]
+    font_file: Option<String>,
+    #[structopt(long = "font", help = "Built-in font: standard, vip, dream6800, eti660 or fishnchips. Default is standard")]
+    font_style: Option<font::FontStyle>,
+    #[structopt(long = "fontbase", help = "Address (in hex) at which the font is loaded, eg: 0x050. Default is 0x000")]
+    font_base: Option<String>,
     #[structopt(long = "gamefile", help = "Name of the file containing game code")]
     game_file: String,
     #[structopt(long = "scale", help = "The scale factor of the Window. Default is 5")]
     scale_factor: Option<u32>,
+    #[structopt(long = "ipf", help = "Instructions executed per 1/60 second frame. Default is 10")]
+    insns_per_frame: Option<u32>,
+    #[structopt(long = "hz", help = "CPU speed in instructions per second, rounded to a whole number per frame. Cannot be used with --ipf")]
+    cpu_hz: Option<u32>,
+    #[structopt(long = "theme", help = "Color theme: default, amber, green, lcd or high-contrast")]
+    theme: Option<String>,
+    #[structopt(long = "palette", help = "Name of a file containing a color palette, one hex color per line")]
+    palette_file: Option<String>,
+    #[structopt(long = "fg", help = "Foreground color in hex, eg: #ffb000")]
+    fg: Option<String>,
+    #[structopt(long = "bg", help = "Background color in hex, eg: #000000")]
+    bg: Option<String>,
+    #[structopt(long = "filter", help = "Display filter to reduce flicker: persistence, or, blend")]
+    filter: Option<String>,
+    #[structopt(long = "decay", help = "Fade out time in ms for the persistence filter. Default is 100")]
+    decay_ms: Option<u32>,
+    #[structopt(long = "blend-frames", help = "Number of frames combined by the or filter. Default is 2")]
+    blend_frames: Option<usize>,
+    #[structopt(long = "blend-weights", help = "Comma separated frame weights for the blend filter, newest first. Default is 1,0.5")]
+    blend_weights: Option<String>,
+    #[structopt(long = "scaling", help = "How the display is scaled to the window: integer or fractional. Default is integer")]
+    scaling: Option<screen::Scaling>,
+    #[structopt(long = "fullscreen", help = "Start in fullscreen mode")]
+    fullscreen: bool,
+    #[structopt(long = "terminal", help = "Play in the terminal instead of a window, drawing with half-block or braille characters")]
+    terminal: Option<terminal::Glyphs>,
+    #[structopt(long = "keymap", help = "Name of a file with key bindings, eg: \"5 = W, Up\" per line")]
+    keymap_file: Option<String>,
+    #[structopt(long = "key", help = "Key binding, eg: \"5 = W, Up, Pad1 dpup\". Can be given multiple times")]
+    key_bindings: Vec<String>,
+    #[structopt(long = "quirks", help = "Interpreter quirks, eg: vip, schip, none or \"shift-vy, clip\". Default is from the ROM database, else none")]
+    quirks: Option<String>,
+    #[structopt(long = "bounds", help = "Out of bounds memory, PC and stack accesses: wrap, trap or vip, or eg: \"mem=wrap, stack=trap\". Default is trap")]
+    bounds: Option<String>,
+    #[structopt(long = "stack", help = "Stack model: mem[:<hex base>[:<depth>]], array[:<depth>] or unlimited. Default is mem:0xea0:24")]
+    stack_model: Option<stack::StackModel>,
+    #[structopt(long = "opcodes", help = "What to do with SYS (0nnn) and unknown opcodes: ignore (with a warning) or halt. Default is ignore")]
+    opcode_policy: Option<cpu::OpcodePolicy>,
+    #[structopt(long = "platform", help = "Machine the game is written for: chip8, schip or xochip. Default is from the ROM database, else chip8")]
+    platform: Option<Platform>,
+    #[structopt(long = "romdb", help = "Name of a ROM database file adding to (or replacing) the built-in entries")]
+    romdb_file: Option<String>,
+    #[structopt(long = "no-romdb", help = "Do not apply settings from the ROM database")]
+    no_romdb: bool,
+    #[structopt(long = "screenshot-at", help = "Save the display as PNG and SVG images after this many frames. F12 saves it at any time")]
+    screenshot_at: Option<u64>,
+    #[structopt(long = "record-video", help = "Record every frame to a file: an animated GIF (.gif) or a Y4M video (.y4m) plus a WAV file of the sound. F9 starts and stops recording at any time")]
+    record_video: Option<String>,
+    #[structopt(long = "frames", help = "Quit after running this many frames")]
+    max_frames: Option<u64>,
+    #[structopt(long = "headless", help = "Run without a window, as fast as possible. Requires --frames")]
+    headless: bool,
+    #[structopt(long = "script", help = "Name of a Rhai script to run with the game, for automating play or for cheats")]
+    script: Option<String>,
+    #[structopt(long = "control", help = "Take JSON-RPC requests from other programs on this address: host:port, a port on localhost, or unix:<path>. Anyone who can connect has full control")]
+    control: Option<String>,
+    #[structopt(long = "netplay", help = "Play a two-player game with someone on another machine: listen:<port>, or connect:<host>:<port> to the player listening")]
+    netplay: Option<netplay::Role>,
+    #[structopt(long = "netplay-protocol", help = "Protocol for netplay: udp or tcp. Default is udp")]
+    netplay_protocol: Option<netplay::Protocol>,
+    #[structopt(long = "netplay-delay", help = "Frames by which local keys are delayed in netplay; more means fewer corrections. Default is 2")]
+    netplay_delay: Option<u32>,
 }
 
+const DEFAULT_DECAY_MS: u32 = 100;
+const DEFAULT_BLEND_FRAMES: usize = 2;
+const DEFAULT_BLEND_WEIGHTS: &str = "1,0.5";
 
+/// Print an error message and exit.
+fn fail(msg: &str) -> ! {
+    eprintln!("error: {}", msg);
+    process::exit(1);
+}
+
+/// Build the palette from the ROM database entry, theme,
+/// palette file and color options. Later options override
+/// earlier ones.
+fn make_palette(opt: &Opt, info: &RomInfo) -> Palette {
+    let mut palette = info.palette.clone().unwrap_or_default();
+    if let Some(ref name) = opt.theme {
+        palette = Palette::theme(name)
+                    .unwrap_or_else(|| fail(&format!("unknown theme: {} (expected one of: {})",
+                                                      name, palette::theme_names().join(", "))));
+    }
+    if let Some(ref filename) = opt.palette_file {
+        palette = Palette::from_file(filename).unwrap_or_else(|e| fail(&e));
+    }
+    if let Some(ref bg) = opt.bg {
+        palette.set_color(0, palette::parse_color(bg).unwrap_or_else(|e| fail(&e)));
+    }
+    if let Some(ref fg) = opt.fg {
+        palette.set_color(1, palette::parse_color(fg).unwrap_or_else(|e| fail(&e)));
+    }
+    palette
+}
+
+/// Build the display filter selected on the command line.
+fn make_filter(opt: &Opt) -> Option<Filter> {
+    let name = match opt.filter {
+        Some(ref name) => name,
+        None => return None,
+    };
+    let weights: Vec<f32> = opt.blend_weights.as_ref()
+        .map_or(DEFAULT_BLEND_WEIGHTS, |w| w.as_str())
+        .split(',')
+        .map(|w| w.trim().parse()
+                  .unwrap_or_else(|_| fail(&format!("invalid blend weight: {}", w))))
+        .collect();
+    let filter = Filter::from_name(
+                    name, 
+                    opt.decay_ms.unwrap_or(DEFAULT_DECAY_MS),
+                    opt.blend_frames.unwrap_or(DEFAULT_BLEND_FRAMES),
+                    &weights);
+    Some(filter.unwrap_or_else(|e| fail(&e)))
+}
+
+/// Return the font (from --fontfile or one of the built-in
+/// ones) and the address at which it is to be loaded.
+fn make_font(opt: &Opt) -> (Vec<u8>, usize) {
+    let base = opt.font_base.as_ref()
+                .map_or(Ok(font::DEFAULT_FONT_BASE), |b| font::parse_base(b))
+                .unwrap_or_else(|e| fail(&e));
+    let data = match opt.font_file {
+        Some(ref filename) => font::from_file(filename, base).unwrap_or_else(|e| fail(&e)),
+        None => opt.font_style.unwrap_or(FontStyle::Standard).data().to_vec(),
+    };
+    (data, base)
+}
+
+/// Look up the game in the ROM database, returning an empty
+/// entry for unknown games (or if the database is disabled).
+fn lookup_rom(opt: &Opt, game: &[u8]) -> RomInfo {
+    if opt.no_romdb {
+        return RomInfo::default();
+    }
+    let mut db = RomDb::builtin();
+    if let Some(ref filename) = opt.romdb_file {
+        db.extend_from_file(filename).unwrap_or_else(|e| fail(&e));
+    }
+    let info = match db.lookup(game) {
+        Some(info) => info.clone(),
+        None => return RomInfo::default(),
+    };
+    if let Some(ref title) = info.title {
+        match info.author {
+            Some(ref author) => eprintln!("{} by {}", title, author),
+            None => eprintln!("{}", title),
+        }
+    }
+    info
+}
+
+/// Build the keymap: the COSMAC VIP layout, modified by the
+/// ROM database entry, the keymap file (including the section
+/// for this ROM, if any) and then by the --key options.
+fn make_keymap(opt: &Opt, info: &RomInfo) -> Keymap {
+    let mut keymap = Keymap::cosmac_vip();
+    for spec in info.keys.iter() {
+        keymap.apply_spec(spec).unwrap_or_else(|e| fail(&e));
+    }
+    if let Some(ref filename) = opt.keymap_file {
+        let rom = Path::new(&opt.game_file).file_name()
+                    .map_or(String::new(), |n| n.to_string_lossy().into_owned());
+        keymap.apply_file(filename, &rom).unwrap_or_else(|e| fail(&e));
+    }
+    for spec in opt.key_bindings.iter() {
+        keymap.apply_spec(spec).unwrap_or_else(|e| fail(&e));
+    }
+    keymap
+}
 
 fn main() {
     let opt = Opt::from_args();
@@ -38,6 +236,86 @@ fn main() {
         scale_factor = s;
     }
 
-    chip8::chip8_run(&opt.font_file, &opt.game_file, scale_factor);
+    let game = fs::read(&opt.game_file)
+                .unwrap_or_else(|e| fail(&format!("{}: {}", opt.game_file, e)));
+    let info = lookup_rom(&opt, &game);
+    let platform = opt.platform.or(info.platform).unwrap_or(Platform::Chip8);
+    let warnings = rom::check(&game, cpu::PC_START, platform)
+                    .unwrap_or_else(|e| fail(&format!("{}: {}", opt.game_file, e)));
+    for w in warnings {
+        eprintln!("warning: {}: {}", opt.game_file, w);
+    }
+
+    if opt.insns_per_frame.is_some() && opt.cpu_hz.is_some() {
+        fail("--ipf and --hz cannot both be given");
+    }
+    let mut insns_per_frame = info.insns_per_frame.unwrap_or(chip8::DEFAULT_INSNS_PER_FRAME);
+    if let Some(n) = opt.insns_per_frame {
+        insns_per_frame = n;
+    } else if let Some(hz) = opt.cpu_hz {
+        let fps = chip8::FRAMES_PER_SECOND;
+        insns_per_frame = std::cmp::max(1, hz.saturating_add(fps / 2) / fps);
+        let actual = u64::from(insns_per_frame) * u64::from(fps);
+        if actual != u64::from(hz) {
+            eprintln!("warning: running at {} Hz instead of {} Hz ({} instructions per frame)",
+                      actual, hz, insns_per_frame);
+        }
+    }
+
+    let (font, font_base) = make_font(&opt);
+    let quirks = match opt.quirks {
+        Some(ref q) => Quirks::parse(q).unwrap_or_else(|e| fail(&e)),
+        None => info.quirks.unwrap_or_default(),
+    };
+    let bounds = opt.bounds.as_ref()
+                    .map_or(Ok(Bounds::default()), |b| Bounds::parse(b))
+                    .unwrap_or_else(|e| fail(&e));
+
+    if opt.headless && opt.max_frames.is_none() {
+        fail("--headless requires --frames");
+    }
+    if opt.headless && opt.control.is_some() {
+        fail("--control cannot be used with --headless");
+    }
+    if opt.netplay.is_some() && (opt.headless || opt.script.is_some()) {
+        fail("--netplay cannot be used with --headless or --script");
+    }
+    let config = chip8::Config {
+        screen: screen::Options {
+            scale_factor,
+            scaling: opt.scaling.unwrap_or(screen::Scaling::Integer),
+            fullscreen: opt.fullscreen,
+            palette: make_palette(&opt, &info),
+            filter: make_filter(&opt),
+            keymap: make_keymap(&opt, &info),
+        },
+        font,
+        font_base,
+        game,
+        quirks,
+        bounds,
+        stack_model: opt.stack_model.unwrap_or_default(),
+        opcode_policy: opt.opcode_policy.unwrap_or(cpu::OpcodePolicy::Ignore),
+        insns_per_frame,
+        name: Path::new(&opt.game_file).file_stem()
+                .map_or(String::from("chip8"), |n| n.to_string_lossy().into_owned()),
+        terminal: opt.terminal,
+        screenshot_at: opt.screenshot_at,
+        record_video: opt.record_video.clone(),
+        max_frames: opt.max_frames,
+        script: opt.script.as_ref().map(|f| Script::load(f).unwrap_or_else(|e| fail(&e))),
+        control: opt.control.as_ref().map(|a| Control::bind(a).unwrap_or_else(|e| fail(&e))),
+        netplay: opt.netplay.clone().map(|role| netplay::Options {
+            role,
+            protocol: opt.netplay_protocol.unwrap_or(netplay::Protocol::Udp),
+            delay: opt.netplay_delay.unwrap_or(netplay::DEFAULT_DELAY),
+        }),
+    };
+    let result = if opt.headless {
+        chip8::chip8_run_headless(config)
+    } else {
+        chip8::chip8_run(config)
+    };
+    result.unwrap_or_else(|e| fail(&e));
 
 }
@@ -0,0 +1,715 @@
+// netplay.rs
+
+//! Netplay: two players on different machines sharing the
+//! keypad, as in PONG2 or TANK. Each player runs the game on
+//! their own emulator; the emulators exchange the keys pressed
+//! in every frame, so that they run in lockstep. One player
+//! listens, the other connects:
+//!
+//! ```text
+//! chip8_emu --gamefile PONG2 --netplay listen:7000
+//! chip8_emu --gamefile PONG2 --netplay connect:192.168.1.20:7000
+//! ```
+//!
+//! The keys of the other player arrive late, so the emulator
+//! guesses them (the last ones received) and runs on. When the
+//! real keys turn out to be different, it rolls back: it loads
+//! the save state from before the first wrong guess and runs
+//! the frames again, with the right keys, within the same
+//! 1/60 second. Local keys are applied a couple of frames late
+//! (--netplay-delay), which makes wrong guesses rarer.
+//!
+//! Every second, the players compare a hash of the RAM and the
+//! display of the last frame both agree on; a difference means
+//! the games have diverged (eg: different quirks), which stops
+//! the emulator.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand;
+
+use cpu::{CPU, NUM_KEYS};
+
+/// Changed whenever the messages or the emulation change in a
+/// way which would break netplay between the old and the new
+/// version.
+const PROTOCOL_VERSION: u8 = 1;
+
+/// Default number of frames by which local keys are delayed.
+pub const DEFAULT_DELAY: u32 = 2;
+
+/// The emulator may run these many frames ahead of the last
+/// keys received from the other player; beyond that, it waits.
+const MAX_ROLLBACK: u64 = 8;
+
+/// Number of frames between two hash checks.
+const HASH_INTERVAL: u64 = 60;
+
+/// Number of our hashes kept for comparison with the other
+/// player's.
+const HASHES_KEPT: usize = 8;
+
+/// Give up if nothing is heard from the other player for this
+/// long.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a player who is connecting says hello, until
+/// answered.
+const HELLO_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Largest message, in bytes.
+const MAX_MESSAGE: usize = 1024;
+
+/// Most keys sent in a message; the rest follow later.
+const MAX_KEYS_SENT: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    Udp,
+    Tcp,
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Protocol, String> {
+        match s {
+            "udp" => Ok(Protocol::Udp),
+            "tcp" => Ok(Protocol::Tcp),
+            _ => Err(format!("unknown netplay protocol: {}", s)),
+        }
+    }
+}
+
+/// Whether we wait for the other player or connect to them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Role {
+    /// Listen on this port, on all interfaces.
+    Listen(u16),
+    /// Connect to "host:port".
+    Connect(String),
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    /// Parse "listen:<port>" or "connect:<host>:<port>".
+    fn from_str(s: &str) -> Result<Role, String> {
+        if let Some(port) = s.strip_prefix("listen:") {
+            port.parse().map(Role::Listen).map_err(|_| format!("invalid port: {}", port))
+        } else if let Some(addr) = s.strip_prefix("connect:") {
+            if addr.rsplit_once(':').is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok()) {
+                Ok(Role::Connect(addr.to_string()))
+            } else {
+                Err(format!("invalid address: {} (expected host:port)", addr))
+            }
+        } else {
+            Err(format!("invalid netplay setting: {} (expected listen:<port> or connect:<host>:<port>)", s))
+        }
+    }
+}
+
+/// Settings for netplay, usually taken from the command line.
+pub struct Options {
+    pub role: Role,
+    pub protocol: Protocol,
+    /// Number of frames by which local keys are delayed.
+    pub delay: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Message {
+    /// From the player who connects. `setup' is a hash of the
+    /// game and settings, which must be the same for both.
+    Hello { version: u8, setup: u64 },
+    /// The answer to a hello: the seed for the random numbers.
+    Welcome { seed: u64 },
+    /// The answer to a hello from a player who cannot join.
+    Reject(String),
+    /// Our keys from frame `start' on, and the number of
+    /// frames for which we have the other player's keys. With
+    /// the hash of the last frame both agree on, if any.
+    Input { ack: u64, start: u64, keys: Vec<u16>, hash: Option<(u64, u64)> },
+    Quit,
+}
+
+/// Reads the fields of a message.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < n {
+            return Err(String::from("message too short"));
+        }
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_be_bytes(b))
+    }
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match *self {
+            Message::Hello { version, setup } => {
+                out.push(b'H');
+                out.push(version);
+                out.extend_from_slice(&setup.to_be_bytes());
+            },
+            Message::Welcome { seed } => {
+                out.push(b'W');
+                out.extend_from_slice(&seed.to_be_bytes());
+            },
+            Message::Reject(ref reason) => {
+                out.push(b'R');
+                out.extend_from_slice(reason.as_bytes());
+            },
+            Message::Input { ack, start, ref keys, hash } => {
+                out.push(b'I');
+                out.extend_from_slice(&ack.to_be_bytes());
+                out.extend_from_slice(&start.to_be_bytes());
+                out.extend_from_slice(&(keys.len() as u16).to_be_bytes());
+                for k in keys {
+                    out.extend_from_slice(&k.to_be_bytes());
+                }
+                if let Some((frame, hash)) = hash {
+                    out.extend_from_slice(&frame.to_be_bytes());
+                    out.extend_from_slice(&hash.to_be_bytes());
+                }
+            },
+            Message::Quit => out.push(b'Q'),
+        }
+        out
+    }
+
+    fn decode(data: &[u8]) -> Result<Message, String> {
+        let mut r = Reader { data };
+        let msg = match r.u8()? {
+            b'H' => Message::Hello { version: r.u8()?, setup: r.u64()? },
+            b'W' => Message::Welcome { seed: r.u64()? },
+            b'R' => Message::Reject(String::from_utf8_lossy(r.bytes(r.data.len())?).into_owned()),
+            b'I' => {
+                let (ack, start) = (r.u64()?, r.u64()?);
+                let count = r.u16()?;
+                let keys = (0..count).map(|_| r.u16()).collect::<Result<_, _>>()?;
+                let hash = if r.data.is_empty() { None } else { Some((r.u64()?, r.u64()?)) };
+                Message::Input { ack, start, keys, hash }
+            },
+            b'Q' => Message::Quit,
+            t => return Err(format!("unknown message type: {}", t)),
+        };
+        if !r.data.is_empty() {
+            return Err(String::from("message too long"));
+        }
+        Ok(msg)
+    }
+}
+
+/// Carries messages to and from the other player, without
+/// blocking.
+pub trait Transport {
+    fn send(&mut self, msg: &[u8]) -> io::Result<()>;
+
+    /// Return the next message received, if any.
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// One message per datagram. Lost messages do not matter,
+/// as every message repeats the keys not yet acknowledged.
+struct UdpTransport(UdpSocket);
+
+impl Transport for UdpTransport {
+    fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        match self.0.send(msg) {
+            // Nobody listening yet, or any more: the timeout
+            // takes care of it.
+            Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+            r => r.map(|_| ()),
+        }
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = [0; MAX_MESSAGE];
+        match self.0.recv(&mut buf) {
+            Ok(n) => Ok(Some(buf[..n].to_vec())),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock
+                       || e.kind() == io::ErrorKind::ConnectionRefused => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Messages are preceded by their length, as 2 bytes.
+struct TcpTransport {
+    stream: TcpStream,
+    /// Bytes received but not yet returned.
+    buf: Vec<u8>,
+    /// Bytes sent but not yet written to the socket, which
+    /// was full.
+    out: Vec<u8>,
+}
+
+impl TcpTransport {
+    /// Write as much of `out' as the socket takes.
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.out.is_empty() {
+            match self.stream.write(&self.out) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "connection closed")),
+                Ok(n) => { self.out.drain(..n); },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        self.out.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+        self.out.extend_from_slice(msg);
+        self.flush()
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.flush()?;
+        let mut chunk = [0; MAX_MESSAGE];
+        loop {
+            if self.buf.len() >= 2 {
+                let len = usize::from(u16::from_be_bytes([self.buf[0], self.buf[1]]));
+                if self.buf.len() >= 2 + len {
+                    let msg = self.buf[2 .. 2 + len].to_vec();
+                    self.buf.drain(.. 2 + len);
+                    return Ok(Some(msg));
+                }
+            }
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")),
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn send(transport: &mut dyn Transport, msg: &Message) -> Result<(), String> {
+    transport.send(&msg.encode()).map_err(|e| format!("netplay: {}", e))
+}
+
+/// Wait for a message, sending `hello' every HELLO_INTERVAL
+/// if given. Messages which cannot be decoded are ignored.
+fn wait_for_message(transport: &mut dyn Transport, hello: Option<&Message>) -> Result<Message, String> {
+    let start = Instant::now();
+    let mut last_hello: Option<Instant> = None;
+    while start.elapsed() < TIMEOUT {
+        if let Some(hello) = hello {
+            if last_hello.is_none_or(|t| t.elapsed() >= HELLO_INTERVAL) {
+                send(transport, hello)?;
+                last_hello = Some(Instant::now());
+            }
+        }
+        match transport.recv().map_err(|e| format!("netplay: {}", e))? {
+            Some(data) => {
+                if let Ok(msg) = Message::decode(&data) {
+                    return Ok(msg);
+                }
+            },
+            None => thread::sleep(Duration::from_millis(5)),
+        }
+    }
+    Err(String::from("netplay: no answer from the other player"))
+}
+
+/// FNV-1a, over `data'.
+fn fnv1a<'a, I: IntoIterator<Item = &'a u8>>(hash: u64, data: I) -> u64 {
+    data.into_iter().fold(hash, |h, b| (h ^ u64::from(*b)).wrapping_mul(0x100_0000_01b3))
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// A hash of what both players must start from: the machine
+/// and the speed.
+pub fn setup_hash(c: &CPU, insns_per_frame: u32) -> u64 {
+    fnv1a(fnv1a(FNV_OFFSET, &c.save_state()), &insns_per_frame.to_be_bytes())
+}
+
+/// A hash of the RAM and the display, for spotting games which
+/// have diverged.
+fn state_hash(c: &CPU) -> u64 {
+    c.display().rows().fold(fnv1a(FNV_OFFSET, c.mem()), fnv1a)
+}
+
+/// Check a hello, returning the reason for turning it down.
+fn check_hello(msg: &Message, setup: u64) -> Result<(), String> {
+    match *msg {
+        Message::Hello { version, .. } if version != PROTOCOL_VERSION => {
+            Err(String::from("a different version of the emulator"))
+        },
+        Message::Hello { setup: s, .. } if s != setup => Err(String::from("a different game or settings")),
+        Message::Hello { .. } => Ok(()),
+        _ => Err(String::from("not a hello")),
+    }
+}
+
+/// A socket waiting for the other player.
+enum Listener {
+    Udp(UdpSocket),
+    Tcp(TcpListener),
+}
+
+impl Listener {
+    /// Listen on `port', on all interfaces; 0 lets the system
+    /// choose.
+    fn bind(protocol: Protocol, port: u16) -> io::Result<Listener> {
+        Ok(match protocol {
+            Protocol::Udp => Listener::Udp(UdpSocket::bind(("0.0.0.0", port))?),
+            Protocol::Tcp => Listener::Tcp(TcpListener::bind(("0.0.0.0", port))?),
+        })
+    }
+
+    fn port(&self) -> io::Result<u16> {
+        match *self {
+            Listener::Udp(ref socket) => socket.local_addr().map(|a| a.port()),
+            Listener::Tcp(ref listener) => listener.local_addr().map(|a| a.port()),
+        }
+    }
+
+    /// Wait for the other player, turning down those with
+    /// another game, then return the session. `setup' is the
+    /// `setup_hash' of the game.
+    fn accept(self, setup: u64, delay: u32) -> Result<Session, String> {
+        let err = |e: io::Error| format!("netplay: {}", e);
+        let seed = rand::random();
+        match self {
+            Listener::Udp(socket) => {
+                let mut buf = [0; MAX_MESSAGE];
+                loop {
+                    let (n, addr) = socket.recv_from(&mut buf).map_err(err)?;
+                    let hello = match Message::decode(&buf[..n]) {
+                        Ok(msg) => msg,
+                        Err(_) => continue,
+                    };
+                    if let Err(reason) = check_hello(&hello, setup) {
+                        let _ = socket.send_to(&Message::Reject(reason).encode(), addr);
+                        continue;
+                    }
+                    socket.connect(addr).map_err(err)?;
+                    socket.set_nonblocking(true).map_err(err)?;
+                    eprintln!("netplay: {} joined", addr);
+                    let mut transport = UdpTransport(socket);
+                    send(&mut transport, &Message::Welcome { seed })?;
+                    return Ok(Session::new(Box::new(transport), seed, delay));
+                }
+            },
+            Listener::Tcp(listener) => {
+                loop {
+                    let (stream, addr) = listener.accept().map_err(err)?;
+                    let mut transport = tcp_transport(stream).map_err(err)?;
+                    let hello = match wait_for_message(&mut transport, None) {
+                        Ok(msg) => msg,
+                        Err(_) => continue,
+                    };
+                    match check_hello(&hello, setup) {
+                        Ok(()) => {
+                            eprintln!("netplay: {} joined", addr);
+                            send(&mut transport, &Message::Welcome { seed })?;
+                            return Ok(Session::new(Box::new(transport), seed, delay));
+                        },
+                        Err(reason) => {
+                            let _ = send(&mut transport, &Message::Reject(reason));
+                        },
+                    }
+                }
+            },
+        }
+    }
+}
+
+fn tcp_transport(stream: TcpStream) -> io::Result<TcpTransport> {
+    stream.set_nodelay(true)?;
+    stream.set_nonblocking(true)?;
+    Ok(TcpTransport { stream, buf: Vec::new(), out: Vec::new() })
+}
+
+/// Connect to the other player at `addr'.
+fn connect(protocol: Protocol, addr: &str, setup: u64, delay: u32) -> Result<Session, String> {
+    let err = |e: io::Error| format!("netplay: {}: {}", addr, e);
+    let mut transport: Box<dyn Transport> = match protocol {
+        Protocol::Udp => {
+            let remote: SocketAddr = std::net::ToSocketAddrs::to_socket_addrs(addr).map_err(err)?
+                                        .next().ok_or_else(|| format!("netplay: {}: no address", addr))?;
+            let local = if remote.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+            let socket = UdpSocket::bind(local).map_err(err)?;
+            socket.connect(remote).map_err(err)?;
+            socket.set_nonblocking(true).map_err(err)?;
+            Box::new(UdpTransport(socket))
+        },
+        Protocol::Tcp => Box::new(tcp_transport(TcpStream::connect(addr).map_err(err)?).map_err(err)?),
+    };
+    let hello = Message::Hello { version: PROTOCOL_VERSION, setup };
+    loop {
+        match wait_for_message(transport.as_mut(), Some(&hello))? {
+            Message::Welcome { seed } => return Ok(Session::new(transport, seed, delay)),
+            Message::Reject(reason) => return Err(format!("netplay: the other player has {}", reason)),
+            _ => {},
+        }
+    }
+}
+
+/// Start a session as asked in `options', for the game loaded
+/// in `c'. Blocks until the other player is there.
+pub fn start(options: &Options, c: &CPU, insns_per_frame: u32) -> Result<Session, String> {
+    let setup = setup_hash(c, insns_per_frame);
+    match options.role {
+        Role::Listen(port) => {
+            let listener = Listener::bind(options.protocol, port)
+                            .map_err(|e| format!("netplay: port {}: {}", port, e))?;
+            eprintln!("netplay: waiting for the other player on port {}", listener.port().unwrap_or(port));
+            listener.accept(setup, options.delay)
+        },
+        Role::Connect(ref addr) => {
+            eprintln!("netplay: connecting to {}", addr);
+            connect(options.protocol, addr, setup, options.delay)
+        },
+    }
+}
+
+fn key_bits(keys: [bool; NUM_KEYS]) -> u16 {
+    keys.iter().enumerate().fold(0, |bits, (k, pressed)| bits | u16::from(*pressed) << k)
+}
+
+fn key_array(bits: u16) -> [bool; NUM_KEYS] {
+    let mut keys = [false; NUM_KEYS];
+    for (k, pressed) in keys.iter_mut().enumerate() {
+        *pressed = bits & (1 << k) != 0;
+    }
+    keys
+}
+
+/// A game in progress with the other player.
+pub struct Session {
+    transport: Box<dyn Transport>,
+    /// Random numbers in frame n come from seed and n, so that
+    /// the frames run again after a rollback get the same ones.
+    seed: u64,
+    /// Number of frames run; the number of the next one.
+    frame: u64,
+    /// Our keys, by frame. The first `delay' frames have none.
+    local: Vec<u16>,
+    /// The other player's keys, by frame, as far as received.
+    remote: Vec<u16>,
+    /// Number of frames run with the other player's real keys.
+    confirmed: u64,
+    /// For each frame from `confirmed' on: the keys guessed for
+    /// the other player, and the state before the frame.
+    guesses: VecDeque<(u16, Vec<u8>)>,
+    /// Number of frames for which the other player has our keys.
+    acked: u64,
+    /// Hashes of the states after every `hash_interval' frames,
+    /// by number of frames run; they may change until
+    /// confirmed.
+    hashes: VecDeque<(u64, u64)>,
+    /// Our last confirmed hashes.
+    kept: VecDeque<(u64, u64)>,
+    /// The last confirmed hash, sent with our keys.
+    last_hash: Option<(u64, u64)>,
+    /// The last hash from the other player not yet checked.
+    remote_hash: Option<(u64, u64)>,
+    last_heard: Instant,
+    hash_interval: u64,
+    /// Number of rollbacks so far.
+    rollbacks: u64,
+}
+
+impl Session {
+    fn new(transport: Box<dyn Transport>, seed: u64, delay: u32) -> Session {
+        Session {
+            transport,
+            seed,
+            frame: 0,
+            local: vec![0; delay as usize],
+            remote: Vec::new(),
+            confirmed: 0,
+            guesses: VecDeque::new(),
+            acked: 0,
+            hashes: VecDeque::new(),
+            kept: VecDeque::new(),
+            last_hash: None,
+            remote_hash: None,
+            last_heard: Instant::now(),
+            hash_interval: HASH_INTERVAL,
+            rollbacks: 0,
+        }
+    }
+
+    /// Number of times frames were run again, for statistics.
+    pub fn rollbacks(&self) -> u64 {
+        self.rollbacks
+    }
+
+    /// Run the next frame with `keys' pressed by the local
+    /// player, rolling back first if the other player's keys
+    /// were guessed wrong. Returns false if the frame could not
+    /// be run yet, because the other player is too far behind.
+    /// Returns an error if the other player has left, or if the
+    /// games have diverged.
+    pub fn run_frame(&mut self, c: &mut CPU, keys: [bool; NUM_KEYS], insns_per_frame: u32)
+        -> Result<bool, String> {
+        self.receive()?;
+        self.roll_back(c, insns_per_frame)?;
+        self.check_hashes()?;
+        let ran = self.frame < self.remote.len() as u64 + MAX_ROLLBACK;
+        if ran {
+            self.local.push(key_bits(keys));
+            self.step(c, insns_per_frame);
+        }
+        self.send_input()?;
+        Ok(ran)
+    }
+
+    /// Handle the messages from the other player.
+    fn receive(&mut self) -> Result<(), String> {
+        loop {
+            let data = match self.transport.recv() {
+                Ok(Some(data)) => data,
+                Ok(None) => break,
+                Err(e) => return Err(format!("netplay: lost the other player: {}", e)),
+            };
+            self.last_heard = Instant::now();
+            match Message::decode(&data) {
+                Ok(Message::Input { ack, start, keys, hash }) => {
+                    // A stale or bogus message may acknowledge frames
+                    // we have not run, or leave a gap in the keys.
+                    self.acked = self.acked.max(ack.min(self.local.len() as u64));
+                    if let Some(known) = (self.remote.len() as u64).checked_sub(start) {
+                        self.remote.extend(keys.into_iter().skip(known as usize));
+                    }
+                    if hash.is_some_and(|(frame, _)| self.remote_hash.is_none_or(|(f, _)| frame > f)) {
+                        self.remote_hash = hash;
+                    }
+                },
+                // Our welcome was lost.
+                Ok(Message::Hello { .. }) => send(self.transport.as_mut(), &Message::Welcome { seed: self.seed })?,
+                Ok(Message::Quit) => return Err(String::from("netplay: the other player has left")),
+                Ok(_) | Err(_) => {},
+            }
+        }
+        if self.last_heard.elapsed() > TIMEOUT {
+            return Err(String::from("netplay: lost the other player"));
+        }
+        Ok(())
+    }
+
+    /// Run the frame `frame' and save the state before it.
+    fn step(&mut self, c: &mut CPU, insns_per_frame: u32) {
+        let f = self.frame as usize;
+        let remote = match self.remote.get(f) {
+            Some(k) => *k,
+            None => self.remote.last().cloned().unwrap_or(0),
+        };
+        self.guesses.push_back((remote, c.save_state()));
+        c.set_keys(key_array(self.local[f] | remote));
+        c.seed_rng(self.seed ^ self.frame.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        c.run_frame(insns_per_frame);
+        self.frame += 1;
+        if self.frame.is_multiple_of(self.hash_interval) {
+            self.hashes.push_back((self.frame, state_hash(c)));
+        }
+    }
+
+    /// Run again the frames since the first wrong guess, if
+    /// any, then forget the guesses which were confirmed.
+    fn roll_back(&mut self, c: &mut CPU, insns_per_frame: u32) -> Result<(), String> {
+        let known = self.frame.min(self.remote.len() as u64);
+        if known <= self.confirmed {
+            return Ok(());
+        }
+        let first_wrong = (self.confirmed..known)
+                            .find(|f| self.guesses[(f - self.confirmed) as usize].0 != self.remote[*f as usize]);
+        if let Some(f) = first_wrong {
+            let n = (f - self.confirmed) as usize;
+            c.load_state(&self.guesses[n].1)?;
+            self.guesses.truncate(n);
+            self.hashes.retain(|(frame, _)| *frame <= f);
+            let end = self.frame;
+            self.frame = f;
+            while self.frame < end {
+                self.step(c, insns_per_frame);
+            }
+            self.rollbacks += 1;
+        }
+        self.guesses.drain(.. (known - self.confirmed) as usize);
+        self.confirmed = known;
+        Ok(())
+    }
+
+    /// Compare the hash from the other player with ours for
+    /// the same frame, once confirmed.
+    fn check_hashes(&mut self) -> Result<(), String> {
+        while let Some(&(frame, hash)) = self.hashes.front() {
+            if frame > self.confirmed {
+                break;
+            }
+            self.hashes.pop_front();
+            self.last_hash = Some((frame, hash));
+            self.kept.push_back((frame, hash));
+            if self.kept.len() > HASHES_KEPT {
+                self.kept.pop_front();
+            }
+        }
+        if let Some((frame, hash)) = self.remote_hash {
+            if let Some(ours) = self.kept.iter().find(|(f, _)| *f == frame) {
+                if ours.1 != hash {
+                    return Err(format!("netplay: the games have diverged at frame {}", frame));
+                }
+                self.remote_hash = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send our keys not yet acknowledged.
+    fn send_input(&mut self) -> Result<(), String> {
+        let msg = Message::Input {
+            ack: self.remote.len() as u64,
+            start: self.acked,
+            keys: self.local[self.acked as usize ..].iter().take(MAX_KEYS_SENT).cloned().collect(),
+            hash: self.last_hash,
+        };
+        send(self.transport.as_mut(), &msg)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.transport.send(&Message::Quit.encode());
+    }
+}
+
+#[cfg(test)]
+#[path="./netplay_test.rs"]
+mod netplay_test;
@@ -0,0 +1,289 @@
+
+use super::*;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::{Arc, Barrier};
+use cpu::PC_START;
+use font::{self, FontStyle};
+
+const IPF: u32 = 10;
+
+/// A CPU with PONG2, which uses random numbers, loaded.
+fn pong2() -> CPU {
+    let mut c = CPU::new();
+    c.load_font(FontStyle::Standard.data(), font::DEFAULT_FONT_BASE);
+    c.load_bytes(include_bytes!("../roms/PONG2"), PC_START);
+    c
+}
+
+type Queue = Rc<RefCell<VecDeque<(u64, Vec<u8>)>>>;
+
+/// Delivers messages `latency' ticks of `clock' after they are
+/// sent.
+struct Loopback {
+    out: Queue,
+    inbox: Queue,
+    clock: Rc<Cell<u64>>,
+    latency: u64,
+}
+
+impl Transport for Loopback {
+    fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        self.out.borrow_mut().push_back((self.clock.get() + self.latency, msg.to_vec()));
+        Ok(())
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut inbox = self.inbox.borrow_mut();
+        if inbox.front().is_some_and(|(t, _)| *t <= self.clock.get()) {
+            Ok(inbox.pop_front().map(|(_, msg)| msg))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Two sessions talking through a loopback.
+fn pair(latency: u64, delay: u32) -> (Session, Session, Rc<Cell<u64>>) {
+    let clock = Rc::new(Cell::new(0));
+    let (a, b) = (Queue::default(), Queue::default());
+    let ta = Loopback { out: a.clone(), inbox: b.clone(), clock: clock.clone(), latency };
+    let tb = Loopback { out: b, inbox: a, clock: clock.clone(), latency };
+    (Session::new(Box::new(ta), 42, delay), Session::new(Box::new(tb), 42, delay), clock)
+}
+
+/// The keys of player 1 (1 and 4) and player 2 (C and D) at
+/// tick `t', which change often until `until'.
+fn player_keys(player: usize, t: u64, until: u64) -> [bool; NUM_KEYS] {
+    let (period, up, down) = if player == 1 { (7, 0x1, 0x4) } else { (11, 0xc, 0xd) };
+    let mut keys = [false; NUM_KEYS];
+    if t < until {
+        match (t / period) % 3 {
+            0 => keys[up] = true,
+            2 => keys[down] = true,
+            _ => {},
+        }
+    }
+    keys
+}
+
+/// Run both players until they have run `frames' frames,
+/// returning their CPUs.
+fn play(a: &mut Session, b: &mut Session, clock: &Cell<u64>, frames: u64) -> (CPU, CPU) {
+    let (mut ca, mut cb) = (pong2(), pong2());
+    let until = frames - 2 * MAX_ROLLBACK;
+    while a.frame < frames || b.frame < frames {
+        let t = clock.get();
+        if a.frame < frames {
+            a.run_frame(&mut ca, player_keys(1, t, until), IPF).unwrap();
+        }
+        if b.frame < frames {
+            b.run_frame(&mut cb, player_keys(2, t, until), IPF).unwrap();
+        }
+        clock.set(t + 1);
+        assert!(t < 10 * frames, "no progress");
+    }
+    (ca, cb)
+}
+
+/// Run the game with both players' keys, as they were
+/// applied, without netplay.
+fn reference(a: &Session, b: &Session, frames: u64) -> CPU {
+    let mut c = pong2();
+    for f in 0..frames {
+        c.set_keys(key_array(a.local[f as usize] | b.local[f as usize]));
+        c.seed_rng(42 ^ f.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        c.run_frame(IPF);
+    }
+    c
+}
+
+#[test]
+fn test_messages() {
+    let messages = vec![
+        Message::Hello { version: PROTOCOL_VERSION, setup: 0x0123_4567_89ab_cdef },
+        Message::Welcome { seed: 7 },
+        Message::Reject(String::from("a different game")),
+        Message::Input { ack: 3, start: 1, keys: vec![0x8001, 0, 2], hash: None },
+        Message::Input { ack: 0, start: 0, keys: vec![], hash: Some((60, 99)) },
+        Message::Quit,
+    ];
+    for msg in messages {
+        assert_eq!(Message::decode(&msg.encode()), Ok(msg));
+    }
+    assert!(Message::decode(b"").is_err());
+    assert!(Message::decode(b"X").is_err());
+    assert!(Message::decode(b"W\0\0").is_err());
+    assert!(Message::decode(b"Q\0").is_err());
+}
+
+#[test]
+fn test_role() {
+    assert_eq!("listen:7000".parse(), Ok(Role::Listen(7000)));
+    assert_eq!("connect:10.0.0.2:7000".parse(), Ok(Role::Connect(String::from("10.0.0.2:7000"))));
+    assert_eq!("connect:[::1]:7000".parse(), Ok(Role::Connect(String::from("[::1]:7000"))));
+    assert!("listen:x".parse::<Role>().is_err());
+    assert!("connect:7000".parse::<Role>().is_err());
+    assert!("7000".parse::<Role>().is_err());
+}
+
+#[test]
+fn test_rollback() {
+    let (mut a, mut b, clock) = pair(3, 1);
+    a.hash_interval = 1;
+    b.hash_interval = 1;
+    let (ca, cb) = play(&mut a, &mut b, &clock, 300);
+    assert!(a.rollbacks > 0 && b.rollbacks > 0);
+    let c = reference(&a, &b, 300);
+    assert!(ca.save_state() == c.save_state());
+    assert!(cb.save_state() == c.save_state());
+}
+
+#[test]
+fn test_wait_for_other_player() {
+    let (mut a, _b, _clock) = pair(0, 2);
+    let mut c = pong2();
+    for _ in 0..MAX_ROLLBACK {
+        assert!(a.run_frame(&mut c, [false; NUM_KEYS], IPF).unwrap());
+    }
+    assert!(!a.run_frame(&mut c, [false; NUM_KEYS], IPF).unwrap());
+}
+
+#[test]
+fn test_high_latency() {
+    // The players wait for each other half of the time.
+    let (mut a, mut b, clock) = pair(MAX_ROLLBACK * 2, 2);
+    let (ca, cb) = play(&mut a, &mut b, &clock, 200);
+    let c = reference(&a, &b, 200);
+    assert!(ca.save_state() == c.save_state());
+    assert!(cb.save_state() == c.save_state());
+}
+
+#[test]
+fn test_desync() {
+    let (mut a, mut b, clock) = pair(2, 2);
+    a.hash_interval = 10;
+    b.hash_interval = 10;
+    let (mut ca, mut cb) = (pong2(), pong2());
+    cb.mem_mut()[0xfff] ^= 1;
+    let mut error = None;
+    for t in 0..100 {
+        clock.set(t);
+        let keys = [false; NUM_KEYS];
+        if let Err(e) = a.run_frame(&mut ca, keys, IPF).and_then(|_| b.run_frame(&mut cb, keys, IPF)) {
+            error = Some(e);
+            break;
+        }
+    }
+    assert!(error.unwrap().contains("diverged at frame 10"));
+}
+
+#[test]
+fn test_quit() {
+    let (mut a, b, _clock) = pair(0, 2);
+    drop(b);
+    let e = a.run_frame(&mut pong2(), [false; NUM_KEYS], IPF).err().unwrap();
+    assert!(e.contains("has left"));
+}
+
+#[test]
+fn test_bogus_input() {
+    let (mut a, mut b, clock) = pair(0, 2);
+    let (mut ca, mut cb) = (pong2(), pong2());
+    for t in 0..3 {
+        clock.set(t);
+        a.run_frame(&mut ca, [false; NUM_KEYS], IPF).unwrap();
+        b.run_frame(&mut cb, [false; NUM_KEYS], IPF).unwrap();
+    }
+    // Acknowledges frames not run yet, and leaves a gap.
+    let msgs = [
+        Message::Input { ack: u64::MAX, start: u64::MAX, keys: vec![1, 2], hash: None },
+        Message::Input { ack: 1000, start: 1000, keys: vec![1], hash: None },
+    ];
+    for msg in &msgs {
+        b.transport.send(&msg.encode()).unwrap();
+    }
+    let remote = a.remote.len();
+    clock.set(3);
+    a.run_frame(&mut ca, [false; NUM_KEYS], IPF).unwrap();
+    assert!(a.acked <= a.local.len() as u64);
+    assert!(a.remote.len() <= remote + 1);
+}
+
+#[test]
+fn test_tcp_full_socket() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let mut sender = tcp_transport(stream).unwrap();
+    let mut receiver = tcp_transport(listener.accept().unwrap().0).unwrap();
+    // Send until the socket is full and part of a message waits.
+    let msg = |n: u32| { let mut m = n.to_be_bytes().to_vec(); m.resize(999, n as u8); m };
+    let mut sent = 0;
+    while sender.out.is_empty() {
+        sender.send(&msg(sent)).unwrap();
+        sent += 1;
+    }
+    let start = Instant::now();
+    let mut received = 0;
+    while received < sent {
+        assert!(start.elapsed() < Duration::from_secs(10), "messages lost");
+        sender.recv().unwrap();
+        while let Some(m) = receiver.recv().unwrap() {
+            assert_eq!(m, msg(received));
+            received += 1;
+        }
+    }
+}
+
+/// Play over the network on localhost, without keys.
+fn play_on_localhost(protocol: Protocol) {
+    const FRAMES: u64 = 120;
+    let setup = setup_hash(&pong2(), IPF);
+    let listener = Listener::bind(protocol, 0).unwrap();
+    let addr = format!("127.0.0.1:{}", listener.port().unwrap());
+    let barrier = Arc::new(Barrier::new(2));
+    let run = |session: &mut Session| {
+        let mut c = pong2();
+        while session.frame < FRAMES {
+            if !session.run_frame(&mut c, [false; NUM_KEYS], IPF).unwrap() {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        state_hash(&c)
+    };
+    let joiner = {
+        let barrier = barrier.clone();
+        thread::spawn(move || {
+            let mut session = connect(protocol, &addr, setup, 2).unwrap();
+            let hash = run(&mut session);
+            barrier.wait();
+            hash
+        })
+    };
+    let mut session = listener.accept(setup, 2).unwrap();
+    let hash = run(&mut session);
+    barrier.wait();
+    assert_eq!(joiner.join().unwrap(), hash);
+}
+
+#[test]
+fn test_udp() {
+    play_on_localhost(Protocol::Udp);
+}
+
+#[test]
+fn test_tcp() {
+    play_on_localhost(Protocol::Tcp);
+}
+
+#[test]
+fn test_different_game() {
+    let listener = Listener::bind(Protocol::Udp, 0).unwrap();
+    let addr = format!("127.0.0.1:{}", listener.port().unwrap());
+    // The listener turns down the player, and waits for another.
+    thread::spawn(move || {
+        let _ = listener.accept(1, 2);
+    });
+    let e = connect(Protocol::Udp, &addr, 2, 2).err().unwrap();
+    assert!(e.contains("a different game"));
+}
@@ -0,0 +1,157 @@
+// osd.rs
+
+//! On-screen display: short messages drawn over the game
+//! (eg: "PAUSED", "SPEED 15") using a tiny built-in font.
+
+/// Width of a character, in OSD pixels.
+pub const GLYPH_WIDTH: usize = 3;
+
+/// Height of a character, in OSD pixels.
+pub const GLYPH_HEIGHT: usize = 5;
+
+/// Horizontal distance between the left edges of two
+/// adjacent characters: the glyph plus one blank column.
+pub const GLYPH_ADVANCE: usize = GLYPH_WIDTH + 1;
+
+/// Number of frames (at 60 per second) for which a
+/// message stays on the screen.
+const MESSAGE_FRAMES: u32 = 90;
+
+/// A 3x5 font. Each glyph is 5 rows, top to bottom; bit 2
+/// of a row is its leftmost pixel.
+const GLYPHS: &[(char, [u8; GLYPH_HEIGHT])] = &[
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b010, 0b101, 0b101, 0b101, 0b010]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+];
+
+/// Shown for characters missing from the font.
+const UNKNOWN_GLYPH: [u8; GLYPH_HEIGHT] = [0b111, 0b001, 0b010, 0b000, 0b010];
+
+/// Return the glyph for `c'. Lower case letters are
+/// shown in upper case.
+pub fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    let c = c.to_ascii_uppercase();
+    GLYPHS.iter()
+        .find(|g| g.0 == c)
+        .map_or(UNKNOWN_GLYPH, |g| g.1)
+}
+
+/// Width of `text' in OSD pixels.
+pub fn text_width(text: &str) -> usize {
+    let n = text.chars().count();
+    if n == 0 { 0 } else { n * GLYPH_ADVANCE - 1 }
+}
+
+/// Return the (x, y) coordinates of the lit pixels of `text',
+/// relative to the top left corner of its first character.
+pub fn text_pixels(text: &str) -> Vec<(usize, usize)> {
+    let mut pixels = Vec::new();
+    for (n, c) in text.chars().enumerate() {
+        let g = glyph(c);
+        for (y, row) in g.iter().enumerate() {
+            for x in 0..GLYPH_WIDTH {
+                if (row >> (GLYPH_WIDTH - 1 - x)) & 1 == 1 {
+                    pixels.push((n * GLYPH_ADVANCE + x, y));
+                }
+            }
+        }
+    }
+    pixels
+}
+
+/// The text to be shown on the screen: a status line which
+/// stays until it is changed (eg: "PAUSED") and a message
+/// which disappears after a while.
+pub struct Osd {
+    status: Option<String>,
+    message: Option<String>,
+    /// Frames left before the message disappears.
+    message_frames: u32,
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Osd { status: None, message: None, message_frames: 0 }
+    }
+
+    /// Show `text' for a short while.
+    pub fn show_message(&mut self, text: &str) {
+        self.message = Some(text.to_uppercase());
+        self.message_frames = MESSAGE_FRAMES;
+    }
+
+    /// Show `text' until the status is changed again;
+    /// None removes the status line.
+    pub fn set_status(&mut self, text: Option<&str>) {
+        self.status = text.map(|t| t.to_uppercase());
+    }
+
+    /// Advance by one frame, removing the message if its
+    /// time is up.
+    pub fn tick(&mut self) {
+        if self.message_frames > 0 {
+            self.message_frames -= 1;
+            if self.message_frames == 0 {
+                self.message = None;
+            }
+        }
+    }
+
+    /// The lines of text to be shown, top to bottom.
+    pub fn lines(&self) -> Vec<&str> {
+        self.status.iter().chain(self.message.iter())
+            .map(|s| s.as_str())
+            .collect()
+    }
+}
+
+impl Default for Osd {
+    fn default() -> Self {
+        Osd::new()
+    }
+}
+
+#[cfg(test)]
+#[path="./osd_test.rs"]
+mod osd_test;
@@ -0,0 +1,41 @@
+
+use super::*;
+
+#[test]
+fn test_glyph() {
+    assert_eq!(glyph('a'), glyph('A'));
+    assert_eq!(glyph('~'), UNKNOWN_GLYPH);
+}
+
+#[test]
+fn test_text_pixels() {
+    assert_eq!(text_width(""), 0);
+    assert_eq!(text_width("ab"), 7);
+
+    // "-" is a single row of 3 pixels in the middle.
+    let pixels = text_pixels("1-");
+    assert!(pixels.contains(&(4, 2)));
+    assert!(pixels.contains(&(5, 2)));
+    assert!(pixels.contains(&(6, 2)));
+    assert!(!pixels.contains(&(5, 1)));
+    // The "1" has 8 lit pixels.
+    assert_eq!(pixels.len(), 8 + 3);
+}
+
+#[test]
+fn test_osd_lines() {
+    let mut osd = Osd::new();
+    assert!(osd.lines().is_empty());
+
+    osd.set_status(Some("Paused"));
+    osd.show_message("reset");
+    assert_eq!(osd.lines(), vec!["PAUSED", "RESET"]);
+
+    for _ in 0..MESSAGE_FRAMES {
+        osd.tick();
+    }
+    assert_eq!(osd.lines(), vec!["PAUSED"]);
+
+    osd.set_status(None);
+    assert!(osd.lines().is_empty());
+}
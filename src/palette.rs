@@ -0,0 +1,144 @@
+// palette.rs
+
+use std::io::prelude::*;
+use std::fs::File;
+
+/// A color, as (red, green, blue) components.
+pub type Rgb = (u8, u8, u8);
+
+/// The built-in themes. Entry 0 of each palette is the
+/// background (pixel OFF) color and entry 1 the foreground
+/// (pixel ON) color. The remaining entries are used by
+/// display modes having more than one bit plane.
+const THEMES: &[(&str, &[Rgb])] = &[
+    ("default", &[(0, 0, 0), (250, 250, 250), (170, 170, 170), (85, 85, 85)]),
+    ("amber", &[(26, 16, 0), (255, 176, 0), (204, 112, 0), (102, 56, 0)]),
+    ("green", &[(0, 20, 0), (51, 255, 51), (0, 170, 34), (0, 85, 17)]),
+    ("lcd", &[(155, 188, 15), (15, 56, 15), (48, 98, 48), (139, 172, 15)]),
+    ("high-contrast", &[(0, 0, 0), (255, 255, 255), (255, 255, 0), (0, 255, 255)]),
+];
+
+/// Maps the value of a pixel to the color used for
+/// displaying it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    colors: Vec<Rgb>,
+}
+
+/// Parse a color written as 6 hex digits, with or without
+/// a leading '#'. Eg: "#ffb000".
+pub fn parse_color(s: &str) -> Result<Rgb, String> {
+    let digits = s.trim().trim_start_matches('#');
+    if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid color: {}", s));
+    }
+    let component = |n: usize| u8::from_str_radix(&digits[n .. n + 2], 16).unwrap();
+    Ok((component(0), component(2), component(4)))
+}
+
+/// Names of the built-in themes, in the order in which
+/// they are cycled through.
+pub fn theme_names() -> Vec<&'static str> {
+    THEMES.iter().map(|t| t.0).collect()
+}
+
+impl Palette {
+    /// Create a palette from a list of colors. A palette
+    /// needs at least two colors: background and foreground.
+    pub fn new(colors: Vec<Rgb>) -> Result<Palette, String> {
+        if colors.len() < 2 {
+            return Err(String::from("a palette needs at least 2 colors"));
+        }
+        Ok(Palette { colors })
+    }
+
+    /// Return the built-in theme called `name'.
+    pub fn theme(name: &str) -> Option<Palette> {
+        THEMES.iter()
+            .find(|t| t.0 == name)
+            .map(|t| Palette { colors: t.1.to_vec() })
+    }
+
+    /// Return the built-in theme following this palette, and
+    /// its name. If this palette is not a built-in theme, the
+    /// first theme is returned.
+    pub fn next_theme(&self) -> (&'static str, Palette) {
+        let current = THEMES.iter().position(|t| t.1 == &self.colors[..]);
+        let next = current.map_or(0, |i| (i + 1) % THEMES.len());
+        (THEMES[next].0, Palette::theme(THEMES[next].0).unwrap())
+    }
+
+    /// Parse the contents of a palette file. The file has
+    /// one color per line, in the format accepted by
+    /// `parse_color'. Blank lines and lines starting
+    /// with "//" are ignored.
+    pub fn parse(text: &str) -> Result<Palette, String> {
+        let mut colors = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            colors.push(parse_color(line)?);
+        }
+        Palette::new(colors)
+    }
+
+    /// Load a palette from a file. See `parse' for the format.
+    pub fn from_file(filename: &str) -> Result<Palette, String> {
+        let mut text = String::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("{}: {}", filename, e))?;
+        Palette::parse(&text).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    /// Return the color for pixel value `val'. Values beyond
+    /// the end of the palette get the last color.
+    pub fn color(&self, val: u8) -> Rgb {
+        let index = usize::from(val);
+        if index < self.colors.len() {
+            self.colors[index]
+        } else {
+            self.colors[self.colors.len() - 1]
+        }
+    }
+
+    /// Replace the color for pixel value `val'. The palette
+    /// grows if needed, new entries copying the last color.
+    pub fn set_color(&mut self, val: u8, color: Rgb) {
+        let index = usize::from(val);
+        while self.colors.len() <= index {
+            let last = self.colors[self.colors.len() - 1];
+            self.colors.push(last);
+        }
+        self.colors[index] = color;
+    }
+
+    /// Return a color between the background (level 0) and
+    /// the foreground (level 255). Used for pixels which are
+    /// fading in or out.
+    pub fn shade(&self, level: u8) -> Rgb {
+        let (bg, fg) = (self.colors[0], self.colors[1]);
+        let mix = |b: u8, f: u8| {
+            let (b, f) = (i32::from(b), i32::from(f));
+            (b + (f - b) * i32::from(level) / 255) as u8
+        };
+        (mix(bg.0, fg.0), mix(bg.1, fg.1), mix(bg.2, fg.2))
+    }
+
+    /// The background (pixel OFF) color.
+    pub fn background(&self) -> Rgb {
+        self.colors[0]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::theme("default").unwrap()
+    }
+}
+
+#[cfg(test)]
+#[path="./palette_test.rs"]
+mod palette_test;
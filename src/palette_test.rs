@@ -0,0 +1,67 @@
+
+use super::*;
+
+#[test]
+fn test_parse_color() {
+    assert_eq!(parse_color("#ffb000"), Ok((0xff, 0xb0, 0x00)));
+    assert_eq!(parse_color("10A0fF"), Ok((0x10, 0xa0, 0xff)));
+    assert!(parse_color("#fff").is_err());
+    assert!(parse_color("#gg0000").is_err());
+}
+
+#[test]
+fn test_themes() {
+    for name in theme_names() {
+        let p = Palette::theme(name).unwrap();
+        assert!(p.colors.len() >= 2);
+    }
+    assert!(Palette::theme("no-such-theme").is_none());
+}
+
+#[test]
+fn test_next_theme() {
+    let names = theme_names();
+    let (name, p) = Palette::theme(names[0]).unwrap().next_theme();
+    assert_eq!(name, names[1]);
+    assert_eq!(Some(p), Palette::theme(names[1]));
+    let last = Palette::theme(names[names.len() - 1]).unwrap();
+    assert_eq!(last.next_theme().0, names[0]);
+    let custom = Palette::new(vec![(1, 2, 3), (4, 5, 6)]).unwrap();
+    assert_eq!(custom.next_theme().0, names[0]);
+}
+
+#[test]
+fn test_parse_palette() {
+    let text = "// a test palette\n\
+                #000000\n\
+                \n\
+                ffffff\n\
+                #ff0000\n";
+    let p = Palette::parse(text).unwrap();
+    assert_eq!(p.color(0), (0, 0, 0));
+    assert_eq!(p.color(1), (255, 255, 255));
+    assert_eq!(p.color(2), (255, 0, 0));
+    // Values beyond the end get the last color.
+    assert_eq!(p.color(3), (255, 0, 0));
+
+    assert!(Palette::parse("#000000\n").is_err());
+    assert!(Palette::parse("#000000\nwhite\n").is_err());
+}
+
+#[test]
+fn test_set_color() {
+    let mut p = Palette::new(vec![(0, 0, 0), (1, 1, 1)]).unwrap();
+    p.set_color(0, (9, 9, 9));
+    assert_eq!(p.background(), (9, 9, 9));
+    p.set_color(3, (3, 3, 3));
+    assert_eq!(p.color(2), (1, 1, 1));
+    assert_eq!(p.color(3), (3, 3, 3));
+}
+
+#[test]
+fn test_shade() {
+    let p = Palette::new(vec![(0, 100, 200), (200, 100, 0)]).unwrap();
+    assert_eq!(p.shade(0), (0, 100, 200));
+    assert_eq!(p.shade(255), (200, 100, 0));
+    assert_eq!(p.shade(51), (40, 100, 160));
+}
@@ -0,0 +1,215 @@
+// python.rs
+
+//! Python bindings, for driving the emulator from research
+//! code. Built with the `python' feature; `maturin develop'
+//! (see pyproject.toml) installs the module. For example:
+//!
+//! ```text
+//! import chip8_emu
+//! m = chip8_emu.CPU(quirks="vip", ipf=15)
+//! m.load_rom(open("PONG", "rb").read())
+//! m.set_key(0x1, True)
+//! m.run_frames(60)
+//! m.framebuffer()   # numpy uint8 array, 32 rows of 64
+//! ```
+//!
+//! There is no window and no sound: the caller decides what
+//! to do with the display.
+
+use numpy::ndarray::Array2;
+use numpy::{IntoPyArray, PyArray2};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use cpu::{self, CPU, MEM_SIZE, NUM_KEYS, NUM_REGS};
+use font::{self, FontStyle};
+use framebuffer::{WIDTH, HEIGHT};
+use quirks::Quirks;
+
+/// A CHIP-8 machine: the CPU, with the font and the game it
+/// was last loaded with.
+#[pyclass(name = "CPU", unsendable)]
+pub struct Machine {
+    cpu: CPU,
+    font: FontStyle,
+    game: Vec<u8>,
+    /// Instructions run per frame by `run_frames'.
+    #[pyo3(get, set)]
+    ipf: u32,
+}
+
+fn value_error(e: String) -> PyErr {
+    PyValueError::new_err(e)
+}
+
+/// Check that `len' bytes starting at `addr' are in memory.
+fn check_range(addr: usize, len: usize) -> PyResult<()> {
+    if addr.checked_add(len).is_some_and(|end| end <= MEM_SIZE) {
+        Ok(())
+    } else {
+        Err(value_error(format!("memory range out of bounds: {:#x}+{}", addr, len)))
+    }
+}
+
+impl Machine {
+    /// Reset the CPU and load the font and the game.
+    fn load(&mut self) {
+        self.cpu.reset();
+        self.cpu.load_font(self.font.data(), font::DEFAULT_FONT_BASE);
+        self.cpu.load_bytes(&self.game, cpu::PC_START);
+    }
+}
+
+#[pymethods]
+impl Machine {
+    /// Create a machine with the given quirks (as for
+    /// --quirks), speed and built-in font. Memory is empty
+    /// until a ROM is loaded.
+    #[new]
+    #[pyo3(signature = (quirks = "none", ipf = 10, font = "standard"))]
+    fn new(quirks: &str, ipf: u32, font: &str) -> PyResult<Machine> {
+        let mut cpu = CPU::new();
+        cpu.set_quirks(Quirks::parse(quirks).map_err(value_error)?);
+        let font = font.parse().map_err(value_error)?;
+        let mut m = Machine { cpu, font, game: Vec::new(), ipf };
+        m.load();
+        Ok(m)
+    }
+
+    /// Load a ROM image and start it afresh.
+    fn load_rom(&mut self, rom: &[u8]) -> PyResult<()> {
+        if rom.is_empty() {
+            return Err(value_error(String::from("ROM is empty")));
+        }
+        check_range(cpu::PC_START, rom.len())?;
+        self.game = rom.to_vec();
+        self.load();
+        Ok(())
+    }
+
+    /// Start the loaded ROM afresh.
+    fn reset(&mut self) {
+        self.load();
+    }
+
+    /// Execute `n' instructions; the timers do not run.
+    #[pyo3(signature = (n = 1))]
+    fn step(&mut self, n: u32) {
+        for _ in 0..n {
+            self.cpu.execute_insn();
+        }
+    }
+
+    /// Run `n' frames of `ipf' instructions and a timer tick.
+    #[pyo3(signature = (n = 1))]
+    fn run_frames(&mut self, n: u32) {
+        for _ in 0..n {
+            self.cpu.run_frame(self.ipf);
+        }
+    }
+
+    /// Set the state of the 16 keys; true if pressed.
+    fn set_keys(&mut self, keys: Vec<bool>) -> PyResult<()> {
+        if keys.len() != NUM_KEYS {
+            return Err(value_error(format!("expected {} keys, got {}", NUM_KEYS, keys.len())));
+        }
+        let mut k = [false; NUM_KEYS];
+        k.copy_from_slice(&keys);
+        self.cpu.set_keys(k);
+        Ok(())
+    }
+
+    fn set_key(&mut self, key: usize, pressed: bool) -> PyResult<()> {
+        if key >= NUM_KEYS {
+            return Err(value_error(format!("invalid key: {}", key)));
+        }
+        let mut keys = self.cpu.keys();
+        keys[key] = pressed;
+        self.cpu.set_keys(keys);
+        Ok(())
+    }
+
+    #[getter]
+    fn keys(&self) -> Vec<bool> {
+        self.cpu.keys().to_vec()
+    }
+
+    #[getter]
+    fn pc(&self) -> usize {
+        self.cpu.pc()
+    }
+
+    #[getter]
+    fn i(&self) -> usize {
+        self.cpu.i()
+    }
+
+    /// Registers V0 to VF.
+    #[getter]
+    fn v(&self) -> Vec<u8> {
+        (0..NUM_REGS).map(|n| self.cpu.v(n)).collect()
+    }
+
+    #[getter]
+    fn delay(&self) -> u8 {
+        self.cpu.timers().0
+    }
+
+    #[getter]
+    fn sound(&self) -> u8 {
+        self.cpu.timers().1
+    }
+
+    /// The fault which stopped the CPU, if any.
+    #[getter]
+    fn fault(&self) -> Option<String> {
+        self.cpu.fault().map(|f| f.to_string())
+    }
+
+    /// Return `n' bytes of memory starting at `addr'.
+    fn read<'py>(&self, py: Python<'py>, addr: usize, n: usize) -> PyResult<Bound<'py, PyBytes>> {
+        check_range(addr, n)?;
+        Ok(PyBytes::new(py, &self.cpu.mem()[addr .. addr + n]))
+    }
+
+    fn write(&mut self, addr: usize, data: &[u8]) -> PyResult<()> {
+        check_range(addr, data.len())?;
+        self.cpu.mem_mut()[addr .. addr + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// All of memory.
+    #[getter]
+    fn memory<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, self.cpu.mem())
+    }
+
+    /// The display as a HEIGHT x WIDTH array of pixel values
+    /// (0 is off).
+    fn framebuffer<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<u8>> {
+        let pixels = self.cpu.display().rows().flat_map(|row| row.iter().cloned()).collect();
+        Array2::from_shape_vec((HEIGHT, WIDTH), pixels).unwrap().into_pyarray(py)
+    }
+
+    /// Return the state of the machine (see `load_state').
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.cpu.save_state())
+    }
+
+    /// Restore a state returned by `save_state'. The quirks,
+    /// speed and ROM are not part of it.
+    fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+        self.cpu.load_state(state).map_err(value_error)
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "chip8_emu")]
+fn python_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Machine>()?;
+    m.add("WIDTH", WIDTH)?;
+    m.add("HEIGHT", HEIGHT)?;
+    m.add("NUM_KEYS", NUM_KEYS)?;
+    Ok(())
+}
@@ -0,0 +1,95 @@
+// quirks.rs
+
+//! Behaviours which differ between CHIP-8 interpreters.
+//! Games written for one interpreter often misbehave on
+//! another, so these can be chosen per game.
+
+/// Each flag selects the behaviour of the original COSMAC VIP
+/// interpreter (or, for `jump_uses_vx', SUPER-CHIP) over the
+/// one this emulator has always had. All flags off is the
+/// default.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quirks {
+    /// 8xy6 and 8xyE shift v[y] (instead of v[x]) and store
+    /// the result in v[x].
+    pub shift_uses_vy: bool,
+    /// Fx55 and Fx65 leave I pointing just past the last
+    /// location stored or loaded.
+    pub load_store_increments_i: bool,
+    /// 8xy1, 8xy2 and 8xy3 set v[f] to 0.
+    pub logic_resets_vf: bool,
+    /// Bnnn jumps to nnn + v[x], x being the high nibble
+    /// of nnn.
+    pub jump_uses_vx: bool,
+    /// Sprites are clipped at the edges of the display
+    /// rather than wrapped around.
+    pub clip_sprites: bool,
+}
+
+/// Names of the flags, in the order of the fields of Quirks.
+const FLAG_NAMES: [&str; 5] = [
+    "shift-vy",
+    "load-store-i",
+    "logic-vf",
+    "jump-vx",
+    "clip",
+];
+
+impl Quirks {
+    /// The behaviour of the COSMAC VIP interpreter.
+    pub fn vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            logic_resets_vf: true,
+            jump_uses_vx: false,
+            clip_sprites: true,
+        }
+    }
+
+    /// The behaviour of SUPER-CHIP 1.1.
+    pub fn schip() -> Quirks {
+        Quirks {
+            jump_uses_vx: true,
+            clip_sprites: true,
+            ..Quirks::default()
+        }
+    }
+
+    fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "shift-vy" => Some(&mut self.shift_uses_vy),
+            "load-store-i" => Some(&mut self.load_store_increments_i),
+            "logic-vf" => Some(&mut self.logic_resets_vf),
+            "jump-vx" => Some(&mut self.jump_uses_vx),
+            "clip" => Some(&mut self.clip_sprites),
+            _ => None,
+        }
+    }
+
+    /// Parse a comma separated list of preset names (none,
+    /// vip, schip) and flag names, eg: "vip, jump-vx". A
+    /// preset replaces all the flags; a flag name turns that
+    /// flag on.
+    pub fn parse(text: &str) -> Result<Quirks, String> {
+        let mut quirks = Quirks::default();
+        for name in text.split(',').map(|n| n.trim().to_lowercase()) {
+            match name.as_str() {
+                "" => {},
+                "none" => quirks = Quirks::default(),
+                "vip" => quirks = Quirks::vip(),
+                "schip" => quirks = Quirks::schip(),
+                _ => {
+                    *quirks.flag_mut(&name)
+                        .ok_or_else(|| format!("unknown quirk: {} (expected one of: none, vip, schip, {})",
+                                               name, FLAG_NAMES.join(", ")))? = true;
+                },
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+#[cfg(test)]
+#[path="./quirks_test.rs"]
+mod quirks_test;
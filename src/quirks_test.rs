@@ -0,0 +1,17 @@
+
+use super::*;
+
+#[test]
+fn test_parse() {
+    assert_eq!(Quirks::parse(""), Ok(Quirks::default()));
+    assert_eq!(Quirks::parse("VIP"), Ok(Quirks::vip()));
+
+    let q = Quirks::parse("schip, shift-vy").unwrap();
+    assert!(q.jump_uses_vx && q.clip_sprites && q.shift_uses_vy);
+    assert!(!q.load_store_increments_i);
+
+    // A preset replaces the flags given before it.
+    assert_eq!(Quirks::parse("clip, none"), Ok(Quirks::default()));
+
+    assert!(Quirks::parse("vip, fast").is_err());
+}
@@ -0,0 +1,313 @@
+// record.rs
+
+//! Recording gameplay, one image per 60 Hz frame: either as
+//! an animated GIF, or as a Y4M video stream with a WAV track
+//! of the beeper next to it. Like screenshots, recordings are
+//! made from the framebuffer and the palette.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use gif;
+
+use chip8::FRAMES_PER_SECOND;
+use framebuffer::{Framebuffer, WIDTH, HEIGHT};
+use palette::Palette;
+use screenshot;
+use sound::{self, Beeper, SAMPLE_RATE};
+
+/// Number of palette entries in a GIF. Pixel values beyond
+/// the last entry get its color.
+const GIF_COLORS: usize = 4;
+
+/// Most GIF viewers show frames which last less than 2/100
+/// second for much longer, so such frames are not written.
+const GIF_MIN_DELAY: u64 = 2;
+
+/// Return the time, in 1/100 second, at which frame number
+/// `n' starts. Rounded, so that errors do not add up.
+fn centiseconds(n: u64) -> u64 {
+    (n * 100 + u64::from(FRAMES_PER_SECOND) / 2) / u64::from(FRAMES_PER_SECOND)
+}
+
+/// Writes frames as an animated GIF which loops forever.
+/// Runs of identical frames are stored as one frame.
+pub struct GifWriter<W: Write> {
+    encoder: gif::Encoder<W>,
+    scale: usize,
+    /// Size of the frames: the display, scaled.
+    width: u16,
+    height: u16,
+    /// Number of frames added so far.
+    frames: u64,
+    /// The frame waiting to be written, as pixels and palette,
+    /// and the number of the frame where it starts. It is
+    /// written once a different frame comes along.
+    pending: Option<(Vec<u8>, Vec<u8>, u64)>,
+}
+
+impl<W: Write> GifWriter<W> {
+    pub fn new(w: W, palette: &Palette, scale: usize) -> Result<GifWriter<W>, String> {
+        let size = |n: usize| n.checked_mul(scale).and_then(|n| u16::try_from(n).ok())
+                    .ok_or_else(|| format!("scale {} is too large for a GIF", scale));
+        let (width, height) = (size(WIDTH)?, size(HEIGHT)?);
+        let mut encoder = gif::Encoder::new(w, width, height, &gif_palette(palette))
+                            .map_err(|e| e.to_string())?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| e.to_string())?;
+        Ok(GifWriter { encoder, scale, width, height, frames: 0, pending: None })
+    }
+
+    pub fn add_frame(&mut self, fb: &Framebuffer, palette: &Palette) -> Result<(), String> {
+        let pixels = gif_pixels(fb, self.scale);
+        let colors = gif_palette(palette);
+        let n = self.frames;
+        self.frames += 1;
+        match self.pending.take() {
+            Some((p, c, start)) => {
+                if p == pixels && c == colors {
+                    self.pending = Some((p, c, start));
+                } else if centiseconds(n) - centiseconds(start) < GIF_MIN_DELAY {
+                    // Too short to be shown: replace it.
+                    self.pending = Some((pixels, colors, start));
+                } else {
+                    self.write_frame(&p, &c, start, n)?;
+                    self.pending = Some((pixels, colors, n));
+                }
+            },
+            None => self.pending = Some((pixels, colors, n)),
+        }
+        Ok(())
+    }
+
+    /// Write the frame shown from frame number `start' to `end'.
+    fn write_frame(&mut self, pixels: &[u8], colors: &[u8], start: u64, end: u64)
+        -> Result<(), String> {
+        let mut delay = centiseconds(end) - centiseconds(start);
+        // Very long frames are written several times over.
+        while delay > 0 {
+            let d = delay.min(u64::from(u16::MAX));
+            let mut frame = gif::Frame::from_palette_pixels(self.width, self.height, pixels, colors, None);
+            frame.delay = d as u16;
+            self.encoder.write_frame(&frame).map_err(|e| e.to_string())?;
+            delay -= d;
+        }
+        Ok(())
+    }
+
+    /// Write the last frame and the end of the file.
+    pub fn finish(mut self) -> Result<W, String> {
+        if let Some((p, c, start)) = self.pending.take() {
+            let end = self.frames.max(start + 1);
+            self.write_frame(&p, &c, start, end)?;
+        }
+        self.encoder.into_inner().map_err(|e| e.to_string())
+    }
+}
+
+fn gif_palette(palette: &Palette) -> Vec<u8> {
+    (0 .. GIF_COLORS as u8).flat_map(|val| {
+        let (r, g, b) = palette.color(val);
+        vec![r, g, b]
+    }).collect()
+}
+
+/// Return the palette index of every pixel, each CHIP-8 pixel
+/// being drawn as a `scale' x `scale' square.
+fn gif_pixels(fb: &Framebuffer, scale: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(WIDTH * HEIGHT * scale * scale);
+    for row in fb.rows() {
+        let line: Vec<u8> = row.iter()
+                                .flat_map(|val| vec![(*val).min(GIF_COLORS as u8 - 1); scale])
+                                .collect();
+        for _ in 0..scale {
+            pixels.extend_from_slice(&line);
+        }
+    }
+    pixels
+}
+
+/// Writes frames as an uncompressed YUV4MPEG2 stream at
+/// 60 frames per second, with no chroma subsampling.
+pub struct Y4mWriter<W: Write> {
+    w: W,
+    scale: usize,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(mut w: W, scale: usize) -> Result<Y4mWriter<W>, String> {
+        writeln!(w, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                 WIDTH * scale, HEIGHT * scale, FRAMES_PER_SECOND)
+            .map_err(|e| e.to_string())?;
+        Ok(Y4mWriter { w, scale })
+    }
+
+    pub fn add_frame(&mut self, fb: &Framebuffer, palette: &Palette) -> Result<(), String> {
+        let rgb = screenshot::rgb_pixels(fb, palette, self.scale);
+        let ycbcr: Vec<(u8, u8, u8)> = rgb.chunks(3).map(|p| rgb_to_ycbcr(p[0], p[1], p[2])).collect();
+        let mut data = Vec::with_capacity(6 + rgb.len());
+        data.extend_from_slice(b"FRAME\n");
+        data.extend(ycbcr.iter().map(|p| p.0));
+        data.extend(ycbcr.iter().map(|p| p.1));
+        data.extend(ycbcr.iter().map(|p| p.2));
+        self.w.write_all(&data).map_err(|e| e.to_string())
+    }
+
+    pub fn finish(mut self) -> Result<W, String> {
+        self.w.flush().map_err(|e| e.to_string())?;
+        Ok(self.w)
+    }
+}
+
+/// Convert a color to Y'CbCr (ITU-R BT.601, studio range).
+pub fn rgb_to_ycbcr(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+    let (r, g, b) = (i32::from(r), i32::from(g), i32::from(b));
+    let y = 16 + ((66 * r + 129 * g + 25 * b + 128) >> 8);
+    let cb = 128 + ((-38 * r - 74 * g + 112 * b + 128) >> 8);
+    let cr = 128 + ((112 * r - 94 * g - 18 * b + 128) >> 8);
+    (y as u8, cb as u8, cr as u8)
+}
+
+/// Writes the beeper as a 16 bit mono WAV file: a square
+/// wave while the sound timer runs, silence otherwise.
+pub struct WavWriter<W: Write + Seek> {
+    w: W,
+    beeper: Beeper,
+    /// Number of samples written so far.
+    samples: u64,
+}
+
+/// Size of the WAV header.
+const WAV_HEADER_SIZE: u32 = 44;
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut w: W) -> Result<WavWriter<W>, String> {
+        // The sizes are filled in by `finish'.
+        WavWriter::write_header(&mut w, 0).map_err(|e| e.to_string())?;
+        Ok(WavWriter { w, beeper: Beeper::new(), samples: 0 })
+    }
+
+    fn write_header(w: &mut W, data_size: u32) -> ::std::io::Result<()> {
+        let (channels, bits) = (1u16, 16u16);
+        let block_align = channels * bits / 8;
+        w.write_all(b"RIFF")?;
+        w.write_all(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        w.write_all(b"WAVEfmt ")?;
+        w.write_all(&16u32.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?;
+        w.write_all(&channels.to_le_bytes())?;
+        w.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        w.write_all(&(SAMPLE_RATE * u32::from(block_align)).to_le_bytes())?;
+        w.write_all(&block_align.to_le_bytes())?;
+        w.write_all(&bits.to_le_bytes())?;
+        w.write_all(b"data")?;
+        w.write_all(&data_size.to_le_bytes())
+    }
+
+    /// Add the sound of one frame.
+    pub fn add_frame(&mut self, beeping: bool) -> Result<(), String> {
+        let samples = self.beeper.samples(beeping, sound::SAMPLES_PER_FRAME);
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes().to_vec()).collect();
+        self.samples += samples.len() as u64;
+        self.w.write_all(&data).map_err(|e| e.to_string())
+    }
+
+    /// Fill in the sizes in the header.
+    pub fn finish(mut self) -> Result<W, String> {
+        let data_size = (self.samples * 2) as u32;
+        self.w.seek(SeekFrom::Start(0))
+            .and_then(|_| WavWriter::write_header(&mut self.w, data_size))
+            .and_then(|_| self.w.flush())
+            .map_err(|e| e.to_string())?;
+        Ok(self.w)
+    }
+}
+
+enum Output {
+    Gif(GifWriter<BufWriter<File>>),
+    Y4m(Y4mWriter<BufWriter<File>>, WavWriter<BufWriter<File>>),
+}
+
+/// A recording in progress.
+pub struct Recorder {
+    output: Output,
+    /// Names of the files being written.
+    names: Vec<String>,
+}
+
+impl Recorder {
+    /// Start recording to `filename'. A name ending in ".gif"
+    /// gives an animated GIF, one ending in ".y4m" a Y4M video
+    /// plus a WAV file with the same name but ending in ".wav".
+    /// The images are `scale' times the size of the display.
+    pub fn create(filename: &str, palette: &Palette, scale: usize) -> Result<Recorder, String> {
+        let create = |name: &str| {
+            File::create(name).map(BufWriter::new).map_err(|e| format!("{}: {}", name, e))
+        };
+        let extension = Path::new(filename).extension()
+                            .map(|e| e.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("gif") => {
+                let gif = GifWriter::new(create(filename)?, palette, scale)
+                            .map_err(|e| format!("{}: {}", filename, e))?;
+                Ok(Recorder { output: Output::Gif(gif), names: vec![String::from(filename)] })
+            },
+            Some("y4m") => {
+                let wav_name = Path::new(filename).with_extension("wav").to_string_lossy().into_owned();
+                let video = Y4mWriter::new(create(filename)?, scale)
+                                .map_err(|e| format!("{}: {}", filename, e))?;
+                let audio = WavWriter::new(create(&wav_name)?)
+                                .map_err(|e| format!("{}: {}", wav_name, e))?;
+                Ok(Recorder { output: Output::Y4m(video, audio),
+                              names: vec![String::from(filename), wav_name] })
+            },
+            _ => Err(format!("{}: unknown video format (expected .gif or .y4m)", filename)),
+        }
+    }
+
+    /// Names of the files being written.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Add a frame: the display, shown with `palette', and the
+    /// state of the beeper.
+    pub fn add_frame(&mut self, fb: &Framebuffer, palette: &Palette, beeping: bool)
+        -> Result<(), String> {
+        let names = &self.names;
+        match self.output {
+            Output::Gif(ref mut gif) => gif.add_frame(fb, palette)
+                                            .map_err(|e| format!("{}: {}", names[0], e)),
+            Output::Y4m(ref mut video, ref mut audio) => {
+                video.add_frame(fb, palette).map_err(|e| format!("{}: {}", names[0], e))?;
+                audio.add_frame(beeping).map_err(|e| format!("{}: {}", names[1], e))
+            },
+        }
+    }
+
+    /// Complete the files. Returns their names.
+    pub fn finish(self) -> Result<Vec<String>, String> {
+        let names = self.names;
+        let flush = |w: BufWriter<File>, name: &str| {
+            w.into_inner().map(|_| ()).map_err(|e| format!("{}: {}", name, e.error()))
+        };
+        match self.output {
+            Output::Gif(gif) => {
+                let w = gif.finish().map_err(|e| format!("{}: {}", names[0], e))?;
+                flush(w, &names[0])?;
+            },
+            Output::Y4m(video, audio) => {
+                let w = video.finish().map_err(|e| format!("{}: {}", names[0], e))?;
+                flush(w, &names[0])?;
+                let w = audio.finish().map_err(|e| format!("{}: {}", names[1], e))?;
+                flush(w, &names[1])?;
+            },
+        }
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+#[path="./record_test.rs"]
+mod record_test;
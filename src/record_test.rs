@@ -0,0 +1,134 @@
+
+use super::*;
+use std::io::Cursor;
+use sound::BEEP_AMPLITUDE;
+
+fn amber() -> Palette {
+    Palette::theme("amber").unwrap()
+}
+
+/// A display with only pixel (n, 0) turned on.
+fn dot(n: usize) -> Framebuffer {
+    let mut fb = Framebuffer::new();
+    fb.set(n, 0, 1);
+    fb
+}
+
+/// Return the delay of every frame of a GIF.
+fn gif_delays(data: &[u8]) -> Vec<u16> {
+    let mut decoder = gif::DecodeOptions::new().read_info(data).unwrap();
+    let mut delays = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        assert_eq!((frame.width, frame.height), (WIDTH as u16 * 2, HEIGHT as u16 * 2));
+        delays.push(frame.delay);
+    }
+    delays
+}
+
+#[test]
+fn test_centiseconds() {
+    assert_eq!(centiseconds(0), 0);
+    assert_eq!(centiseconds(1), 2);
+    assert_eq!(centiseconds(2), 3);
+    assert_eq!(centiseconds(60), 100);
+}
+
+#[test]
+fn test_gif_deduplicates_frames() {
+    let mut gif = GifWriter::new(Vec::new(), &amber(), 2).unwrap();
+    for _ in 0..3 {
+        gif.add_frame(&dot(0), &amber()).unwrap();
+    }
+    gif.add_frame(&dot(1), &amber()).unwrap();
+    let data = gif.finish().unwrap();
+    // Frames 0 - 2, then frame 3.
+    assert_eq!(gif_delays(&data), vec![5, 2]);
+}
+
+#[test]
+fn test_gif_drops_short_frames() {
+    let mut gif = GifWriter::new(Vec::new(), &amber(), 2).unwrap();
+    for n in 0..4 {
+        gif.add_frame(&dot(n), &amber()).unwrap();
+    }
+    let data = gif.finish().unwrap();
+    // Frame 1 would only last 1/100 second.
+    let delays = gif_delays(&data);
+    assert_eq!(delays, vec![2, 3, 2]);
+    assert_eq!(delays.iter().map(|d| u64::from(*d)).sum::<u64>(), centiseconds(4));
+}
+
+#[test]
+fn test_gif_palette_change() {
+    let mut gif = GifWriter::new(Vec::new(), &amber(), 2).unwrap();
+    gif.add_frame(&dot(0), &amber()).unwrap();
+    gif.add_frame(&dot(0), &amber()).unwrap();
+    gif.add_frame(&dot(0), &Palette::theme("green").unwrap()).unwrap();
+    let data = gif.finish().unwrap();
+    assert_eq!(gif_delays(&data).len(), 2);
+}
+
+#[test]
+fn test_gif_scale_too_large() {
+    // GIF sizes are 16 bits: 64 * 1024 does not fit.
+    assert!(GifWriter::new(Vec::new(), &amber(), 1023).is_ok());
+    assert!(GifWriter::new(Vec::new(), &amber(), 1024).is_err());
+    assert!(GifWriter::new(Vec::new(), &amber(), usize::MAX).is_err());
+}
+
+#[test]
+fn test_rgb_to_ycbcr() {
+    assert_eq!(rgb_to_ycbcr(0, 0, 0), (16, 128, 128));
+    assert_eq!(rgb_to_ycbcr(255, 255, 255), (235, 128, 128));
+    let (_, cb, cr) = rgb_to_ycbcr(255, 0, 0);
+    assert!(cb < 128 && cr > 128);
+}
+
+#[test]
+fn test_y4m() {
+    let mut video = Y4mWriter::new(Vec::new(), 2).unwrap();
+    video.add_frame(&dot(0), &amber()).unwrap();
+    video.add_frame(&dot(1), &amber()).unwrap();
+    let data = video.finish().unwrap();
+
+    let header = b"YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\n";
+    assert!(data.starts_with(header));
+    let frame_size = 6 + WIDTH * HEIGHT * 4 * 3;
+    assert_eq!(data.len(), header.len() + 2 * frame_size);
+    let frame = &data[header.len() .. header.len() + frame_size];
+    assert!(frame.starts_with(b"FRAME\n"));
+    // Luma of the lit pixel and of the one next to it.
+    let (fg, bg) = (amber().color(1), amber().background());
+    assert_eq!(frame[6], rgb_to_ycbcr(fg.0, fg.1, fg.2).0);
+    assert_eq!(frame[6 + 2], rgb_to_ycbcr(bg.0, bg.1, bg.2).0);
+}
+
+#[test]
+fn test_wav() {
+    let mut audio = WavWriter::new(Cursor::new(Vec::new())).unwrap();
+    audio.add_frame(true).unwrap();
+    audio.add_frame(false).unwrap();
+    let data = audio.finish().unwrap().into_inner();
+
+    let samples = sound::SAMPLES_PER_FRAME;
+    assert_eq!(data.len(), WAV_HEADER_SIZE as usize + 2 * samples * 2);
+    assert_eq!(&data[0..4], b"RIFF");
+    assert_eq!(&data[8..16], b"WAVEfmt ");
+    let size_at = |n: usize| u32::from_le_bytes([data[n], data[n + 1], data[n + 2], data[n + 3]]);
+    assert_eq!(size_at(4) as usize, data.len() - 8);
+    assert_eq!(size_at(40) as usize, samples * 4);
+
+    let sample = |n: usize| {
+        let at = WAV_HEADER_SIZE as usize + n * 2;
+        i16::from_le_bytes([data[at], data[at + 1]])
+    };
+    assert!((0..samples).all(|n| sample(n).abs() == BEEP_AMPLITUDE));
+    assert!((0..samples).any(|n| sample(n) < 0));
+    assert!((samples .. 2 * samples).all(|n| sample(n) == 0));
+}
+
+#[test]
+fn test_unknown_format() {
+    assert!(Recorder::create("clip.avi", &amber(), 1).is_err());
+    assert!(Recorder::create("clip", &amber(), 1).is_err());
+}
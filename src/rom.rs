@@ -0,0 +1,162 @@
+// rom.rs
+
+//! Sanity checks on ROM images, done before they are loaded
+//! into memory.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+use cpu;
+
+/// The machines CHIP-8 programs are written for. Each one
+/// adds instructions to the one before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Platform, String> {
+        match s {
+            "chip8" => Ok(Platform::Chip8),
+            "schip" => Ok(Platform::SuperChip),
+            "xochip" => Ok(Platform::XoChip),
+            _ => Err(format!("unknown platform: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::SuperChip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Return the first platform which has the instruction `op',
+/// or None if no platform has it.
+pub fn opcode_platform(op: u16) -> Option<Platform> {
+    let (x, n, nn) = ((op >> 8) & 0xf, op & 0xf, op & 0xff);
+    match op >> 12 {
+        0x0 => match op {
+            0x0000 => None,
+            0x00e0 | 0x00ee => Some(Platform::Chip8),
+            0x00fb ..= 0x00ff => Some(Platform::SuperChip),
+            _ if op & 0xfff0 == 0x00c0 => Some(Platform::SuperChip),
+            _ if op & 0xfff0 == 0x00d0 => Some(Platform::XoChip),
+            // 0nnn calls a machine language routine.
+            _ => Some(Platform::Chip8),
+        },
+        0x1 | 0x2 | 0x3 | 0x4 | 0x6 | 0x7 | 0xa | 0xb | 0xc => Some(Platform::Chip8),
+        0x5 => match n {
+            0x0 => Some(Platform::Chip8),
+            0x2 | 0x3 => Some(Platform::XoChip),
+            _ => None,
+        },
+        0x8 => match n {
+            0x0 ..= 0x7 | 0xe => Some(Platform::Chip8),
+            _ => None,
+        },
+        0x9 if n == 0 => Some(Platform::Chip8),
+        0xd if n == 0 => Some(Platform::SuperChip),
+        0xd => Some(Platform::Chip8),
+        0xe => match nn {
+            0x9e | 0xa1 => Some(Platform::Chip8),
+            _ => None,
+        },
+        0xf => match nn {
+            0x07 | 0x0a | 0x15 | 0x18 | 0x1e | 0x29 | 0x33 | 0x55 | 0x65 => Some(Platform::Chip8),
+            0x30 | 0x75 | 0x85 => Some(Platform::SuperChip),
+            0x00 if x == 0 => Some(Platform::XoChip),
+            0x01 | 0x3a => Some(Platform::XoChip),
+            0x02 if x == 0 => Some(Platform::XoChip),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Check a ROM image which is to be loaded at `offset' and
+/// run on `platform'.
+///
+/// Images which are empty or do not fit in memory are
+/// rejected. Otherwise a (possibly empty) list of warnings
+/// is returned, for images which load but look suspicious.
+pub fn check(rom: &[u8], offset: usize, platform: Platform) -> Result<Vec<String>, String> {
+    if rom.is_empty() {
+        return Err(String::from("ROM is empty"));
+    }
+    let room = cpu::MEM_SIZE.checked_sub(offset)
+                .ok_or_else(|| format!("ROM address {:#05x} is beyond the end of memory", offset))?;
+    if rom.len() > room {
+        return Err(format!("ROM is {} bytes long; only {} bytes fit between {:#05x} and the end of memory",
+                           rom.len(), room, offset));
+    }
+
+    let mut warnings = Vec::new();
+    if rom.len() % 2 == 1 {
+        warnings.push(format!("ROM has an odd length ({} bytes)", rom.len()));
+    }
+    if rom.len() < 2 || opcode_platform(word(rom, 0)).is_none() {
+        warnings.push(format!("no valid instruction at {:#05x}", offset));
+    }
+
+    // Data and code are mixed in most ROMs, so only the
+    // instructions reachable from the start are looked at.
+    let newer = reachable(rom, offset).into_iter()
+                    .map(|n| (n, word(rom, n)))
+                    .find(|&(_, op)| opcode_platform(op).is_some_and(|p| p > platform));
+    if let Some((n, op)) = newer {
+        warnings.push(format!("ROM may be written for {}: found {:04x} at {:#05x}",
+                              opcode_platform(op).unwrap(), op, offset + n));
+    }
+    Ok(warnings)
+}
+
+/// Return the offsets (sorted) of the instructions which can be
+/// reached from the start of the ROM, loaded at `offset', by
+/// following jumps, calls and skips. Computed jumps (Bnnn) are
+/// not followed, nor are jumps and calls below the start of
+/// the ROM.
+fn reachable(rom: &[u8], offset: usize) -> Vec<usize> {
+    let mut seen = BTreeSet::new();
+    let mut pending = vec![0];
+    while let Some(n) = pending.pop() {
+        if n + 1 >= rom.len() || !seen.insert(n) {
+            continue;
+        }
+        let op = word(rom, n);
+        let target = usize::from(op & 0xfff).checked_sub(offset);
+        match op >> 12 {
+            _ if opcode_platform(op).is_none() => {},
+            // Return, exit (SUPER-CHIP) and computed jump.
+            _ if op == 0x00ee || op == 0x00fd => {},
+            0xb => {},
+            0x1 => pending.extend(target),
+            0x2 => { pending.extend(target); pending.push(n + 2); },
+            0x3 | 0x4 | 0x5 | 0x9 | 0xe => { pending.push(n + 2); pending.push(n + 4); },
+            // F000 nnnn (XO-CHIP) is 4 bytes long.
+            0xf if op == 0xf000 => pending.push(n + 4),
+            _ => pending.push(n + 2),
+        }
+    }
+    seen.into_iter().collect()
+}
+
+/// Return the big-endian 16 bit word at rom[n].
+fn word(rom: &[u8], n: usize) -> u16 {
+    (u16::from(rom[n]) << 8) | u16::from(rom[n + 1])
+}
+
+#[cfg(test)]
+#[path="./rom_test.rs"]
+mod rom_test;
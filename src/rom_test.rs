@@ -0,0 +1,83 @@
+
+use super::*;
+
+#[test]
+fn test_opcode_platform() {
+    assert_eq!(opcode_platform(0x00e0), Some(Platform::Chip8));
+    assert_eq!(opcode_platform(0x1234), Some(Platform::Chip8));
+    assert_eq!(opcode_platform(0xd125), Some(Platform::Chip8));
+    assert_eq!(opcode_platform(0xf165), Some(Platform::Chip8));
+    assert_eq!(opcode_platform(0x00ff), Some(Platform::SuperChip));
+    assert_eq!(opcode_platform(0x00c4), Some(Platform::SuperChip));
+    assert_eq!(opcode_platform(0xd120), Some(Platform::SuperChip));
+    assert_eq!(opcode_platform(0xf375), Some(Platform::SuperChip));
+    assert_eq!(opcode_platform(0x5122), Some(Platform::XoChip));
+    assert_eq!(opcode_platform(0xf000), Some(Platform::XoChip));
+    assert_eq!(opcode_platform(0x0000), None);
+    assert_eq!(opcode_platform(0x8128), None);
+    assert_eq!(opcode_platform(0xe1ff), None);
+    assert_eq!(opcode_platform(0xf1ff), None);
+}
+
+#[test]
+fn test_check_size() {
+    assert!(check(&[], cpu::PC_START, Platform::Chip8).is_err());
+    let room = cpu::MEM_SIZE - cpu::PC_START;
+    assert!(check(&vec![0x12; room], cpu::PC_START, Platform::Chip8).is_ok());
+    assert!(check(&vec![0x12; room + 1], cpu::PC_START, Platform::Chip8).is_err());
+    assert!(check(&[0x12, 0x00], cpu::MEM_SIZE + 1, Platform::Chip8).is_err());
+}
+
+#[test]
+fn test_check_warnings() {
+    // "jmp 0x200": nothing to warn about.
+    assert!(check(&[0x12, 0x00], cpu::PC_START, Platform::Chip8).unwrap().is_empty());
+
+    let w = check(&[0x12, 0x00, 0xff], cpu::PC_START, Platform::Chip8).unwrap();
+    assert_eq!(w.len(), 1);
+    assert!(w[0].contains("odd length"));
+
+    let w = check(&[0x00, 0x00, 0x12, 0x00], cpu::PC_START, Platform::Chip8).unwrap();
+    assert_eq!(w, vec!["no valid instruction at 0x200"]);
+
+    // "high; jmp 0x202" uses a SUPER-CHIP instruction.
+    let rom = [0x00, 0xff, 0x12, 0x02];
+    let w = check(&rom, cpu::PC_START, Platform::Chip8).unwrap();
+    assert_eq!(w, vec!["ROM may be written for SUPER-CHIP: found 00ff at 0x200"]);
+    assert!(check(&rom, cpu::PC_START, Platform::SuperChip).unwrap().is_empty());
+
+    // Data which is never executed is not looked at.
+    let rom = [0x12, 0x00, 0x00, 0xff];
+    assert!(check(&rom, cpu::PC_START, Platform::Chip8).unwrap().is_empty());
+}
+
+#[test]
+fn test_reachable() {
+    // 200: call 206; 202: skip if v0 == 0; 204: jmp 204;
+    // 206: ret; 208: data.
+    let rom = [0x22, 0x06, 0x30, 0x00, 0x12, 0x04, 0x00, 0xee, 0xff, 0xff];
+    assert_eq!(reachable(&rom, cpu::PC_START), vec![0, 2, 4, 6]);
+}
+
+#[test]
+fn test_reachable_below_start() {
+    // 200: call 000; 202: jmp 1ff.
+    let rom = [0x20, 0x00, 0x11, 0xff];
+    assert_eq!(reachable(&rom, cpu::PC_START), vec![0, 2]);
+    assert!(check(&rom, cpu::PC_START, Platform::Chip8).is_ok());
+}
+
+#[test]
+fn test_reachable_at_offset() {
+    // 600: jmp 604; 602: data; 604: jmp 604.
+    let rom = [0x16, 0x04, 0xff, 0xff, 0x16, 0x04];
+    assert_eq!(reachable(&rom, 0x600), vec![0, 4]);
+    assert_eq!(reachable(&rom, cpu::PC_START), vec![0]);
+}
+
+#[test]
+fn test_parse_platform() {
+    assert_eq!("schip".parse(), Ok(Platform::SuperChip));
+    assert!("chip10".parse::<Platform>().is_err());
+}
+
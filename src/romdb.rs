@@ -0,0 +1,169 @@
+// romdb.rs
+
+//! A database of known ROMs, used to pick suitable settings
+//! (quirks, speed, keys, colors) for a game automatically.
+//!
+//! Entries are keyed by the SHA-1 of the ROM image. The
+//! database is a text file: a "[<sha1>]" header starts an
+//! entry and is followed by "field = value" lines:
+//!
+//!     [b232ef880bd6060fb45fa6effed7edf0ae95670e]
+//!     title = Pong
+//!     author = Paul Vervalin
+//!     platform = chip8
+//!     quirks = vip, jump-vx
+//!     ipf = 15
+//!     key = 1 = 1, W
+//!     palette = amber
+//!
+//! "key" may be given more than once; its value is a key
+//! binding in the format accepted by `Keymap::apply_spec'.
+//! "palette" is a theme name or a list of hex colors.
+//! "platform" is chip8, schip or xochip.
+//! Blank lines and lines starting with '#' are ignored.
+
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::fs::File;
+
+use sha1::Sha1;
+
+use keymap::Keymap;
+use palette::{self, Palette};
+use quirks::Quirks;
+use rom::Platform;
+
+/// The database compiled into the emulator.
+const BUILTIN_DB: &str = include_str!("romdb.txt");
+
+/// What the database knows about a ROM.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RomInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// The machine the game was written for.
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub insns_per_frame: Option<u32>,
+    /// Key bindings, applied on top of the default keymap.
+    pub keys: Vec<String>,
+    pub palette: Option<Palette>,
+}
+
+impl RomInfo {
+    /// Set a field from a "field = value" line.
+    fn set(&mut self, field: &str, value: &str) -> Result<(), String> {
+        let text = Some(String::from(value));
+        match field {
+            "title" => self.title = text,
+            "author" => self.author = text,
+            "platform" => self.platform = Some(value.parse()?),
+            "quirks" => self.quirks = Some(Quirks::parse(value)?),
+            "ipf" => {
+                self.insns_per_frame = match value.parse() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => return Err(format!("invalid ipf: {}", value)),
+                };
+            },
+            "key" => {
+                // Check the binding now rather than when it is used.
+                Keymap::empty().apply_spec(value)?;
+                self.keys.push(String::from(value));
+            },
+            "palette" => self.palette = Some(parse_palette(value)?),
+            _ => return Err(format!("unknown field: {}", field)),
+        }
+        Ok(())
+    }
+}
+
+/// Parse a palette given as a theme name or as a list of
+/// colors separated by spaces.
+fn parse_palette(value: &str) -> Result<Palette, String> {
+    if let Some(p) = Palette::theme(value) {
+        return Ok(p);
+    }
+    let colors = value.split_whitespace()
+                    .map(palette::parse_color)
+                    .collect::<Result<Vec<_>, _>>()?;
+    Palette::new(colors)
+}
+
+/// Return the SHA-1 of `data' as 40 lower case hex digits.
+pub fn sha1_hex(data: &[u8]) -> String {
+    Sha1::from(data).digest().to_string()
+}
+
+pub struct RomDb {
+    entries: HashMap<String, RomInfo>,
+}
+
+impl RomDb {
+    pub fn empty() -> RomDb {
+        RomDb { entries: HashMap::new() }
+    }
+
+    /// The database compiled into the emulator.
+    pub fn builtin() -> RomDb {
+        let mut db = RomDb::empty();
+        db.extend(BUILTIN_DB).expect("romdb: invalid built-in database");
+        db
+    }
+
+    /// Add the entries in `text' (see the module documentation
+    /// for the format). An entry for a ROM which is already in
+    /// the database replaces the old one.
+    pub fn extend(&mut self, text: &str) -> Result<(), String> {
+        let mut current: Option<(String, RomInfo)> = None;
+        for (lineno, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |e: String| format!("line {}: {}", lineno + 1, e);
+            if line.starts_with('[') && line.ends_with(']') {
+                let hash = line[1 .. line.len() - 1].trim().to_lowercase();
+                if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(error(format!("invalid SHA-1: {}", hash)));
+                }
+                if let Some((hash, info)) = current.take() {
+                    self.entries.insert(hash, info);
+                }
+                current = Some((hash, RomInfo::default()));
+                continue;
+            }
+            let info = match current {
+                Some((_, ref mut info)) => info,
+                None => return Err(error(String::from("field outside of an entry"))),
+            };
+            let mut parts = line.splitn(2, '=');
+            let field = parts.next().unwrap().trim().to_lowercase();
+            let value = parts.next()
+                            .ok_or_else(|| error(format!("expected \"field = value\": {}", line)))?
+                            .trim();
+            info.set(&field, value).map_err(error)?;
+        }
+        if let Some((hash, info)) = current {
+            self.entries.insert(hash, info);
+        }
+        Ok(())
+    }
+
+    /// Add the entries in a database file.
+    pub fn extend_from_file(&mut self, filename: &str) -> Result<(), String> {
+        let mut text = String::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("{}: {}", filename, e))?;
+        self.extend(&text).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    /// Look up the ROM image `rom'.
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.entries.get(&sha1_hex(rom))
+    }
+}
+
+#[cfg(test)]
+#[path="./romdb_test.rs"]
+mod romdb_test;
@@ -0,0 +1,46 @@
+
+use super::*;
+
+#[test]
+fn test_sha1_hex() {
+    assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+}
+
+#[test]
+fn test_builtin() {
+    let db = RomDb::builtin();
+    let pong = db.entries.get("b232ef880bd6060fb45fa6effed7edf0ae95670e").unwrap();
+    assert_eq!(pong.title, Some(String::from("Pong")));
+    assert_eq!(pong.keys.len(), 4);
+    assert!(db.lookup(b"not a known rom").is_none());
+}
+
+#[test]
+fn test_extend() {
+    let rom = [0x12, 0x00];
+    let text = format!("# local additions\n\
+                        [{}]\n\
+                        title = Loop\n\
+                        quirks = vip\n\
+                        ipf = 30\n\
+                        key = 5 = Up\n\
+                        palette = #000000 #00ff00\n", sha1_hex(&rom));
+    let mut db = RomDb::empty();
+    db.extend(&text).unwrap();
+    let info = db.lookup(&rom).unwrap();
+    assert_eq!(info.title, Some(String::from("Loop")));
+    assert_eq!(info.author, None);
+    assert_eq!(info.quirks, Some(Quirks::vip()));
+    assert_eq!(info.insns_per_frame, Some(30));
+    assert_eq!(info.keys, vec!["5 = Up"]);
+    assert_eq!(info.palette.as_ref().map(|p| p.color(1)), Some((0, 255, 0)));
+
+    // A later entry replaces an earlier one.
+    db.extend(&format!("[{}]\ntitle = Other\n", sha1_hex(&rom))).unwrap();
+    assert_eq!(db.lookup(&rom).unwrap().insns_per_frame, None);
+
+    assert!(db.extend("title = Orphan\n").is_err());
+    assert!(db.extend("[1234]\n").is_err());
+    let e = db.extend(&format!("[{}]\nipf = 0\n", sha1_hex(&rom))).unwrap_err();
+    assert!(e.starts_with("line 2:"));
+}
@@ -1,150 +1,421 @@
 // screen.rs
 
+use std::cmp;
 use std::collections::HashMap;
+use std::str::FromStr;
 
 use sdl2;
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
 //use sdl2::event::Event;
 //use sdl2::keyboard::Keycode;
 use sdl2::EventPump;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
-use sdl2::keyboard::Keycode;
+use sdl2::render::{Canvas, Texture};
+use sdl2::VideoSubsystem;
+use sdl2::video::{FullscreenType, Window};
+use sdl2::event::WindowEvent;
+use sdl2::keyboard::{Keycode, Scancode};
 use sdl2::event::Event;
 
+use framebuffer::{self, Framebuffer};
+use palette::{Palette, Rgb};
+use filter::{DisplayFilter, Filter};
+use keymap::Keymap;
+use gamepad::{self, Gamepads};
+use osd::{self, Osd};
+use chip8::Command;
+use frontend::Frontend;
+use cpu::NUM_KEYS;
+
 /// Default screen height in pixels
-pub const SCREEN_HEIGHT:u16 = 32;
+pub const SCREEN_HEIGHT:u16 = framebuffer::HEIGHT as u16;
 
 /// Default screen width in pixels
-pub const SCREEN_WIDTH:u16 = 64;
+pub const SCREEN_WIDTH:u16 = framebuffer::WIDTH as u16;
 
 pub const DEFAULT_SCALE_FACTOR: u32 = 5;
 
 static WINDOW_TITLE: &'static str = "CHIP-8 Demo!";
 
-/// CHIP-8 uses keys from 0,1,...9 and a, b, ... f.
-/// These are assigned codes from 0, 1, ... 0xf.
-lazy_static! {
-    static ref KEYCODES:HashMap<u8, Keycode> = hashmap! {
-         0x0 => Keycode::Num0,
-         0x1 => Keycode::Num1,
-         0x2 => Keycode::Num2,
-         0x3 => Keycode::Num3,
-         0x4 => Keycode::Num4,
-         0x5 => Keycode::Num5,
-         0x6 => Keycode::Num6,
-         0x7 => Keycode::Num7,
-         0x8 => Keycode::Num8,
-         0x9 => Keycode::Num9,
-         0xa => Keycode::A,
-         0xb => Keycode::B,
-         0xc => Keycode::C,
-         0xd => Keycode::D,
-         0xe => Keycode::E,
-         0xf => Keycode::F,
-    };
+/// Key which switches to the next built-in color theme.
+const CYCLE_THEME_KEY: Keycode = Keycode::F3;
+
+/// Key which turns the display filter on and off.
+const TOGGLE_FILTER_KEY: Keycode = Keycode::F4;
+
+/// Key which switches between windowed and fullscreen mode.
+const TOGGLE_FULLSCREEN_KEY: Keycode = Keycode::F11;
+
+/// Keys which control the emulator rather than the screen.
+/// Turbo is active only while its key is held down.
+const QUIT_KEY: Keycode = Keycode::Escape;
+const RESET_KEY: Keycode = Keycode::F5;
+const PAUSE_KEY: Keycode = Keycode::F6;
+const FRAME_ADVANCE_KEY: Keycode = Keycode::F7;
+const SLOW_MOTION_KEY: Keycode = Keycode::F8;
+const TURBO_KEY: Keycode = Keycode::Tab;
+const SPEED_DOWN_KEY: Keycode = Keycode::Minus;
+const SPEED_UP_KEY: Keycode = Keycode::Equals;
+const SCREENSHOT_KEY: Keycode = Keycode::F12;
+const RECORD_KEY: Keycode = Keycode::F9;
+
+/// Return the emulator command bound to hotkey `k'.
+fn hotkey_command(k: Keycode) -> Option<Command> {
+    match k {
+        QUIT_KEY => Some(Command::Quit),
+        RESET_KEY => Some(Command::Reset),
+        PAUSE_KEY => Some(Command::TogglePause),
+        FRAME_ADVANCE_KEY => Some(Command::FrameAdvance),
+        SLOW_MOTION_KEY => Some(Command::ToggleSlowMotion),
+        TURBO_KEY => Some(Command::Turbo(true)),
+        SPEED_DOWN_KEY => Some(Command::SpeedDown),
+        SPEED_UP_KEY => Some(Command::SpeedUp),
+        SCREENSHOT_KEY => Some(Command::Screenshot),
+        RECORD_KEY => Some(Command::ToggleRecording),
+        _ => None,
+    }
+}
+
+/// How the CHIP-8 display is stretched to fit the window.
+/// In both cases the aspect ratio is preserved and the unused
+/// parts of the window are filled with the background color.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scaling {
+    /// Each CHIP-8 pixel is drawn as an NxN square, N being
+    /// the largest integer which fits. Gives sharp pixels.
+    Integer,
+    /// Fill as much of the window as possible.
+    Fractional,
 }
 
-/// CHIP-8 uses only two colors: 0 for OFF and
-/// 1 for ON.
-lazy_static! {
-    pub static ref PIXEL_COLORS:[Color; 2] = 
-        [Color::RGBA(0, 0, 0, 255), 
-         Color::RGBA(250, 250, 250, 255)];
+impl FromStr for Scaling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Scaling, String> {
+        match s {
+            "integer" => Ok(Scaling::Integer),
+            "fractional" => Ok(Scaling::Fractional),
+            _ => Err(format!("unknown scaling mode: {}", s)),
+        }
+    }
+}
+
+/// Settings used when creating a `Screen'.
+pub struct Options {
+    /// Initial size of the window is SCREEN_WIDTH * scale_factor
+    /// by SCREEN_HEIGHT * scale_factor.
+    pub scale_factor: u32,
+    pub scaling: Scaling,
+    pub fullscreen: bool,
+    pub palette: Palette,
+    /// Filter applied to the framebuffer before drawing it.
+    pub filter: Option<Filter>,
+    /// Host key names are SDL scancode names or game
+    /// controller inputs (see `gamepad').
+    pub keymap: Keymap,
 }
 
+/// Return the rectangle, centered in an output area of size
+/// `width' x `height', on which the CHIP-8 display is drawn.
+pub fn letterbox(width: u32, height: u32, scaling: Scaling) -> Rect {
+    let (sw, sh) = (u32::from(SCREEN_WIDTH), u32::from(SCREEN_HEIGHT));
+    let (w, h) = match scaling {
+        Scaling::Integer => {
+            let scale = cmp::max(1, cmp::min(width / sw, height / sh));
+            (sw * scale, sh * scale)
+        },
+        Scaling::Fractional => {
+            let scale = f64::min(f64::from(width) / f64::from(sw),
+                                 f64::from(height) / f64::from(sh));
+            (cmp::max(1, (f64::from(sw) * scale).round() as u32),
+             cmp::max(1, (f64::from(sh) * scale).round() as u32))
+        },
+    };
+    Rect::new((width as i32 - w as i32) / 2, (height as i32 - h as i32) / 2, w, h)
+}
+
+/// Number of bytes used to store one pixel in the texture.
+const BYTES_PER_PIXEL: usize = 3;
+
 pub struct Screen {
-    scale_factor: u32,
     pub canvas: Canvas<Window>,
     pub events: EventPump,
-    /// `mem' is a representation of the display within the
-    /// virtual machine. If mem[i] is 1, the corresponding 
-    /// pixel on the real screen is ON, otherwise OFF.
-    ///  
-    /// Had to use this because rust-sdl2 
-    /// does not seem to provide an easy way to get the
-    /// color of a pixel.
-    mem: [u8; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize],
+    /// A streaming texture of size SCREEN_WIDTH x SCREEN_HEIGHT.
+    /// The framebuffer is uploaded to it once per frame and it
+    /// is then stretched over the window.
+    texture: Texture,
+    /// Staging buffer holding the RGB value of every pixel,
+    /// in the layout expected by `texture'.
+    pixels: Vec<u8>,
+    /// Colors used for drawing the pixels.
+    palette: Palette,
+    /// Filter applied to the framebuffer before drawing it.
+    filter: Option<DisplayFilter>,
+    scaling: Scaling,
+    /// The area of the window on which the display is drawn.
+    /// Recomputed whenever the window changes size.
+    dst: Rect,
+    /// Maps the physical keys of the keyboard to CHIP-8 keys.
+    keymap: HashMap<Scancode, u8>,
+    /// Game controllers; None if SDL could not initialize
+    /// its game controller subsystem.
+    gamepads: Option<Gamepads>,
+    /// For each of the 16 CHIP-8 keys, the number of host
+    /// keys and controller inputs mapped to it which are
+    /// being held down.
+    keys: [u32; NUM_KEYS],
+    /// Messages drawn over the display.
+    osd: Osd,
 }
 
 impl Screen {
-    pub fn new(width: u32, height: u32, scale_factor: u32) -> Screen {
+    pub fn new(options: Options) -> Screen {
         let ctxt = sdl2::init().expect("SDL2 library initialization failed.");
         let video = ctxt.video().expect("Unable to get video subsystem.");
-        let window = 
-            video.window(WINDOW_TITLE, width * scale_factor, height * scale_factor)
-            .position_centered()
-            .opengl()
-            .build()
-            .expect("Unable to get Window");
-        
-        let mut canvas = window.into_canvas().build().expect("Unable to get canvas");
+        let (width, height) = (u32::from(SCREEN_WIDTH) * options.scale_factor,
+                               u32::from(SCREEN_HEIGHT) * options.scale_factor);
+
+        // Fall back to the software renderer if the system
+        // does not support accelerated rendering.
+        let window = Screen::build_window(&video, width, height);
+        let mut canvas = match window.into_canvas().accelerated().build() {
+            Ok(canvas) => canvas,
+            Err(e) => {
+                eprintln!("warning: accelerated rendering unavailable ({}), \
+                           using software renderer", e);
+                let window = Screen::build_window(&video, width, height);
+                window.into_canvas().software().build().expect("Unable to get canvas")
+            },
+        };
+        if options.fullscreen {
+            canvas.window_mut().set_fullscreen(FullscreenType::Desktop)
+                .expect("Unable to switch to fullscreen");
+        }
 
-        canvas.set_draw_color(PIXEL_COLORS[0]);
+        canvas.set_draw_color(to_color(options.palette.background()));
         canvas.clear();
         canvas.present();
+
+        let texture = canvas.create_texture_streaming(
+                        PixelFormatEnum::RGB24, 
+                        u32::from(SCREEN_WIDTH), 
+                        u32::from(SCREEN_HEIGHT))
+                      .expect("Unable to create texture");
         
+        let gamepads = match ctxt.game_controller() {
+            Ok(subsystem) => Some(Gamepads::new(subsystem, &options.keymap)),
+            Err(e) => {
+                eprintln!("warning: game controllers unavailable: {}", e);
+                None
+            },
+        };
+
         let events = ctxt.event_pump().expect("Unable to get event pump"); 
 
-        Screen{ 
-            scale_factor: scale_factor,
-            canvas: canvas, events: events,
-            mem: [0; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize],
+        let mut scr = Screen{ 
+            canvas, events, texture,
+            pixels: vec![0; (SCREEN_WIDTH as usize) * (SCREEN_HEIGHT as usize) * BYTES_PER_PIXEL],
+            palette: options.palette,
+            filter: options.filter.map(DisplayFilter::new),
+            scaling: options.scaling,
+            dst: Rect::new(0, 0, width, height),
+            keymap: Screen::resolve_keymap(&options.keymap),
+            gamepads,
+            keys: [0; NUM_KEYS],
+            osd: Osd::new(),
+        };
+        scr.update_dst();
+        scr
+    }
+
+    /// Look up the SDL scancodes of the host keys in `keymap'.
+    fn resolve_keymap(keymap: &Keymap) -> HashMap<Scancode, u8> {
+        let mut m = HashMap::new();
+        for &(ref name, key) in keymap.bindings() {
+            if gamepad::is_pad_name(name) {
+                continue;
+            }
+            match Scancode::from_name(name) {
+                Some(code) => { m.insert(code, key); },
+                None => eprintln!("warning: keymap: unknown key name: {}", name),
+            }
         }
+        m
+    }
+
+    fn build_window(video: &VideoSubsystem, width: u32, height: u32) -> Window {
+        video.window(WINDOW_TITLE, width, height)
+            .position_centered()
+            .resizable()
+            .build()
+            .expect("Unable to get Window")
     }
 
-    pub fn draw_pixel(&mut self, x: u32, y: u32, color:Color) {
-        self.canvas.set_draw_color(color);
-        self.canvas.fill_rect(
-            Rect::new(
-                x as i32 * self.scale_factor as i32, 
-                y as i32 * self.scale_factor as i32,
-                self.scale_factor, self.scale_factor)).expect("Error in draw_point");
-        let c = if color == PIXEL_COLORS[0] { 0 } else { 1 };
-        self.mem[(y * u32::from(SCREEN_WIDTH) + x) as usize] = c;
+    /// Store the color of pixel number `index' in the
+    /// staging buffer.
+    fn put_pixel(pixels: &mut [u8], index: usize, color: Rgb) {
+        let offset = index * BYTES_PER_PIXEL;
+        pixels[offset] = color.0;
+        pixels[offset + 1] = color.1;
+        pixels[offset + 2] = color.2;
     }
 
-    /// Map an SDL Keycode to the numeric key value used
-    /// by CHIP-8.
-    fn keycode_to_keyval(k: Keycode) -> Option<u8> {
-        for (chip8_val, sdl_code) in KEYCODES.iter() {
-            if *sdl_code == k {
-                return Some(*chip8_val);
-            }
+    /// Recompute the area of the window on which the
+    /// display is drawn.
+    fn update_dst(&mut self) {
+        let (w, h) = self.canvas.output_size().expect("Unable to get window size");
+        self.dst = letterbox(w, h, self.scaling);
+    }
+
+    /// Switch between windowed and fullscreen mode.
+    fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let new_state = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        if let Err(e) = window.set_fullscreen(new_state) {
+            eprintln!("warning: unable to toggle fullscreen: {}", e);
         }
-        None
     }
 
-    /// Do a blocking read from the keyboard. Return the
-    /// associated CHIP-8 key value if a keypress is 
-    /// detected and the pressed key is valid.
-    pub fn read_key_blocking(&mut self) -> Option<u8> {
-        loop {
-            let e = self.events.wait_event();
-            if let Event::KeyDown { keycode: Some(k), ..} = e {
-                break Screen::keycode_to_keyval(k);
+    /// Draw the OSD text in the top left corner of the display.
+    fn draw_osd(&mut self) {
+        // Size of an OSD pixel, in window pixels.
+        let px = cmp::max(1, self.dst.height() / 80) as i32;
+        let (fg, bg) = (to_color(self.palette.color(1)), to_color(self.palette.background()));
+        let line_height = (osd::GLYPH_HEIGHT as i32 + 2) * px;
+        let (x0, y0) = (self.dst.x() + 2 * px, self.dst.y() + 2 * px);
+
+        for (n, line) in self.osd.lines().iter().enumerate() {
+            let y = y0 + n as i32 * line_height;
+            let width = (osd::text_width(line) as u32 + 2) * px as u32;
+            self.canvas.set_draw_color(bg);
+            let _ = self.canvas.fill_rect(Rect::new(x0 - px, y - px, width, line_height as u32));
+
+            let rects: Vec<Rect> = osd::text_pixels(line).iter()
+                .map(|&(tx, ty)| Rect::new(x0 + tx as i32 * px, y + ty as i32 * px,
+                                           px as u32, px as u32))
+                .collect();
+            self.canvas.set_draw_color(fg);
+            let _ = self.canvas.fill_rects(&rects);
+        }
+    }
+
+    /// Switch to the built-in theme following the current one.
+    fn cycle_theme(&mut self) {
+        let (name, palette) = self.palette.next_theme();
+        self.palette = palette;
+        self.show_message(&format!("theme {}", name));
+    }
+
+}
+
+impl Frontend for Screen {
+    fn present(&mut self, fb: &Framebuffer) {
+        if let Some(ref mut filter) = self.filter {
+            let levels = filter.apply(fb);
+            for (index, level) in levels.iter().enumerate() {
+                let color = self.palette.shade(*level);
+                Screen::put_pixel(&mut self.pixels, index, color);
+            }
+        } else {
+            let pixels = fb.rows().flat_map(|row| row.iter());
+            for (index, val) in pixels.enumerate() {
+                let color = self.palette.color(*val);
+                Screen::put_pixel(&mut self.pixels, index, color);
             }
         }
+        self.texture.update(None, &self.pixels, SCREEN_WIDTH as usize * BYTES_PER_PIXEL)
+            .expect("Unable to update texture");
+        
+        self.canvas.set_draw_color(to_color(self.palette.background()));
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, self.dst).expect("Unable to copy texture");
+        self.draw_osd();
+        self.canvas.present();
+        self.osd.tick();
     }
 
-    /// Do a non-blocking read from the keyboard. Return
-    /// the associated CHIP-8 key value if a keypress is
-    /// detected and the pressed key is valid.
-    pub fn read_key_noblocking(&mut self) -> Option<u8> {
-        if let Some(e) = self.events.poll_event() {
+    fn show_message(&mut self, text: &str) {
+        self.osd.show_message(text);
+    }
+
+    fn set_status(&mut self, text: Option<&str>) {
+        self.osd.set_status(text);
+    }
+
+    fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    fn keys(&self) -> [bool; NUM_KEYS] {
+        let mut keys = [false; NUM_KEYS];
+        for (k, n) in keys.iter_mut().zip(self.keys.iter()) {
+            *k = *n > 0;
+        }
+        keys
+    }
+
+    fn poll_events(&mut self) -> Vec<Command> {
+        let mut commands = Vec::new();
+        while let Some(e) = self.events.poll_event() {
+            if let Event::KeyDown { keycode: Some(k), repeat: false, ..} = e {
+                if let Some(command) = hotkey_command(k) {
+                    commands.push(command);
+                    continue;
+                }
+            }
             match e {
-                Event::KeyDown { keycode: Some(k), ..} => {
-                        Screen::keycode_to_keyval(k)
+                Event::Quit { .. } => commands.push(Command::Quit),
+                Event::KeyUp { keycode: Some(TURBO_KEY), ..} => {
+                    commands.push(Command::Turbo(false));
+                },
+                Event::KeyDown { keycode: Some(CYCLE_THEME_KEY), repeat: false, ..} => {
+                    self.cycle_theme();
+                },
+                Event::KeyDown { keycode: Some(TOGGLE_FULLSCREEN_KEY), repeat: false, ..} => {
+                    self.toggle_fullscreen();
+                    self.update_dst();
+                },
+                Event::Window { win_event: WindowEvent::Resized(..), ..} |
+                Event::Window { win_event: WindowEvent::SizeChanged(..), ..} => {
+                    self.update_dst();
+                },
+                Event::KeyDown { keycode: Some(TOGGLE_FILTER_KEY), repeat: false, ..} => {
+                    if let Some(ref mut filter) = self.filter {
+                        let enabled = !filter.is_enabled();
+                        filter.set_enabled(enabled);
+                        self.osd.show_message(if enabled { "filter on" } else { "filter off" });
+                    }
+                },
+                Event::KeyDown { scancode: Some(k), repeat: false, ..} => {
+                    if let Some(key) = self.keymap.get(&k) {
+                        self.keys[usize::from(*key)] += 1;
+                    }
+                },
+                Event::KeyUp { scancode: Some(k), ..} => {
+                    if let Some(key) = self.keymap.get(&k) {
+                        let n = &mut self.keys[usize::from(*key)];
+                        *n = n.saturating_sub(1);
+                    }
+                },
+                _ => {
+                    if let Some(ref mut g) = self.gamepads {
+                        g.handle_event(&e, &mut self.keys);
+                    }
                 },
-                _ => None,
             }
-        } else {
-            None
         }
+        commands
     }
+}
 
-    pub fn get_pixel(&mut self, x: u32, y: u32) -> u8 {
-        self.mem[(y * u32::from(SCREEN_WIDTH) + x) as usize]
-    }
- }
+/// Convert an RGB triple to an SDL color.
+fn to_color(c: Rgb) -> Color {
+    Color::RGB(c.0, c.1, c.2)
+}
+
+#[cfg(test)]
+#[path="./screen_test.rs"]
+mod screen_test;
@@ -0,0 +1,27 @@
+
+use super::*;
+
+#[test]
+fn test_letterbox_integer() {
+    // Exact multiple of the display size.
+    assert_eq!(letterbox(320, 160, Scaling::Integer), Rect::new(0, 0, 320, 160));
+    // Scale 4 fits; the rest is split evenly on both sides.
+    assert_eq!(letterbox(300, 200, Scaling::Integer), Rect::new(22, 36, 256, 128));
+    // Never smaller than one window pixel per CHIP-8 pixel.
+    assert_eq!(letterbox(32, 16, Scaling::Integer), Rect::new(-16, -8, 64, 32));
+}
+
+#[test]
+fn test_letterbox_fractional() {
+    // Limited by the width.
+    assert_eq!(letterbox(300, 200, Scaling::Fractional), Rect::new(0, 25, 300, 150));
+    // Limited by the height.
+    assert_eq!(letterbox(500, 100, Scaling::Fractional), Rect::new(150, 0, 200, 100));
+}
+
+#[test]
+fn test_parse_scaling() {
+    assert_eq!("integer".parse(), Ok(Scaling::Integer));
+    assert_eq!("fractional".parse(), Ok(Scaling::Fractional));
+    assert!("stretch".parse::<Scaling>().is_err());
+}
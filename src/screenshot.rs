@@ -0,0 +1,110 @@
+// screenshot.rs
+
+//! Saving the display as an image. Images are made from the
+//! framebuffer and the palette, not read back from the window,
+//! so they do not depend on the window size or on filters.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use png;
+
+use framebuffer::{Framebuffer, WIDTH, HEIGHT};
+use palette::Palette;
+
+/// Return the RGB value of every pixel of the display, each
+/// CHIP-8 pixel being drawn as a `scale' x `scale' square.
+pub fn rgb_pixels(fb: &Framebuffer, palette: &Palette, scale: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(WIDTH * HEIGHT * scale * scale * 3);
+    for row in fb.rows() {
+        let mut line = Vec::with_capacity(WIDTH * scale * 3);
+        for val in row {
+            let (r, g, b) = palette.color(*val);
+            for _ in 0..scale {
+                line.extend_from_slice(&[r, g, b]);
+            }
+        }
+        for _ in 0..scale {
+            pixels.extend_from_slice(&line);
+        }
+    }
+    pixels
+}
+
+/// Write the display to `w' as a PNG image, scaled by `scale'.
+pub fn write_png<W: Write>(w: W, fb: &Framebuffer, palette: &Palette, scale: usize)
+    -> Result<(), String> {
+    let (width, height) = ((WIDTH * scale) as u32, (HEIGHT * scale) as u32);
+    let mut encoder = png::Encoder::new(w, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&rgb_pixels(fb, palette, scale)).map_err(|e| e.to_string())
+}
+
+/// Return the display as an SVG image, `scale' times the
+/// size of the CHIP-8 display. The background is one
+/// rectangle; the pixels of each other color make up a path,
+/// with horizontal runs of pixels merged.
+pub fn svg(fb: &Framebuffer, palette: &Palette, scale: usize) -> String {
+    let hex = |(r, g, b): (u8, u8, u8)| format!("#{:02x}{:02x}{:02x}", r, g, b);
+    let mut paths: Vec<(u8, String)> = Vec::new();
+    for (y, row) in fb.rows().enumerate() {
+        let mut x = 0;
+        while x < row.len() {
+            let val = row[x];
+            let len = row[x..].iter().take_while(|v| **v == val).count();
+            if val != 0 {
+                let run = format!("M{} {}h{}v1h-{}z", x, y, len, len);
+                match paths.iter_mut().find(|p| p.0 == val) {
+                    Some(p) => p.1.push_str(&run),
+                    None => paths.push((val, run)),
+                }
+            }
+            x += len;
+        }
+    }
+    paths.sort();
+
+    let mut text = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+                            viewBox=\"0 0 {} {}\" shape-rendering=\"crispEdges\">\n",
+                           WIDTH * scale, HEIGHT * scale, WIDTH, HEIGHT);
+    text.push_str(&format!("<rect width=\"{}\" height=\"{}\" fill=\"{}\"/>\n",
+                           WIDTH, HEIGHT, hex(palette.background())));
+    for (val, d) in paths {
+        text.push_str(&format!("<path fill=\"{}\" d=\"{}\"/>\n", hex(palette.color(val)), d));
+    }
+    text.push_str("</svg>\n");
+    text
+}
+
+/// Save the display as "<prefix>.png" (native resolution),
+/// "<prefix>-x<scale>.png" and "<prefix>.svg". Returns the
+/// names of the files written.
+pub fn save(prefix: &str, fb: &Framebuffer, palette: &Palette, scale: usize)
+    -> Result<Vec<String>, String> {
+    let mut names = Vec::new();
+    let create = |name: &str| {
+        File::create(name).map(BufWriter::new).map_err(|e| format!("{}: {}", name, e))
+    };
+
+    let mut scales = vec![1];
+    if scale > 1 {
+        scales.push(scale);
+    }
+    for s in scales {
+        let name = if s == 1 { format!("{}.png", prefix) } else { format!("{}-x{}.png", prefix, s) };
+        write_png(create(&name)?, fb, palette, s).map_err(|e| format!("{}: {}", name, e))?;
+        names.push(name);
+    }
+
+    let name = format!("{}.svg", prefix);
+    create(&name)?.write_all(svg(fb, palette, scale).as_bytes())
+        .map_err(|e| format!("{}: {}", name, e))?;
+    names.push(name);
+    Ok(names)
+}
+
+#[cfg(test)]
+#[path="./screenshot_test.rs"]
+mod screenshot_test;
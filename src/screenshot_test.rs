@@ -0,0 +1,63 @@
+
+use super::*;
+
+fn amber() -> Palette {
+    Palette::theme("amber").unwrap()
+}
+
+#[test]
+fn test_rgb_pixels() {
+    let mut fb = Framebuffer::new();
+    fb.set(1, 0, 1);
+    let p = amber();
+    let (bg, fg) = (p.background(), p.color(1));
+
+    let pixels = rgb_pixels(&fb, &p, 1);
+    assert_eq!(pixels.len(), WIDTH * HEIGHT * 3);
+    assert_eq!(&pixels[0..3], &[bg.0, bg.1, bg.2]);
+    assert_eq!(&pixels[3..6], &[fg.0, fg.1, fg.2]);
+
+    // Pixel (1, 0) becomes the square (2, 0) - (3, 1).
+    let pixels = rgb_pixels(&fb, &p, 2);
+    let at = |x: usize, y: usize| {
+        let n = (y * WIDTH * 2 + x) * 3;
+        (pixels[n], pixels[n + 1], pixels[n + 2])
+    };
+    assert_eq!(at(1, 0), bg);
+    assert_eq!(at(2, 0), fg);
+    assert_eq!(at(3, 1), fg);
+    assert_eq!(at(4, 1), bg);
+    assert_eq!(at(2, 2), bg);
+}
+
+#[test]
+fn test_write_png() {
+    let mut fb = Framebuffer::new();
+    fb.xor_sprite(0, 0, &[0xf0]);
+    let mut data = Vec::new();
+    write_png(&mut data, &fb, &amber(), 3).unwrap();
+
+    let decoder = png::Decoder::new(&data[..]);
+    let mut reader = decoder.read_info().unwrap();
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).unwrap();
+    assert_eq!((info.width, info.height), (WIDTH as u32 * 3, HEIGHT as u32 * 3));
+    assert_eq!(info.color_type, png::ColorType::Rgb);
+    assert_eq!(&buf[..info.buffer_size()], &rgb_pixels(&fb, &amber(), 3)[..]);
+}
+
+#[test]
+fn test_svg() {
+    let mut fb = Framebuffer::new();
+    // A run of 3 pixels and a single one on the next row.
+    fb.xor_sprite(2, 0, &[0xe0, 0x80]);
+    let text = svg(&fb, &amber(), 5);
+    assert!(text.starts_with("<svg "));
+    assert!(text.contains("width=\"320\" height=\"160\" viewBox=\"0 0 64 32\""));
+    assert!(text.contains("<rect width=\"64\" height=\"32\" fill=\"#1a1000\"/>"));
+    assert!(text.contains("<path fill=\"#ffb000\" d=\"M2 0h3v1h-3zM2 1h1v1h-1z\"/>"));
+    assert!(text.trim_end().ends_with("</svg>"));
+
+    // A blank display has no paths.
+    assert!(!svg(&Framebuffer::new(), &amber(), 1).contains("<path"));
+}
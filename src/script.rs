@@ -0,0 +1,342 @@
+// script.rs
+
+//! Scripts, written in Rhai, for automating and modding runs
+//! without recompiling the emulator. A script runs once when
+//! the game starts; from then on, it is called back through
+//! the hooks it registers:
+//!
+//! ```text
+//! on_frame(|frame| ...)          after every frame
+//! on_exec(addr, |pc| ...)        before the instruction at addr
+//! on_write(addr, |addr, val| ...) after the program writes addr
+//! on_key(|key, pressed| ...)     when the player presses or
+//!                                releases a key
+//! ```
+//!
+//! and it can use these functions:
+//!
+//! ```text
+//! pc() set_pc(addr) i() set_i(addr) v(n) set_v(n, val)
+//! peek(addr) poke(addr, val) frame()
+//! press(key) release(key)
+//! message(text) overlay(text) screenshot()
+//! save_state(file) load_state(file)
+//! ```
+//!
+//! For example, a trainer keeping V5 (lives, say) at 3:
+//!
+//! ```text
+//! on_frame(|frame| set_v(5, 3));
+//! ```
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::prelude::*;
+use std::mem;
+use std::rc::Rc;
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, FuncArgs, Scope, AST, INT};
+
+use cpu::{CPU, MEM_SIZE, NUM_KEYS, NUM_REGS};
+
+/// Requests from a script which the emulator carries out
+/// after the frame, through the frontend.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Show a message for a short while.
+    Message(String),
+    /// Show a status line; None removes it.
+    Overlay(Option<String>),
+    Screenshot,
+}
+
+type Hooks<T> = Vec<(T, FnPtr)>;
+
+/// The state shared between the script and the functions it
+/// calls.
+struct Shared {
+    /// The emulator's CPU, moved here while the script runs.
+    cpu: CPU,
+    frame: u64,
+    on_frame: Hooks<()>,
+    on_exec: Hooks<usize>,
+    on_write: Hooks<usize>,
+    on_key: Hooks<()>,
+    /// Keys held down by the script, and by the player.
+    pressed: [bool; NUM_KEYS],
+    player_keys: [bool; NUM_KEYS],
+    actions: Vec<Action>,
+}
+
+impl Shared {
+    fn update_keys(&mut self) {
+        let mut keys = self.player_keys;
+        for (k, pressed) in keys.iter_mut().enumerate() {
+            *pressed |= self.pressed[k];
+        }
+        self.cpu.set_keys(keys);
+    }
+}
+
+pub struct Script {
+    /// The file name, for the error messages.
+    name: String,
+    engine: Engine,
+    ast: AST,
+    shared: Rc<RefCell<Shared>>,
+    /// The first error; the script is not run after it.
+    error: Option<String>,
+}
+
+type FnResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Check that `n' is in 0 .. `limit'.
+fn check(n: INT, limit: usize, what: &str) -> FnResult<usize> {
+    if n >= 0 && (n as u64) < limit as u64 {
+        Ok(n as usize)
+    } else {
+        Err(format!("{} out of range: {}", what, n).into())
+    }
+}
+
+impl Script {
+    /// Compile the script in `filename'.
+    pub fn load(filename: &str) -> Result<Script, String> {
+        let mut text = String::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("{}: {}", filename, e))?;
+        Script::new(filename, &text)
+    }
+
+    /// Compile `text'; `name' is used in error messages.
+    pub fn new(name: &str, text: &str) -> Result<Script, String> {
+        let shared = Rc::new(RefCell::new(Shared {
+            cpu: CPU::new(),
+            frame: 0,
+            on_frame: Vec::new(),
+            on_exec: Vec::new(),
+            on_write: Vec::new(),
+            on_key: Vec::new(),
+            pressed: [false; NUM_KEYS],
+            player_keys: [false; NUM_KEYS],
+            actions: Vec::new(),
+        }));
+        let mut engine = Engine::new();
+        register(&mut engine, &shared);
+        let ast = engine.compile(text).map_err(|e| format!("{}: {}", name, e))?;
+        Ok(Script { name: String::from(name), engine, ast, shared, error: None })
+    }
+
+    /// The first error the script ran into, if any.
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    /// Run `f' with the script, the CPU lent to it.
+    fn with_cpu<F>(&mut self, c: &mut CPU, f: F)
+        where F: FnOnce(&Script) -> FnResult<()> {
+        if self.error.is_some() {
+            return;
+        }
+        mem::swap(c, &mut self.shared.borrow_mut().cpu);
+        let result = f(self);
+        mem::swap(c, &mut self.shared.borrow_mut().cpu);
+        if let Err(e) = result {
+            self.error = Some(format!("{}: {}", self.name, e));
+            // Nothing takes the writes once the script is stopped.
+            c.log_writes(false);
+        }
+    }
+
+    fn call<A: FuncArgs + Clone>(&self, hooks: &[FnPtr], args: A) -> FnResult<()> {
+        // Hooks may return anything; it is ignored.
+        for hook in hooks {
+            let _: Dynamic = hook.call(&self.engine, &self.ast, args.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Run the top level of the script, which registers the
+    /// hooks. Called once the game is loaded.
+    pub fn start(&mut self, c: &mut CPU) {
+        self.with_cpu(c, |s| {
+            s.engine.run_ast_with_scope(&mut Scope::new(), &s.ast)
+        });
+    }
+
+    /// Set the keys of the CPU to those pressed by the player
+    /// or held by the script, calling the key hooks for the
+    /// keys the player pressed or released.
+    pub fn set_keys(&mut self, c: &mut CPU, keys: [bool; NUM_KEYS]) {
+        self.with_cpu(c, |s| {
+            let old = mem::replace(&mut s.shared.borrow_mut().player_keys, keys);
+            s.shared.borrow_mut().update_keys();
+            let hooks: Vec<FnPtr> = s.shared.borrow().on_key.iter().map(|h| h.1.clone()).collect();
+            for k in (0..NUM_KEYS).filter(|&k| old[k] != keys[k]) {
+                s.call(&hooks, (k as INT, keys[k]))?;
+            }
+            Ok(())
+        });
+    }
+
+    /// Return the hooks registered for `addr'.
+    fn hooks_at(hooks: &Hooks<usize>, addr: usize) -> Vec<FnPtr> {
+        hooks.iter().filter(|h| h.0 == addr).map(|h| h.1.clone()).collect()
+    }
+
+    /// Run one frame, like `CPU::run_frame', calling the hooks.
+    pub fn run_frame(&mut self, c: &mut CPU, insns_per_frame: u32) {
+        let (exec, write) = {
+            let shared = self.shared.borrow();
+            (!shared.on_exec.is_empty(), !shared.on_write.is_empty())
+        };
+        if self.error.is_some() || !(exec || write) {
+            c.run_frame(insns_per_frame);
+        } else {
+            c.log_writes(write);
+            for _ in 0..insns_per_frame {
+                let hooks = Script::hooks_at(&self.shared.borrow().on_exec, c.pc());
+                if !hooks.is_empty() {
+                    let pc = c.pc() as INT;
+                    self.with_cpu(c, |s| s.call(&hooks, (pc,)));
+                }
+                c.execute_insn();
+                for addr in c.take_writes() {
+                    let hooks = Script::hooks_at(&self.shared.borrow().on_write, addr);
+                    if !hooks.is_empty() {
+                        let val = INT::from(c.mem()[addr]);
+                        self.with_cpu(c, |s| s.call(&hooks, (addr as INT, val)));
+                    }
+                }
+                if c.fault().is_some() {
+                    return;
+                }
+            }
+            c.decrement_counters();
+        }
+        self.shared.borrow_mut().frame += 1;
+        let frame = self.shared.borrow().frame as INT;
+        self.with_cpu(c, |s| {
+            let hooks: Vec<FnPtr> = s.shared.borrow().on_frame.iter().map(|h| h.1.clone()).collect();
+            s.call(&hooks, (frame,))
+        });
+    }
+
+    /// Return the requests made by the script since the last
+    /// call.
+    pub fn take_actions(&mut self) -> Vec<Action> {
+        mem::take(&mut self.shared.borrow_mut().actions)
+    }
+}
+
+/// Register the functions scripts can call.
+fn register(engine: &mut Engine, shared: &Rc<RefCell<Shared>>) {
+    let s = shared.clone();
+    engine.register_fn("on_frame", move |f: FnPtr| s.borrow_mut().on_frame.push(((), f)));
+    let s = shared.clone();
+    engine.register_fn("on_exec", move |addr: INT, f: FnPtr| -> FnResult<()> {
+        let addr = check(addr, MEM_SIZE, "address")?;
+        s.borrow_mut().on_exec.push((addr, f));
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("on_write", move |addr: INT, f: FnPtr| -> FnResult<()> {
+        let addr = check(addr, MEM_SIZE, "address")?;
+        s.borrow_mut().on_write.push((addr, f));
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("on_key", move |f: FnPtr| s.borrow_mut().on_key.push(((), f)));
+
+    let s = shared.clone();
+    engine.register_fn("pc", move || s.borrow().cpu.pc() as INT);
+    let s = shared.clone();
+    engine.register_fn("set_pc", move |addr: INT| -> FnResult<()> {
+        let addr = check(addr, MEM_SIZE, "address")?;
+        s.borrow_mut().cpu.set_pc(addr);
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("i", move || s.borrow().cpu.i() as INT);
+    let s = shared.clone();
+    engine.register_fn("set_i", move |addr: INT| -> FnResult<()> {
+        let addr = check(addr, 0x10000, "address")?;
+        s.borrow_mut().cpu.set_i(addr);
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("v", move |n: INT| -> FnResult<INT> {
+        let n = check(n, NUM_REGS, "register")?;
+        Ok(INT::from(s.borrow().cpu.v(n)))
+    });
+    let s = shared.clone();
+    engine.register_fn("set_v", move |n: INT, val: INT| -> FnResult<()> {
+        let n = check(n, NUM_REGS, "register")?;
+        let val = check(val, 256, "byte")?;
+        s.borrow_mut().cpu.set_v(n, val as u8);
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("peek", move |addr: INT| -> FnResult<INT> {
+        let addr = check(addr, MEM_SIZE, "address")?;
+        Ok(INT::from(s.borrow().cpu.mem()[addr]))
+    });
+    let s = shared.clone();
+    engine.register_fn("poke", move |addr: INT, val: INT| -> FnResult<()> {
+        let addr = check(addr, MEM_SIZE, "address")?;
+        let val = check(val, 256, "byte")?;
+        s.borrow_mut().cpu.mem_mut()[addr] = val as u8;
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("frame", move || s.borrow().frame as INT);
+
+    let s = shared.clone();
+    engine.register_fn("press", move |key: INT| -> FnResult<()> {
+        let key = check(key, NUM_KEYS, "key")?;
+        let mut shared = s.borrow_mut();
+        shared.pressed[key] = true;
+        shared.update_keys();
+        Ok(())
+    });
+    let s = shared.clone();
+    engine.register_fn("release", move |key: INT| -> FnResult<()> {
+        let key = check(key, NUM_KEYS, "key")?;
+        let mut shared = s.borrow_mut();
+        shared.pressed[key] = false;
+        shared.update_keys();
+        Ok(())
+    });
+
+    let s = shared.clone();
+    engine.register_fn("message", move |text: &str| {
+        s.borrow_mut().actions.push(Action::Message(String::from(text)));
+    });
+    let s = shared.clone();
+    engine.register_fn("overlay", move |text: &str| {
+        let text = if text.is_empty() { None } else { Some(String::from(text)) };
+        s.borrow_mut().actions.push(Action::Overlay(text));
+    });
+    let s = shared.clone();
+    engine.register_fn("screenshot", move || s.borrow_mut().actions.push(Action::Screenshot));
+    let s = shared.clone();
+    engine.register_fn("save_state", move |filename: &str| -> FnResult<()> {
+        let state = s.borrow().cpu.save_state();
+        File::create(filename)
+            .and_then(|mut f| f.write_all(&state))
+            .map_err(|e| format!("{}: {}", filename, e).into())
+    });
+    let s = shared.clone();
+    engine.register_fn("load_state", move |filename: &str| -> FnResult<()> {
+        let mut state = Vec::new();
+        File::open(filename)
+            .and_then(|mut f| f.read_to_end(&mut state))
+            .map_err(|e| format!("{}: {}", filename, e))?;
+        s.borrow_mut().cpu.load_state(&state).map_err(|e| format!("{}: {}", filename, e).into())
+    });
+}
+
+#[cfg(test)]
+#[path="./script_test.rs"]
+mod script_test;
@@ -0,0 +1,125 @@
+
+use super::*;
+use std::env;
+use std::fs;
+use cpu::PC_START;
+
+/// A CPU with `code' loaded at PC_START.
+fn cpu_with(code: &[u8]) -> CPU {
+    let mut c = CPU::new();
+    c.load_bytes(code, PC_START);
+    c
+}
+
+fn started(text: &str, c: &mut CPU) -> Script {
+    let mut s = Script::new("test.rhai", text).unwrap();
+    s.start(c);
+    assert_eq!(s.error(), None);
+    s
+}
+
+#[test]
+fn test_syntax_error() {
+    let e = Script::new("test.rhai", "on_frame(|f| ").err().unwrap();
+    assert!(e.starts_with("test.rhai: "));
+}
+
+#[test]
+fn test_on_frame() {
+    // Instruction: 0x1200 (loop)
+    let mut c = cpu_with(&[0x12, 0x00]);
+    let mut s = started("on_frame(|f| set_v(5, f));", &mut c);
+    for _ in 0..3 {
+        s.run_frame(&mut c, 10);
+    }
+    assert_eq!(c.v(5), 3);
+}
+
+#[test]
+fn test_on_exec() {
+    // Instructions: 0x6001, 0x7001, 0x1202
+    let mut c = cpu_with(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02]);
+    let mut s = started("on_exec(0x202, |pc| poke(0x300, peek(0x300) + 1));", &mut c);
+    s.run_frame(&mut c, 10);
+    assert_eq!(s.error(), None);
+    assert_eq!(c.mem()[0x300], 5);
+    assert_eq!(c.v(0), 6);
+}
+
+#[test]
+fn test_on_write() {
+    // Instructions: 0x6007, 0xa300, 0xf055, 0x1204
+    let mut c = cpu_with(&[0x60, 0x07, 0xa3, 0x00, 0xf0, 0x55, 0x12, 0x04]);
+    let mut s = started("on_write(0x300, |addr, val| set_v(1, val + addr - 0x300));", &mut c);
+    s.run_frame(&mut c, 4);
+    assert_eq!(c.v(1), 7);
+}
+
+#[test]
+fn test_keys() {
+    let mut c = cpu_with(&[0x12, 0x00]);
+    let mut s = started("on_key(|k, pressed| if pressed { press(k + 1) } else { release(k + 1) });", &mut c);
+    let mut keys = [false; NUM_KEYS];
+    keys[2] = true;
+    s.set_keys(&mut c, keys);
+    assert!(c.keys()[2] && c.keys()[3]);
+    s.set_keys(&mut c, keys);
+    s.set_keys(&mut c, [false; NUM_KEYS]);
+    assert_eq!(c.keys(), [false; NUM_KEYS]);
+}
+
+#[test]
+fn test_actions() {
+    let mut c = cpu_with(&[0x12, 0x00]);
+    let mut s = started(r#"message("hi"); overlay("lives: 3"); overlay(""); screenshot();"#, &mut c);
+    assert_eq!(s.take_actions(), vec![
+        Action::Message(String::from("hi")),
+        Action::Overlay(Some(String::from("lives: 3"))),
+        Action::Overlay(None),
+        Action::Screenshot,
+    ]);
+    assert_eq!(s.take_actions(), vec![]);
+}
+
+#[test]
+fn test_runtime_error() {
+    let mut c = cpu_with(&[0x12, 0x00]);
+    let mut s = started("on_frame(|f| { poke(0x300, f); set_v(16, 0) });", &mut c);
+    s.run_frame(&mut c, 10);
+    let e = s.error().unwrap();
+    assert!(e.starts_with("test.rhai: ") && e.contains("register out of range: 16"));
+    // The script is stopped, but not the game.
+    s.run_frame(&mut c, 10);
+    assert_eq!(c.mem()[0x300], 1);
+    assert_eq!(c.pc(), PC_START);
+}
+
+#[test]
+fn test_runtime_error_in_write_hook() {
+    // Instructions: 0x6007, 0xa300, 0xf055, 0x1204
+    let mut c = cpu_with(&[0x60, 0x07, 0xa3, 0x00, 0xf0, 0x55, 0x12, 0x04]);
+    let mut s = started("on_write(0x300, |addr, val| set_v(16, val));", &mut c);
+    s.run_frame(&mut c, 10);
+    assert!(s.error().is_some());
+    // Writes are no longer logged for the stopped script.
+    s.run_frame(&mut c, 10);
+    assert!(c.take_writes().is_empty());
+}
+
+#[test]
+fn test_save_state() {
+    let path = env::temp_dir().join(format!("chip8-script-test-{}.state", std::process::id()));
+    // Instructions: 0x7001, 0x1200
+    let mut c = cpu_with(&[0x70, 0x01, 0x12, 0x00]);
+    let text = format!(r#"
+        let path = "{}";
+        on_frame(|f| if f == 1 {{ save_state(path) }} else if f == 3 {{ load_state(path) }});
+    "#, path.display());
+    let mut s = started(&text, &mut c);
+    for _ in 0..3 {
+        s.run_frame(&mut c, 10);
+    }
+    fs::remove_file(&path).unwrap();
+    assert_eq!(s.error(), None);
+    assert_eq!(c.v(0), 5);
+}
@@ -0,0 +1,61 @@
+// sound.rs
+
+//! The beeper. CHIP-8 has a single tone, which sounds while
+//! the sound timer runs; it is made here as a square wave.
+
+use cpu;
+
+/// Sample rate of the generated sound.
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// Number of samples in one frame (one timer tick).
+pub const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / cpu::TIMER_HZ) as usize;
+
+/// Pitch of the beeper.
+const BEEP_HZ: u32 = 440;
+
+pub const BEEP_AMPLITUDE: i16 = 8000;
+
+/// Makes the samples of the beeper, one batch at a time. The
+/// wave keeps its phase from one batch to the next.
+pub struct Beeper {
+    /// Number of samples made so far.
+    samples: u64,
+}
+
+impl Beeper {
+    pub fn new() -> Beeper {
+        Beeper { samples: 0 }
+    }
+
+    /// Return the next `count' samples: the tone if `beeping'
+    /// is true, silence otherwise.
+    pub fn samples(&mut self, beeping: bool, count: usize) -> Vec<i16> {
+        self.next_samples(beeping, count).collect()
+    }
+
+    /// Like `samples', without collecting them, for callers
+    /// which reuse a buffer.
+    pub fn next_samples(&mut self, beeping: bool, count: usize) -> impl Iterator<Item = i16> {
+        let start = self.samples;
+        self.samples += count as u64;
+        (start .. self.samples).map(move |n| {
+            let half_periods = n * 2 * u64::from(BEEP_HZ) / u64::from(SAMPLE_RATE);
+            match (beeping, half_periods % 2) {
+                (false, _) => 0,
+                (true, 0) => BEEP_AMPLITUDE,
+                (true, _) => -BEEP_AMPLITUDE,
+            }
+        })
+    }
+}
+
+impl Default for Beeper {
+    fn default() -> Self {
+        Beeper::new()
+    }
+}
+
+#[cfg(test)]
+#[path="./sound_test.rs"]
+mod sound_test;
@@ -0,0 +1,27 @@
+
+use super::*;
+
+#[test]
+fn test_silence() {
+    let mut b = Beeper::new();
+    assert_eq!(b.samples(false, 100), vec![0; 100]);
+}
+
+#[test]
+fn test_square_wave() {
+    let mut b = Beeper::new();
+    let samples = b.samples(true, SAMPLE_RATE as usize);
+    assert!(samples.iter().all(|s| s.abs() == BEEP_AMPLITUDE));
+    // Count the rising edges over one second.
+    let rising = samples.windows(2).filter(|w| w[0] < 0 && w[1] > 0).count();
+    assert!((BEEP_HZ as usize - 1 ..= BEEP_HZ as usize).contains(&rising));
+}
+
+#[test]
+fn test_phase_kept_across_batches() {
+    let mut whole = Beeper::new();
+    let mut parts = Beeper::new();
+    let mut samples = parts.samples(true, 300);
+    samples.extend(parts.samples(true, 435));
+    assert_eq!(samples, whole.samples(true, 735));
+}
@@ -0,0 +1,246 @@
+// stack.rs
+
+//! The call stack. Where it is kept and how deep it can get
+//! differ between interpreters, so there is a choice of models.
+
+use std::str::FromStr;
+
+use bounds::{BoundsPolicy, FaultKind};
+use cpu::MEM_SIZE;
+
+/// The COSMAC VIP keeps 24 entries in memory at 0xea0.
+pub const DEFAULT_STACK_BASE: usize = 0xea0;
+pub const DEFAULT_STACK_DEPTH: usize = 24;
+
+/// The deepest stack kept outside of memory which can be
+/// asked for; far beyond what any program needs.
+pub const MAX_ARRAY_DEPTH: usize = 4096;
+
+/// Each entry of a stack in memory is a 2 byte address,
+/// stored in big endian format.
+const ENTRY_SIZE: usize = 2;
+
+/// Entries of a stack in memory wrap around at 4K, so one running
+/// past its ends (VIP policy) is kept within this many entries.
+const MEM_ENTRIES: isize = (MEM_SIZE / ENTRY_SIZE) as isize;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StackModel {
+    /// `depth' entries in CHIP-8 memory, starting at `base'.
+    /// The first entry pushed goes at `base'. Programs can
+    /// see (and overwrite) the stack.
+    Memory { base: usize, depth: usize },
+    /// `depth' entries kept outside of CHIP-8 memory.
+    Array { depth: usize },
+    /// A stack outside of memory which grows as needed.
+    Unlimited,
+}
+
+impl StackModel {
+    /// Parse "mem[:<base>[:<depth>]]", "array[:<depth>]" or
+    /// "unlimited". The base is in hex, the depth in decimal.
+    /// Eg: "mem:0xea0:24", "array:16".
+    pub fn parse(text: &str) -> Result<StackModel, String> {
+        let parts: Vec<&str> = text.split(':').map(|p| p.trim()).collect();
+        let depth = |n: usize| -> Result<Option<usize>, String> {
+            match parts.get(n) {
+                None => Ok(None),
+                Some(d) => match d.parse() {
+                    Ok(d) if d > 0 => Ok(Some(d)),
+                    _ => Err(format!("invalid stack depth: {}", d)),
+                },
+            }
+        };
+        let model = match parts[0] {
+            "mem" if parts.len() <= 3 => {
+                let base = match parts.get(1) {
+                    None => DEFAULT_STACK_BASE,
+                    Some(b) => usize::from_str_radix(b.trim_start_matches("0x"), 16)
+                                .map_err(|_| format!("invalid stack address: {}", b))?,
+                };
+                if base >= MEM_SIZE {
+                    return Err(format!("stack address {:#05x} is beyond the end of memory", base));
+                }
+                let depth = depth(2)?.unwrap_or(DEFAULT_STACK_DEPTH);
+                let end = depth.checked_mul(ENTRY_SIZE).and_then(|n| n.checked_add(base));
+                if end.is_none_or(|end| end > MEM_SIZE) {
+                    return Err(format!("a stack of {} entries at {:#05x} does not fit in memory",
+                                       depth, base));
+                }
+                StackModel::Memory { base, depth }
+            },
+            "array" if parts.len() <= 2 => {
+                let depth = depth(1)?.unwrap_or(DEFAULT_STACK_DEPTH);
+                if depth > MAX_ARRAY_DEPTH {
+                    return Err(format!("stack depth {} is more than the maximum, {}",
+                                       depth, MAX_ARRAY_DEPTH));
+                }
+                StackModel::Array { depth }
+            },
+            "unlimited" if parts.len() == 1 => StackModel::Unlimited,
+            _ => return Err(format!("invalid stack model: {}", text)),
+        };
+        Ok(model)
+    }
+}
+
+impl FromStr for StackModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StackModel, String> {
+        StackModel::parse(s)
+    }
+}
+
+impl Default for StackModel {
+    fn default() -> Self {
+        StackModel::Memory { base: DEFAULT_STACK_BASE, depth: DEFAULT_STACK_DEPTH }
+    }
+}
+
+pub struct Stack {
+    model: StackModel,
+    /// Number of entries on the stack. Stays within 0..=depth,
+    /// except with the VIP bounds policy, which lets a stack in
+    /// memory run past either end (within MEM_ENTRIES).
+    top: isize,
+    /// The entries of stacks kept outside of memory.
+    entries: Vec<usize>,
+}
+
+impl Stack {
+    pub fn new(model: StackModel) -> Stack {
+        let entries = match model {
+            StackModel::Array { depth } => vec![0; depth],
+            _ => Vec::new(),
+        };
+        Stack { model, top: 0, entries }
+    }
+
+    pub fn model(&self) -> StackModel {
+        self.model
+    }
+
+    fn in_memory(&self) -> bool {
+        matches!(self.model, StackModel::Memory { .. })
+    }
+
+    /// Maximum number of entries, None if unlimited.
+    fn depth(&self) -> Option<isize> {
+        match self.model {
+            StackModel::Memory { depth, .. } | StackModel::Array { depth } => Some(depth as isize),
+            StackModel::Unlimited => None,
+        }
+    }
+
+    /// Address of entry number `n' of a stack in memory,
+    /// wrapping around at 4K.
+    fn entry_address(base: usize, n: isize) -> usize {
+        let addr = base as isize + n * ENTRY_SIZE as isize;
+        addr.rem_euclid(MEM_SIZE as isize) as usize
+    }
+
+    fn write(&mut self, mem: &mut [u8], n: isize, val: usize) {
+        match self.model {
+            StackModel::Memory { base, .. } => {
+                let addr = Stack::entry_address(base, n);
+                mem[addr] = ((val >> 8) & 0xff) as u8;
+                mem[(addr + 1) % MEM_SIZE] = (val & 0xff) as u8;
+            },
+            StackModel::Array { .. } => self.entries[n as usize] = val,
+            StackModel::Unlimited => {
+                self.entries.truncate(n as usize);
+                self.entries.push(val);
+            },
+        }
+    }
+
+    fn read(&self, mem: &[u8], n: isize) -> usize {
+        match self.model {
+            StackModel::Memory { base, .. } => {
+                let addr = Stack::entry_address(base, n);
+                (usize::from(mem[addr]) << 8) | usize::from(mem[(addr + 1) % MEM_SIZE])
+            },
+            _ => self.entries[n as usize],
+        }
+    }
+
+    /// Push a return address. When the stack is full, it wraps
+    /// around to the bottom (with the Wrap policy, or the VIP
+    /// policy for stacks outside memory), runs on into the memory
+    /// beyond it (VIP policy) or a fault is returned (Trap).
+    pub fn push(&mut self, mem: &mut [u8], addr: usize, policy: BoundsPolicy)
+        -> Result<(), FaultKind> {
+        if Some(self.top) == self.depth() {
+            match (policy, self.model) {
+                (BoundsPolicy::Trap, _) => return Err(FaultKind::StackOverflow),
+                (BoundsPolicy::Vip, StackModel::Memory { .. }) => {},
+                _ => self.top = 0,
+            }
+        }
+        let top = self.top;
+        self.write(mem, top, addr);
+        self.top += 1;
+        if self.top > MEM_ENTRIES && self.in_memory() {
+            self.top -= MEM_ENTRIES;
+        }
+        Ok(())
+    }
+
+    /// Pop a return address. An empty stack wraps around to the
+    /// top, reads the memory below it (VIP policy, stacks in memory
+    /// only) or returns a fault. Unlimited stacks always fault.
+    pub fn pop(&mut self, mem: &[u8], policy: BoundsPolicy) -> Result<usize, FaultKind> {
+        if self.top == 0 {
+            match (policy, self.model) {
+                (BoundsPolicy::Trap, _) | (_, StackModel::Unlimited) => {
+                    return Err(FaultKind::StackUnderflow);
+                },
+                (BoundsPolicy::Vip, StackModel::Memory { .. }) => {},
+                _ => self.top = self.depth().unwrap(),
+            }
+        }
+        self.top -= 1;
+        if self.top < -MEM_ENTRIES && self.in_memory() {
+            self.top += MEM_ENTRIES;
+        }
+        Ok(self.read(mem, self.top))
+    }
+
+    /// The number of entries on the stack, and the entries kept
+    /// outside of memory. Used for save states.
+    pub fn state(&self) -> (isize, &[usize]) {
+        (self.top, &self.entries)
+    }
+
+    /// Restore the state returned by `state', which must be
+    /// that of a stack with the same model, run with the same
+    /// bounds policy.
+    pub fn set_state(&mut self, top: isize, entries: Vec<usize>, policy: BoundsPolicy)
+        -> Result<(), String> {
+        let valid = match self.model {
+            StackModel::Memory { .. } if policy == BoundsPolicy::Vip => {
+                entries.is_empty() && (-MEM_ENTRIES ..= MEM_ENTRIES).contains(&top)
+            },
+            StackModel::Memory { depth, .. } => entries.is_empty() && (0 ..= depth as isize).contains(&top),
+            StackModel::Array { depth } => entries.len() == depth && (0 ..= depth as isize).contains(&top),
+            StackModel::Unlimited => (0 ..= entries.len() as isize).contains(&top),
+        };
+        if !valid {
+            return Err(String::from("save state does not match the stack model"));
+        }
+        self.top = top;
+        self.entries = entries;
+        Ok(())
+    }
+
+    /// The return addresses on the stack, from the oldest call
+    /// to the most recent one.
+    pub fn return_addresses(&self, mem: &[u8]) -> Vec<usize> {
+        (0 .. self.top.max(0)).map(|n| self.read(mem, n)).collect()
+    }
+}
+
+#[cfg(test)]
+#[path="./stack_test.rs"]
+mod stack_test;
@@ -0,0 +1,108 @@
+
+use super::*;
+
+#[test]
+fn test_parse() {
+    assert_eq!(StackModel::parse("mem"), Ok(StackModel::default()));
+    assert_eq!(StackModel::parse("mem:0x100:12"), Ok(StackModel::Memory { base: 0x100, depth: 12 }));
+    assert_eq!(StackModel::parse("array:16"), Ok(StackModel::Array { depth: 16 }));
+    assert_eq!(StackModel::parse("array"), Ok(StackModel::Array { depth: DEFAULT_STACK_DEPTH }));
+    assert_eq!(StackModel::parse("unlimited"), Ok(StackModel::Unlimited));
+    assert!(StackModel::parse("mem:0xffe:2").is_err());
+    assert!(StackModel::parse("array:0").is_err());
+    assert!(StackModel::parse("heap").is_err());
+}
+
+#[test]
+fn test_parse_out_of_range() {
+    assert!(StackModel::parse("mem:ffffffffffffffff:1").is_err());
+    assert!(StackModel::parse("mem:0x100:9223372036854775807").is_err());
+    assert!(StackModel::parse("mem:0x1000:1").is_err());
+    assert!(StackModel::parse("mem:0x1000").is_err());
+    assert_eq!(StackModel::parse("array:4096"), Ok(StackModel::Array { depth: MAX_ARRAY_DEPTH }));
+    assert!(StackModel::parse("array:4097").is_err());
+    assert!(StackModel::parse("array:100000000000").is_err());
+}
+
+#[test]
+fn test_memory_stack() {
+    let mut mem = [0u8; MEM_SIZE];
+    let mut s = Stack::new(StackModel::Memory { base: 0x100, depth: 2 });
+    s.push(&mut mem, 0x234, BoundsPolicy::Trap).unwrap();
+    s.push(&mut mem, 0x456, BoundsPolicy::Trap).unwrap();
+    assert_eq!(&mem[0x100 .. 0x104], &[0x02, 0x34, 0x04, 0x56]);
+    assert_eq!(s.return_addresses(&mem), vec![0x234, 0x456]);
+    assert_eq!(s.push(&mut mem, 0x678, BoundsPolicy::Trap), Err(FaultKind::StackOverflow));
+
+    // The VIP runs on into the memory past the stack.
+    s.push(&mut mem, 0x678, BoundsPolicy::Vip).unwrap();
+    assert_eq!(&mem[0x104 .. 0x106], &[0x06, 0x78]);
+    assert_eq!(s.pop(&mem, BoundsPolicy::Vip), Ok(0x678));
+    assert_eq!(s.pop(&mem, BoundsPolicy::Trap), Ok(0x456));
+    assert_eq!(s.pop(&mem, BoundsPolicy::Trap), Ok(0x234));
+    assert_eq!(s.pop(&mem, BoundsPolicy::Trap), Err(FaultKind::StackUnderflow));
+
+    // An empty stack wraps around to its top entry.
+    assert_eq!(s.pop(&mem, BoundsPolicy::Wrap), Ok(0x456));
+}
+
+#[test]
+fn test_unlimited_stack() {
+    let mut mem = [0u8; MEM_SIZE];
+    let mut s = Stack::new(StackModel::Unlimited);
+    for addr in 0..1000 {
+        s.push(&mut mem, addr, BoundsPolicy::Trap).unwrap();
+    }
+    assert_eq!(s.return_addresses(&mem).len(), 1000);
+    assert_eq!(s.pop(&mem, BoundsPolicy::Trap), Ok(999));
+    assert!(mem.iter().all(|b| *b == 0));
+
+    let mut s = Stack::new(StackModel::Unlimited);
+    assert_eq!(s.pop(&mem, BoundsPolicy::Wrap), Err(FaultKind::StackUnderflow));
+}
+
+#[test]
+fn test_set_state() {
+    let mut mem = [0u8; MEM_SIZE];
+    let mut s = Stack::new(StackModel::Unlimited);
+    s.push(&mut mem, 0x234, BoundsPolicy::Trap).unwrap();
+    let (top, entries) = s.state();
+    let entries = entries.to_vec();
+
+    let mut t = Stack::new(StackModel::Unlimited);
+    t.set_state(top, entries.clone(), BoundsPolicy::Trap).unwrap();
+    assert_eq!(t.pop(&mem, BoundsPolicy::Trap), Ok(0x234));
+
+    // Not the state of a stack of this model.
+    let mut t = Stack::new(StackModel::Memory { base: 0x100, depth: 2 });
+    assert!(t.set_state(top, entries, BoundsPolicy::Trap).is_err());
+    let mut t = Stack::new(StackModel::Unlimited);
+    assert!(t.set_state(2, vec![0x234], BoundsPolicy::Trap).is_err());
+
+    // Only the VIP policy lets a stack in memory run past its ends.
+    let mut t = Stack::new(StackModel::Memory { base: 0x100, depth: 2 });
+    assert!(t.set_state(2, vec![], BoundsPolicy::Trap).is_ok());
+    assert!(t.set_state(3, vec![], BoundsPolicy::Trap).is_err());
+    assert!(t.set_state(-1, vec![], BoundsPolicy::Wrap).is_err());
+    assert!(t.set_state(-1, vec![], BoundsPolicy::Vip).is_ok());
+    assert!(t.set_state(MEM_ENTRIES, vec![], BoundsPolicy::Vip).is_ok());
+    assert!(t.set_state(MEM_ENTRIES + 1, vec![], BoundsPolicy::Vip).is_err());
+    assert!(t.set_state(isize::MIN, vec![], BoundsPolicy::Vip).is_err());
+}
+
+#[test]
+fn test_memory_stack_wraps_at_4k() {
+    // Runaway recursion with the VIP policy keeps going, and the
+    // stack stays small enough to be saved and restored.
+    let mut mem = [0u8; MEM_SIZE];
+    let mut s = Stack::new(StackModel::default());
+    for addr in 0..5000 {
+        s.push(&mut mem, addr, BoundsPolicy::Vip).unwrap();
+    }
+    let (top, _) = s.state();
+    assert!(top <= MEM_ENTRIES);
+    assert_eq!(s.pop(&mem, BoundsPolicy::Vip), Ok(4999));
+    let mut t = Stack::new(StackModel::default());
+    t.set_state(top, vec![], BoundsPolicy::Vip).unwrap();
+    assert!(t.return_addresses(&mem).len() <= MEM_ENTRIES as usize);
+}
@@ -0,0 +1,389 @@
+// terminal.rs
+
+//! A frontend which runs in a text terminal, for playing
+//! without a graphical display (eg: over SSH).
+//!
+//! The display is drawn with Unicode block or braille
+//! characters in 24 bit color. Keys are read in raw mode.
+//! Terminals do not normally report key releases; on those
+//! which support the kitty keyboard protocol, releases are
+//! asked for, and on the others a key counts as held down
+//! for a while after it is pressed (or repeated).
+
+use std::collections::HashMap;
+use std::io::{self, IsTerminal, Stdout, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crossterm::{self, cursor, event, terminal, Command as TermCommand};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+                       KeyboardEnhancementFlags};
+use crossterm::style::{Color, ResetColor, SetBackgroundColor, SetForegroundColor};
+
+use chip8::Command;
+use cpu::NUM_KEYS;
+use filter::DisplayFilter;
+use framebuffer::{Framebuffer, WIDTH, HEIGHT};
+use frontend::Frontend;
+use gamepad;
+use keymap::Keymap;
+use osd::Osd;
+use palette::{Palette, Rgb};
+use screen;
+
+/// How long a key counts as held down after it is pressed,
+/// on terminals which do not report key releases. Terminals
+/// repeat held keys, but only after a delay longer than this,
+/// so a key held down may seem to be released briefly.
+const KEY_HOLD: Duration = Duration::from_millis(200);
+
+/// Hotkeys, as in the SDL frontend.
+const CYCLE_THEME_KEY: KeyCode = KeyCode::F(3);
+const TOGGLE_FILTER_KEY: KeyCode = KeyCode::F(4);
+const TURBO_KEY: KeyCode = KeyCode::Tab;
+
+/// Return the emulator command bound to hotkey `k'.
+fn hotkey_command(k: KeyCode) -> Option<Command> {
+    match k {
+        KeyCode::Esc => Some(Command::Quit),
+        KeyCode::F(5) => Some(Command::Reset),
+        KeyCode::F(6) => Some(Command::TogglePause),
+        KeyCode::F(7) => Some(Command::FrameAdvance),
+        KeyCode::F(8) => Some(Command::ToggleSlowMotion),
+        TURBO_KEY => Some(Command::Turbo(true)),
+        KeyCode::Char('-') => Some(Command::SpeedDown),
+        KeyCode::Char('=') => Some(Command::SpeedUp),
+        KeyCode::F(9) => Some(Command::ToggleRecording),
+        KeyCode::F(12) => Some(Command::Screenshot),
+        _ => None,
+    }
+}
+
+/// The characters used for drawing the display.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Glyphs {
+    /// Upper half blocks, with the upper pixel drawn in the
+    /// foreground color and the lower one in the background
+    /// color. Each character is 1x2 pixels, in full color.
+    HalfBlock,
+    /// Braille patterns. Each character is 2x4 pixels, which
+    /// makes the display smaller, but only two colors can be
+    /// shown.
+    Braille,
+}
+
+impl FromStr for Glyphs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Glyphs, String> {
+        match s {
+            "half-block" => Ok(Glyphs::HalfBlock),
+            "braille" => Ok(Glyphs::Braille),
+            _ => Err(format!("unknown terminal glyphs: {}", s)),
+        }
+    }
+}
+
+/// A character on the terminal, with its colors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub ch: char,
+    pub fg: Rgb,
+    pub bg: Rgb,
+}
+
+/// Draw the display, given as the color of each pixel, with
+/// half blocks. Returns the rows of characters.
+pub fn half_block_rows(colors: &[Rgb]) -> Vec<Vec<Cell>> {
+    (0 .. HEIGHT / 2).map(|row| {
+        (0 .. WIDTH).map(|x| {
+            let (top, bottom) = (2 * row * WIDTH + x, (2 * row + 1) * WIDTH + x);
+            Cell { ch: '\u{2580}', fg: colors[top], bg: colors[bottom] }
+        }).collect()
+    }).collect()
+}
+
+/// Braille dot for the pixel at (x, y) within a character.
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+/// Draw the display, given as the color of each pixel, with
+/// braille patterns. Pixels of the background color `bg' are
+/// left blank; the others are drawn in the color of the first
+/// one in the character.
+pub fn braille_rows(colors: &[Rgb], bg: Rgb) -> Vec<Vec<Cell>> {
+    (0 .. HEIGHT / 4).map(|row| {
+        (0 .. WIDTH / 2).map(|col| {
+            let mut dots = 0;
+            let mut fg = None;
+            for (dy, line) in BRAILLE_DOTS.iter().enumerate() {
+                for (dx, dot) in line.iter().enumerate() {
+                    let color = colors[(4 * row + dy) * WIDTH + 2 * col + dx];
+                    if color != bg {
+                        dots |= dot;
+                        fg = fg.or(Some(color));
+                    }
+                }
+            }
+            let ch = ::std::char::from_u32(0x2800 + dots).unwrap();
+            Cell { ch, fg: fg.unwrap_or(bg), bg }
+        }).collect()
+    }).collect()
+}
+
+fn term_color(c: Rgb) -> Color {
+    Color::Rgb { r: c.0, g: c.1, b: c.2 }
+}
+
+/// Return the text (characters and escape sequences) which
+/// draws a row of cells. Colors are only set when they change.
+pub fn row_text(cells: &[Cell]) -> String {
+    let mut text = String::new();
+    let mut colors = None;
+    for cell in cells {
+        if colors != Some((cell.fg, cell.bg)) {
+            let _ = SetForegroundColor(term_color(cell.fg)).write_ansi(&mut text);
+            let _ = SetBackgroundColor(term_color(cell.bg)).write_ansi(&mut text);
+            colors = Some((cell.fg, cell.bg));
+        }
+        text.push(cell.ch);
+    }
+    let _ = ResetColor.write_ansi(&mut text);
+    text
+}
+
+/// Return the terminal key for host key `name' of a keymap.
+/// Names are those used by the SDL frontend: letters, digits,
+/// "Space", "Return", "Up" etc. Terminals cannot tell keypad
+/// keys from the other ones, so "Keypad 8" is the same as "8".
+pub fn key_code(name: &str) -> Option<KeyCode> {
+    let name = name.strip_prefix("Keypad ").unwrap_or(name);
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(c.to_ascii_lowercase()));
+    }
+    match name {
+        "Space" => Some(KeyCode::Char(' ')),
+        "Return" | "Enter" => Some(KeyCode::Enter),
+        "Backspace" => Some(KeyCode::Backspace),
+        "Tab" => Some(KeyCode::Tab),
+        "Up" => Some(KeyCode::Up),
+        "Down" => Some(KeyCode::Down),
+        "Left" => Some(KeyCode::Left),
+        "Right" => Some(KeyCode::Right),
+        "Home" => Some(KeyCode::Home),
+        "End" => Some(KeyCode::End),
+        "Insert" => Some(KeyCode::Insert),
+        "Delete" => Some(KeyCode::Delete),
+        "PageUp" => Some(KeyCode::PageUp),
+        "PageDown" => Some(KeyCode::PageDown),
+        _ => None,
+    }
+}
+
+pub struct Terminal {
+    out: Stdout,
+    glyphs: Glyphs,
+    palette: Palette,
+    filter: Option<DisplayFilter>,
+    keymap: HashMap<KeyCode, u8>,
+    /// True if the terminal reports key releases.
+    releases: bool,
+    /// The keys being held down, until they are released or,
+    /// if the terminal does not report releases, until the
+    /// given time.
+    held: HashMap<KeyCode, Option<Instant>>,
+    /// The rows drawn by the last frame, so that only the
+    /// ones which change are drawn again.
+    drawn: Vec<String>,
+    osd: Osd,
+}
+
+impl Terminal {
+    /// Take over the terminal: switch to raw mode and to the
+    /// alternate screen. The terminal is restored when the
+    /// `Terminal' is dropped. Of the screen options, the
+    /// palette, filter and keymap are used.
+    pub fn new(options: screen::Options, glyphs: Glyphs) -> Result<Terminal, String> {
+        let mut out = io::stdout();
+        if !out.is_terminal() {
+            return Err(String::from("standard output is not a terminal"));
+        }
+        terminal::enable_raw_mode().map_err(|e| format!("unable to use the terminal: {}", e))?;
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        let mut setup = crossterm::execute!(out, terminal::EnterAlternateScreen, cursor::Hide,
+                                            terminal::Clear(terminal::ClearType::All));
+        if releases && setup.is_ok() {
+            let flags = KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES |
+                        KeyboardEnhancementFlags::REPORT_EVENT_TYPES;
+            setup = crossterm::execute!(out, event::PushKeyboardEnhancementFlags(flags));
+        }
+
+        let term = Terminal {
+            out, glyphs,
+            palette: options.palette,
+            filter: options.filter.map(DisplayFilter::new),
+            keymap: Terminal::resolve_keymap(&options.keymap),
+            releases,
+            held: HashMap::new(),
+            drawn: Vec::new(),
+            osd: Osd::new(),
+        };
+        // Dropping `term' restores the terminal.
+        setup.map_err(|e| format!("unable to use the terminal: {}", e))?;
+        Ok(term)
+    }
+
+    /// Look up the terminal keys of the host keys in `keymap'.
+    /// Game controllers are not available in the terminal.
+    fn resolve_keymap(keymap: &Keymap) -> HashMap<KeyCode, u8> {
+        let mut m = HashMap::new();
+        for &(ref name, key) in keymap.bindings() {
+            if gamepad::is_pad_name(name) {
+                continue;
+            }
+            match key_code(name) {
+                Some(code) => { m.insert(code, key); },
+                None => eprintln!("warning: keymap: key not available in the terminal: {}", name),
+            }
+        }
+        m
+    }
+
+    /// Draw everything again on the next frame.
+    fn redraw(&mut self) {
+        self.drawn.clear();
+        let _ = crossterm::queue!(self.out, terminal::Clear(terminal::ClearType::All));
+    }
+
+    fn handle_key(&mut self, k: KeyEvent, commands: &mut Vec<Command>) {
+        // Raw mode turns off the interrupt key.
+        if k.code == KeyCode::Char('c') && k.modifiers.contains(KeyModifiers::CONTROL) {
+            commands.push(Command::Quit);
+            return;
+        }
+        let code = match k.code {
+            KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+            code => code,
+        };
+        if k.kind == KeyEventKind::Release {
+            self.held.remove(&code);
+            return;
+        }
+        // Repeats are reported as presses by most terminals.
+        if k.kind == KeyEventKind::Press && !self.held.contains_key(&code) {
+            match code {
+                CYCLE_THEME_KEY => {
+                    let (name, palette) = self.palette.next_theme();
+                    self.palette = palette;
+                    self.redraw();
+                    self.show_message(&format!("theme {}", name));
+                },
+                TOGGLE_FILTER_KEY => {
+                    if let Some(ref mut filter) = self.filter {
+                        let enabled = !filter.is_enabled();
+                        filter.set_enabled(enabled);
+                        self.osd.show_message(if enabled { "filter on" } else { "filter off" });
+                    }
+                },
+                _ => commands.extend(hotkey_command(code)),
+            }
+        }
+        let until = if self.releases { None } else { Some(Instant::now() + KEY_HOLD) };
+        self.held.insert(code, until);
+    }
+}
+
+impl Frontend for Terminal {
+    fn poll_events(&mut self) -> Vec<Command> {
+        let mut commands = Vec::new();
+        let turbo = self.held.contains_key(&TURBO_KEY);
+        while let Ok(true) = event::poll(Duration::from_secs(0)) {
+            match event::read() {
+                Ok(Event::Key(k)) => self.handle_key(k, &mut commands),
+                Ok(Event::Resize(..)) => self.redraw(),
+                _ => {},
+            }
+        }
+        let now = Instant::now();
+        self.held.retain(|_, until| until.is_none_or(|t| t > now));
+        if turbo && !self.held.contains_key(&TURBO_KEY) {
+            commands.push(Command::Turbo(false));
+        }
+        commands
+    }
+
+    fn keys(&self) -> [bool; NUM_KEYS] {
+        let mut keys = [false; NUM_KEYS];
+        for code in self.held.keys() {
+            if let Some(key) = self.keymap.get(code) {
+                keys[usize::from(*key)] = true;
+            }
+        }
+        keys
+    }
+
+    fn present(&mut self, fb: &Framebuffer) {
+        let palette = &self.palette;
+        let colors: Vec<Rgb> = match self.filter {
+            Some(ref mut filter) => filter.apply(fb).iter().map(|l| palette.shade(*l)).collect(),
+            None => fb.rows().flat_map(|row| row.iter()).map(|v| palette.color(*v)).collect(),
+        };
+        let rows = match self.glyphs {
+            Glyphs::HalfBlock => half_block_rows(&colors),
+            Glyphs::Braille => braille_rows(&colors, self.palette.background()),
+        };
+        let mut lines: Vec<String> = rows.iter().map(|r| row_text(r)).collect();
+        // The OSD goes below the display, in the terminal's colors.
+        for n in 0..2 {
+            let text = self.osd.lines().get(n).map_or("", |l| *l).to_string();
+            let mut line = String::new();
+            let _ = terminal::Clear(terminal::ClearType::UntilNewLine).write_ansi(&mut line);
+            lines.push(text + line.as_str());
+        }
+
+        for (y, line) in lines.into_iter().enumerate() {
+            if self.drawn.get(y) == Some(&line) {
+                continue;
+            }
+            let _ = crossterm::queue!(self.out, cursor::MoveTo(0, y as u16));
+            let _ = self.out.write_all(line.as_bytes());
+            if y < self.drawn.len() {
+                self.drawn[y] = line;
+            } else {
+                self.drawn.push(line);
+            }
+        }
+        let _ = self.out.flush();
+        self.osd.tick();
+    }
+
+    fn show_message(&mut self, text: &str) {
+        // Messages often come along with lines printed on
+        // standard error, which mess up the display.
+        self.redraw();
+        self.osd.show_message(text);
+    }
+
+    fn set_status(&mut self, text: Option<&str>) {
+        self.redraw();
+        self.osd.set_status(text);
+    }
+
+    fn palette(&self) -> &Palette {
+        &self.palette
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.releases {
+            let _ = crossterm::execute!(self.out, event::PopKeyboardEnhancementFlags);
+        }
+        let _ = crossterm::execute!(self.out, ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+#[cfg(test)]
+#[path="./terminal_test.rs"]
+mod terminal_test;
@@ -0,0 +1,70 @@
+
+use super::*;
+
+const BG: Rgb = (0, 0, 0);
+const FG: Rgb = (255, 176, 0);
+
+/// The colors of a display with the given pixels lit.
+fn colors(lit: &[(usize, usize)]) -> Vec<Rgb> {
+    let mut colors = vec![BG; WIDTH * HEIGHT];
+    for &(x, y) in lit {
+        colors[y * WIDTH + x] = FG;
+    }
+    colors
+}
+
+#[test]
+fn test_glyphs_from_str() {
+    assert_eq!("half-block".parse(), Ok(Glyphs::HalfBlock));
+    assert_eq!("braille".parse(), Ok(Glyphs::Braille));
+    assert!("ascii".parse::<Glyphs>().is_err());
+}
+
+#[test]
+fn test_half_block_rows() {
+    let rows = half_block_rows(&colors(&[(3, 0), (5, 3)]));
+    assert_eq!(rows.len(), HEIGHT / 2);
+    assert!(rows.iter().all(|r| r.len() == WIDTH));
+    assert_eq!(rows[0][3], Cell { ch: '\u{2580}', fg: FG, bg: BG });
+    assert_eq!(rows[1][5], Cell { ch: '\u{2580}', fg: BG, bg: FG });
+    assert_eq!(rows[0][4], Cell { ch: '\u{2580}', fg: BG, bg: BG });
+}
+
+#[test]
+fn test_braille_rows() {
+    // Top left and bottom right dots of the second character.
+    let rows = braille_rows(&colors(&[(2, 0), (3, 3)]), BG);
+    assert_eq!(rows.len(), HEIGHT / 4);
+    assert!(rows.iter().all(|r| r.len() == WIDTH / 2));
+    assert_eq!(rows[0][1], Cell { ch: '\u{2881}', fg: FG, bg: BG });
+    assert_eq!(rows[0][0], Cell { ch: '\u{2800}', fg: BG, bg: BG });
+}
+
+#[test]
+fn test_row_text() {
+    let a = Cell { ch: 'a', fg: FG, bg: BG };
+    let b = Cell { ch: 'b', fg: BG, bg: FG };
+    let text = row_text(&[a, a, b]);
+    assert!(text.contains("aa"));
+    // Colors are set once for "aa" and once for "b".
+    assert_eq!(text.matches("38;2;255;176;0").count(), 1);
+    assert_eq!(text.matches("48;2;255;176;0").count(), 1);
+    assert!(text.ends_with("\x1b[0m"));
+}
+
+#[test]
+fn test_key_code() {
+    assert_eq!(key_code("Q"), Some(KeyCode::Char('q')));
+    assert_eq!(key_code("1"), Some(KeyCode::Char('1')));
+    assert_eq!(key_code("Keypad 8"), Some(KeyCode::Char('8')));
+    assert_eq!(key_code("Space"), Some(KeyCode::Char(' ')));
+    assert_eq!(key_code("Up"), Some(KeyCode::Up));
+    assert_eq!(key_code("Left Shift"), None);
+}
+
+#[test]
+fn test_hotkeys() {
+    assert_eq!(hotkey_command(KeyCode::Esc), Some(Command::Quit));
+    assert_eq!(hotkey_command(TURBO_KEY), Some(Command::Turbo(true)));
+    assert_eq!(hotkey_command(KeyCode::Char('q')), None);
+}
@@ -0,0 +1,140 @@
+/* capi_test.c: exercises the C API; built and run by tests/capi.rs. */
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "chip8_emu.h"
+
+/* Draws digit 5 at (0, 0), waits for a key, then draws the
+   digit of the key at (8, 0) and loops. */
+static const uint8_t rom[] = {
+    0x60, 0x05, /* LD V0, 5 */
+    0xf0, 0x29, /* LD F, V0 */
+    0x61, 0x00, /* LD V1, 0 */
+    0xd1, 0x15, /* DRW V1, V1, 5 */
+    0x62, 0x78, /* LD V2, 0x78 */
+    0xf2, 0x18, /* LD ST, V2 */
+    0xf3, 0x0a, /* LD V3, K */
+    0xf3, 0x29, /* LD F, V3 */
+    0x64, 0x08, /* LD V4, 8 */
+    0xd4, 0x15, /* DRW V4, V1, 5 */
+    0x12, 0x14, /* JP 0x214 */
+};
+
+static int failures;
+
+#define CHECK(cond) \
+    do { \
+        if (!(cond)) { \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond); \
+            failures++; \
+        } \
+    } while (0)
+
+/* The top row of a digit drawn at (x, 0), as a font byte. */
+static uint8_t top_row(Chip8 *c, int x)
+{
+    uint8_t pixels[CHIP8_WIDTH * CHIP8_HEIGHT];
+    uint8_t byte = 0;
+    int n;
+
+    CHECK(chip8_framebuffer(c, pixels, sizeof pixels) == CHIP8_STATUS_OK);
+    for (n = 0; n < 8; n++)
+        byte = byte << 1 | (pixels[x + n] != 0);
+    return byte;
+}
+
+int main(void)
+{
+    Chip8 *c = chip8_new();
+    Chip8Registers regs;
+    uint8_t big[CHIP8_MEM_SIZE];
+    uint8_t small[4];
+    uint8_t *state;
+    size_t size;
+
+    CHECK(c != NULL);
+
+    /* Errors. */
+    CHECK(chip8_load_rom(c, rom, 0) == CHIP8_STATUS_ROM_SIZE);
+    CHECK(chip8_load_rom(c, big, sizeof big) == CHIP8_STATUS_ROM_SIZE);
+    CHECK(chip8_load_rom(NULL, rom, sizeof rom) == CHIP8_STATUS_NULL_POINTER);
+    CHECK(chip8_load_rom(c, NULL, sizeof rom) == CHIP8_STATUS_NULL_POINTER);
+    CHECK(chip8_set_quirks(c, "bogus") == CHIP8_STATUS_BAD_QUIRKS);
+    CHECK(chip8_set_register(c, CHIP8_NUM_REGS, 0) == CHIP8_STATUS_OUT_OF_RANGE);
+    CHECK(chip8_read_memory(c, CHIP8_MEM_SIZE - 2, small, sizeof small) == CHIP8_STATUS_OUT_OF_RANGE);
+    CHECK(chip8_framebuffer(c, small, sizeof small) == CHIP8_STATUS_BUFFER_TOO_SMALL);
+    CHECK(chip8_load_state(c, small, sizeof small) == CHIP8_STATUS_BAD_STATE);
+    CHECK(strcmp(chip8_status_message(CHIP8_STATUS_OUT_OF_RANGE), "out of range") == 0);
+    CHECK(strcmp(chip8_status_message(42), "unknown status") == 0);
+    CHECK(strcmp(chip8_status_message(-100), "unknown status") == 0);
+    CHECK(chip8_read_memory(c, 0x200, small, SIZE_MAX) == CHIP8_STATUS_OUT_OF_RANGE);
+
+    CHECK(chip8_set_quirks(c, "vip") == CHIP8_STATUS_OK);
+    CHECK(chip8_load_rom(c, rom, sizeof rom) == CHIP8_STATUS_OK);
+
+    /* Registers and memory. */
+    CHECK(chip8_registers(c, &regs) == CHIP8_STATUS_OK);
+    CHECK(regs.pc == 0x200);
+    CHECK(chip8_read_memory(c, 0x200, small, 2) == CHIP8_STATUS_OK);
+    CHECK(small[0] == 0x60 && small[1] == 0x05);
+    CHECK(chip8_step(c, 1) == CHIP8_STATUS_OK);
+    CHECK(chip8_registers(c, &regs) == CHIP8_STATUS_OK);
+    CHECK(regs.v[0] == 5 && regs.pc == 0x202);
+
+    /* Video and sound. */
+    CHECK(chip8_run_frame(c, 10) == CHIP8_STATUS_OK);
+    CHECK(top_row(c, 0) == 0xf0);
+    CHECK(chip8_beeping(c));
+    CHECK(top_row(c, 8) == 0);
+
+    size = chip8_state_size(c);
+    state = malloc(size);
+    CHECK(size > 0 && state != NULL);
+    CHECK(chip8_save_state(c, state, size - 1) == CHIP8_STATUS_BUFFER_TOO_SMALL);
+    CHECK(chip8_save_state(c, state, size) == CHIP8_STATUS_OK);
+
+    /* Input: key 7 is drawn. */
+    CHECK(chip8_set_keys(c, 1 << 7) == CHIP8_STATUS_OK);
+    CHECK(chip8_run_frame(c, 10) == CHIP8_STATUS_OK);
+    CHECK(chip8_registers(c, &regs) == CHIP8_STATUS_OK);
+    CHECK(regs.v[3] == 7);
+    CHECK(top_row(c, 8) == 0xf0);
+
+    /* Save states. */
+    CHECK(chip8_load_state(c, state, size) == CHIP8_STATUS_OK);
+    CHECK(top_row(c, 8) == 0);
+    free(state);
+
+    /* Faults: a return with an empty stack. */
+    small[0] = 0x00;
+    small[1] = 0xee;
+    CHECK(chip8_write_memory(c, 0x300, small, 2) == CHIP8_STATUS_OK);
+    CHECK(chip8_set_pc(c, 0x300) == CHIP8_STATUS_OK);
+    CHECK(chip8_step(c, 1) == CHIP8_STATUS_FAULT);
+    CHECK(chip8_reset(c) == CHIP8_STATUS_OK);
+    CHECK(chip8_step(c, 1) == CHIP8_STATUS_OK);
+
+    /* Registers: I stays within 16 bits when FX1E runs past memory. */
+    small[0] = 0xf0; /* ADD I, V0 */
+    small[1] = 0x1e;
+    small[2] = 0x13; /* JP 0x300 */
+    small[3] = 0x00;
+    CHECK(chip8_write_memory(c, 0x300, small, 4) == CHIP8_STATUS_OK);
+    CHECK(chip8_set_register(c, 0, 0xff) == CHIP8_STATUS_OK);
+    CHECK(chip8_set_pc(c, 0x300) == CHIP8_STATUS_OK);
+    CHECK(chip8_step(c, 1000) == CHIP8_STATUS_OK);
+    CHECK(chip8_registers(c, &regs) == CHIP8_STATUS_OK);
+    CHECK(regs.pc == 0x300);
+
+    chip8_free(c);
+    chip8_free(NULL);
+
+    if (failures) {
+        fprintf(stderr, "%d failures\n", failures);
+        return 1;
+    }
+    return 0;
+}
@@ -0,0 +1,92 @@
+// capi.rs
+
+//! Checks that include/chip8_emu.h is up to date, and runs a C
+//! program against the library built next to the tests.
+
+extern crate cbindgen;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const HEADER: &str = "include/chip8_emu.h";
+
+/// The header, as cbindgen makes it from src/capi.rs.
+fn generate_header() -> String {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(root.join("cbindgen.toml")).unwrap();
+    let mut out = Vec::new();
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(root.join("src/capi.rs"))
+        .generate()
+        .unwrap()
+        .write(&mut out);
+    String::from_utf8(out).unwrap()
+}
+
+/// The directory with the library, built next to the tests.
+fn lib_dir() -> PathBuf {
+    let name = format!("{}chip8_emu{}", env::consts::DLL_PREFIX, env::consts::DLL_SUFFIX);
+    let exe = env::current_exe().unwrap();
+    let deps = exe.parent().unwrap();
+    for dir in &[deps, deps.parent().unwrap()] {
+        if dir.join(&name).exists() {
+            return dir.to_path_buf();
+        }
+    }
+    panic!("{} not found", name);
+}
+
+/// Set UPDATE_HEADER=1 to write the header instead.
+#[test]
+fn test_header() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(HEADER);
+    let header = generate_header();
+    if env::var_os("UPDATE_HEADER").is_some() {
+        fs::write(&path, &header).unwrap();
+    } else {
+        let committed = fs::read_to_string(&path).unwrap();
+        assert!(committed == header, "{} is out of date: run `UPDATE_HEADER=1 cargo test --test capi'", HEADER);
+    }
+    // C can pass any int where an enum is expected, which is
+    // undefined behaviour for a Rust enum.
+    for line in header.lines() {
+        let params = line.split_once('(').map_or("", |(_, params)| params);
+        assert!(!params.contains("enum "), "enum parameter in {}", line.trim());
+    }
+}
+
+#[test]
+fn test_c_program() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let lib = lib_dir();
+    let exe = Path::new(env!("CARGO_TARGET_TMPDIR")).join("capi_test");
+    let cc = env::var("CC").unwrap_or_else(|_| String::from("cc"));
+    let status = Command::new(&cc)
+        .arg("-std=c99").arg("-Wall").arg("-Werror")
+        .arg("-I").arg(root.join("include"))
+        .arg(root.join("tests/c/capi_test.c"))
+        .arg("-o").arg(&exe)
+        .arg("-L").arg(&lib)
+        .arg(format!("-Wl,-rpath,{}", lib.display()))
+        .arg("-lchip8_emu")
+        .status();
+    match status {
+        Ok(status) => assert!(status.success(), "{} failed", cc),
+        Err(e) => {
+            eprintln!("skipped: cannot run {}: {}", cc, e);
+            return;
+        }
+    }
+    // Cargo's library path, which wins over the rpath, may hold
+    // an older copy of the library.
+    let mut path = vec![lib];
+    path.extend(env::var_os("LD_LIBRARY_PATH").iter().flat_map(env::split_paths));
+    let status = Command::new(&exe)
+        .env("LD_LIBRARY_PATH", env::join_paths(path).unwrap())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
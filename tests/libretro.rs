@@ -0,0 +1,403 @@
+// libretro.rs
+
+//! Loads the libretro core like a frontend would, and calls
+//! its entry points directly.
+
+extern crate libloading;
+
+use std::env;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_uint, c_void};
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::{Mutex, MutexGuard};
+
+use libloading::{Library, Symbol};
+
+const ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+const ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+const ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+const DEVICE_JOYPAD: c_uint = 1;
+const JOYPAD_A: c_uint = 8;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+/// Draws digit 5 at (0, 0), waits for a key, then draws the
+/// digit of the key at (8, 0) and loops.
+const ROM: [u8; 22] = [
+    0x60, 0x05, // LD V0, 5
+    0xf0, 0x29, // LD F, V0
+    0x61, 0x00, // LD V1, 0
+    0xd1, 0x15, // DRW V1, V1, 5
+    0x62, 0x78, // LD V2, 0x78
+    0xf2, 0x18, // LD ST, V2
+    0xf3, 0x0a, // LD V3, K
+    0xf3, 0x29, // LD F, V3
+    0x64, 0x08, // LD V4, 8
+    0xd4, 0x15, // DRW V4, V1, 5
+    0x12, 0x14, // JP 0x214
+];
+
+#[repr(C)]
+struct SystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+struct SystemAvInfo {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+struct GameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+#[repr(C)]
+struct Variable {
+    key: *const c_char,
+    value: *const c_char,
+}
+
+/// What the core gave to the frontend, and what the frontend
+/// gives to the core.
+struct Frontend {
+    /// The keys of the core options.
+    option_keys: Vec<String>,
+    /// Options set by the user.
+    options: Vec<(CString, CString)>,
+    options_updated: bool,
+    pixel_format: Option<c_uint>,
+    video: Vec<u32>,
+    audio: Vec<i16>,
+    joypad_a: bool,
+    /// Called from `video_refresh', as frontends may call the
+    /// core from its callbacks; what it returned.
+    on_video: Option<extern "C" fn() -> usize>,
+    on_video_result: Option<usize>,
+}
+
+static FRONTEND: Mutex<Frontend> = Mutex::new(Frontend {
+    option_keys: Vec::new(),
+    options: Vec::new(),
+    options_updated: false,
+    pixel_format: None,
+    video: Vec::new(),
+    audio: Vec::new(),
+    joypad_a: false,
+    on_video: None,
+    on_video_result: None,
+});
+
+/// The core has global state: one test at a time.
+static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+fn frontend() -> MutexGuard<'static, Frontend> {
+    FRONTEND.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    let mut f = frontend();
+    unsafe {
+        match cmd {
+            ENVIRONMENT_SET_PIXEL_FORMAT => {
+                f.pixel_format = Some(*(data as *const c_uint));
+                true
+            }
+            ENVIRONMENT_SET_VARIABLES => {
+                let mut var = data as *const Variable;
+                while !(*var).key.is_null() {
+                    let key = CStr::from_ptr((*var).key).to_string_lossy().into_owned();
+                    f.option_keys.push(key);
+                    var = var.offset(1);
+                }
+                true
+            }
+            ENVIRONMENT_GET_VARIABLE => {
+                let var = data as *mut Variable;
+                let key = CStr::from_ptr((*var).key);
+                match f.options.iter().find(|o| o.0.as_c_str() == key) {
+                    Some(o) => {
+                        (*var).value = o.1.as_ptr();
+                        true
+                    }
+                    None => false,
+                }
+            }
+            ENVIRONMENT_GET_VARIABLE_UPDATE => {
+                *(data as *mut bool) = f.options_updated;
+                f.options_updated = false;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    assert_eq!((width as usize, height as usize, pitch), (WIDTH, HEIGHT, WIDTH * 4));
+    let pixels = unsafe { std::slice::from_raw_parts(data as *const u32, WIDTH * HEIGHT) };
+    frontend().video = pixels.to_vec();
+    let on_video = frontend().on_video;
+    if let Some(f) = on_video {
+        let result = f();
+        frontend().on_video_result = Some(result);
+    }
+}
+
+extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    let samples = unsafe { std::slice::from_raw_parts(data, frames * 2) };
+    frontend().audio.extend_from_slice(samples);
+    frames
+}
+
+extern "C" fn input_poll() {}
+
+extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    let pressed = port == 0 && device == DEVICE_JOYPAD && id == JOYPAD_A && frontend().joypad_a;
+    pressed as i16
+}
+
+/// The core, built next to the tests.
+fn core_path() -> PathBuf {
+    let name = format!("{}chip8_emu{}", env::consts::DLL_PREFIX, env::consts::DLL_SUFFIX);
+    let exe = env::current_exe().unwrap();
+    let deps = exe.parent().unwrap();
+    for dir in &[deps, deps.parent().unwrap()] {
+        if dir.join(&name).exists() {
+            return dir.join(&name);
+        }
+    }
+    panic!("{} not found", name);
+}
+
+struct Core {
+    lib: Library,
+}
+
+impl Core {
+    /// Load the core and the test ROM, with the given options.
+    fn load(options: &[(&str, &str)]) -> Core {
+        {
+            let mut f = frontend();
+            f.option_keys.clear();
+            f.options = options.iter().map(|&(k, v)| {
+                (CString::new(k).unwrap(), CString::new(v).unwrap())
+            }).collect();
+            f.options_updated = false;
+            f.pixel_format = None;
+            f.video.clear();
+            f.audio.clear();
+            f.joypad_a = false;
+            f.on_video = None;
+            f.on_video_result = None;
+        }
+        let core = Core { lib: unsafe { Library::new(core_path()) }.unwrap() };
+        unsafe {
+            core.sym::<extern "C" fn(extern "C" fn(c_uint, *mut c_void) -> bool)>(b"retro_set_environment\0")(environment);
+            core.sym::<extern "C" fn(extern "C" fn(*const c_void, c_uint, c_uint, usize))>(b"retro_set_video_refresh\0")(video_refresh);
+            core.sym::<extern "C" fn(extern "C" fn(i16, i16))>(b"retro_set_audio_sample\0")(audio_sample);
+            core.sym::<extern "C" fn(extern "C" fn(*const i16, usize) -> usize)>(b"retro_set_audio_sample_batch\0")(audio_sample_batch);
+            core.sym::<extern "C" fn(extern "C" fn())>(b"retro_set_input_poll\0")(input_poll);
+            core.sym::<extern "C" fn(extern "C" fn(c_uint, c_uint, c_uint, c_uint) -> i16)>(b"retro_set_input_state\0")(input_state);
+            core.call(b"retro_init\0");
+            let game = GameInfo {
+                path: ptr::null(),
+                data: ROM.as_ptr() as *const c_void,
+                size: ROM.len(),
+                meta: ptr::null(),
+            };
+            assert!(core.sym::<extern "C" fn(*const GameInfo) -> bool>(b"retro_load_game\0")(&game));
+        }
+        core
+    }
+
+    unsafe fn sym<T>(&self, name: &[u8]) -> Symbol<'_, T> {
+        self.lib.get(name).unwrap()
+    }
+
+    fn call(&self, name: &[u8]) {
+        unsafe { self.sym::<extern "C" fn()>(name)() }
+    }
+
+    fn run(&self, frames: usize) {
+        for _ in 0..frames {
+            self.call(b"retro_run\0");
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        unsafe {
+            let size = self.sym::<extern "C" fn() -> usize>(b"retro_serialize_size\0")();
+            let mut data = vec![0u8; size];
+            let ok = self.sym::<extern "C" fn(*mut c_void, usize) -> bool>(b"retro_serialize\0")(
+                data.as_mut_ptr() as *mut c_void, size);
+            assert!(ok);
+            data
+        }
+    }
+
+    fn unserialize(&self, data: &[u8]) -> bool {
+        unsafe {
+            self.sym::<extern "C" fn(*const c_void, usize) -> bool>(b"retro_unserialize\0")(
+                data.as_ptr() as *const c_void, data.len())
+        }
+    }
+}
+
+impl Drop for Core {
+    fn drop(&mut self) {
+        self.call(b"retro_unload_game\0");
+        self.call(b"retro_deinit\0");
+    }
+}
+
+/// Return true if the pixel at (x, y) is lit, ie: brighter
+/// than the background in the top right corner.
+fn lit(video: &[u32], x: usize, y: usize) -> bool {
+    video[y * WIDTH + x] != video[WIDTH - 1]
+}
+
+/// The top row of a digit drawn at (x, 0), as a font byte.
+fn top_row(video: &[u32], x: usize) -> u8 {
+    (0..8).fold(0, |byte, n| byte << 1 | lit(video, x + n, 0) as u8)
+}
+
+#[test]
+fn test_system_info() {
+    let _lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let core = Core::load(&[]);
+    unsafe {
+        assert_eq!(core.sym::<extern "C" fn() -> c_uint>(b"retro_api_version\0")(), 1);
+
+        let mut info: SystemInfo = std::mem::zeroed();
+        core.sym::<extern "C" fn(*mut SystemInfo)>(b"retro_get_system_info\0")(&mut info);
+        assert_eq!(CStr::from_ptr(info.library_name).to_str(), Ok("CHIP-8"));
+        assert!(!CStr::from_ptr(info.library_version).to_bytes().is_empty());
+        assert!(CStr::from_ptr(info.valid_extensions).to_str().unwrap().contains("ch8"));
+        assert!(!info.need_fullpath && !info.block_extract);
+
+        let mut av: SystemAvInfo = std::mem::zeroed();
+        core.sym::<extern "C" fn(*mut SystemAvInfo)>(b"retro_get_system_av_info\0")(&mut av);
+        assert_eq!((av.base_width, av.base_height), (64, 32));
+        assert_eq!((av.max_width, av.max_height), (64, 32));
+        assert_eq!(av.aspect_ratio, 2.0);
+        assert_eq!(av.fps, 60.0);
+        assert_eq!(av.sample_rate, 44100.0);
+    }
+    let f = frontend();
+    assert_eq!(f.pixel_format, Some(1));
+    assert_eq!(f.option_keys, vec!["chip8_quirks", "chip8_ipf", "chip8_theme"]);
+}
+
+#[test]
+fn test_video_and_audio() {
+    let _lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let core = Core::load(&[]);
+    core.run(1);
+    {
+        let f = frontend();
+        assert_eq!(f.video.len(), WIDTH * HEIGHT);
+        // The top row of the 5 of the standard font.
+        assert_eq!(top_row(&f.video, 0), 0xf0);
+        // One frame of stereo samples, the beeper sounding.
+        assert_eq!(f.audio.len(), 735 * 2);
+        assert!(f.audio.iter().any(|s| *s != 0));
+    }
+    core.run(150);
+    let f = frontend();
+    assert_eq!(f.audio.len(), 151 * 735 * 2);
+    // The sound timer ran out after two seconds.
+    assert!(f.audio[f.audio.len() - 2 * 735 ..].iter().all(|s| *s == 0));
+}
+
+#[test]
+fn test_call_core_from_callback() {
+    let _lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let core = Core::load(&[]);
+    let size = *unsafe { core.sym::<extern "C" fn() -> usize>(b"retro_serialize_size\0") };
+    frontend().on_video = Some(size);
+    core.run(2);
+    assert_eq!(frontend().on_video_result, Some(size()));
+    assert_eq!(frontend().audio.len(), 2 * 735 * 2);
+}
+
+#[test]
+fn test_speed_option() {
+    let _lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let core = Core::load(&[("chip8_ipf", "1")]);
+    core.run(3);
+    assert_eq!(top_row(&frontend().video, 0), 0);
+    core.run(1);
+    assert_eq!(top_row(&frontend().video, 0), 0xf0);
+}
+
+#[test]
+fn test_theme_option() {
+    let _lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let core = Core::load(&[]);
+    core.run(1);
+    let before = frontend().video[0];
+    {
+        let mut f = frontend();
+        f.options = vec![(CString::new("chip8_theme").unwrap(), CString::new("amber").unwrap())];
+        f.options_updated = true;
+    }
+    core.run(1);
+    let after = frontend().video[0];
+    assert_ne!(before, after);
+    assert_eq!(after, 0xffb000);
+}
+
+#[test]
+fn test_input() {
+    let _lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let core = Core::load(&[]);
+    core.run(5);
+    assert_eq!(top_row(&frontend().video, 8), 0);
+    // A is key 5: its digit is drawn once the key is released.
+    frontend().joypad_a = true;
+    core.run(1);
+    frontend().joypad_a = false;
+    core.run(2);
+    assert_eq!(top_row(&frontend().video, 8), 0xf0);
+}
+
+#[test]
+fn test_save_state() {
+    let _lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let core = Core::load(&[]);
+    core.run(2);
+    let state = core.serialize();
+    let video = frontend().video.clone();
+
+    frontend().joypad_a = true;
+    core.run(1);
+    frontend().joypad_a = false;
+    core.run(2);
+    assert_ne!(frontend().video, video);
+
+    assert!(core.unserialize(&state));
+    core.run(1);
+    assert_eq!(frontend().video, video);
+
+    assert!(!core.unserialize(&state[.. state.len() - 1]));
+    assert!(!core.unserialize(b"not a state"));
+}
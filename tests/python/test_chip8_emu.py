@@ -0,0 +1,104 @@
+# Tests for the Python module. Install it with "maturin
+# develop" (or put the library built with --features python
+# on the path as chip8_emu.so), then run:
+#
+#     python3 -m unittest discover tests/python
+
+import unittest
+
+import chip8_emu
+
+# Draws digit 5 at (0, 0), sets the sound timer, then waits
+# for a key.
+ROM = bytes([
+    0x60, 0x05,  # LD V0, 5
+    0xf0, 0x29,  # LD F, V0
+    0x61, 0x00,  # LD V1, 0
+    0xd1, 0x15,  # DRW V1, V1, 5
+    0x62, 0x78,  # LD V2, 0x78
+    0xf2, 0x18,  # LD ST, V2
+    0xf3, 0x0a,  # LD V3, K
+    0x12, 0x0c,  # JP 0x20c
+])
+
+
+class TestCPU(unittest.TestCase):
+
+    def setUp(self):
+        self.m = chip8_emu.CPU()
+        self.m.load_rom(ROM)
+
+    def test_step(self):
+        self.assertEqual(self.m.pc, 0x200)
+        self.m.step()
+        self.assertEqual(self.m.v[0], 5)
+        self.m.step(3)
+        self.assertEqual(self.m.pc, 0x208)
+        self.assertEqual(self.m.i, 5 * 5)
+
+    def test_run_frames(self):
+        self.m.run_frames()
+        self.assertEqual(self.m.sound, 0x78 - 1)
+        self.m.ipf = 1
+        self.m.run_frames(2)
+        self.assertEqual(self.m.sound, 0x78 - 3)
+
+    def test_memory(self):
+        self.assertEqual(self.m.read(0x200, 2), ROM[:2])
+        self.assertEqual(len(self.m.memory), 4096)
+        self.m.write(0x300, b"\x01\x02")
+        self.assertEqual(self.m.memory[0x300:0x302], b"\x01\x02")
+        with self.assertRaises(ValueError):
+            self.m.read(4095, 2)
+
+    def test_load_rom(self):
+        with self.assertRaises(ValueError):
+            self.m.load_rom(b"")
+        with self.assertRaises(ValueError):
+            self.m.load_rom(bytes(4096 - 0x200 + 1))
+        # A failed load leaves the old ROM running.
+        self.assertEqual(self.m.read(0x200, 2), ROM[:2])
+
+    def test_keys(self):
+        self.m.run_frames()
+        self.m.set_key(0xa, True)
+        self.m.run_frames()
+        self.m.set_keys([False] * 16)
+        self.m.run_frames()
+        self.assertEqual(self.m.v[3], 0xa)
+        self.assertEqual(self.m.keys, [False] * 16)
+        with self.assertRaises(ValueError):
+            self.m.set_keys([True])
+
+    def test_save_state(self):
+        self.m.run_frames()
+        state = self.m.save_state()
+        self.m.reset()
+        self.assertEqual(self.m.pc, 0x200)
+        self.m.load_state(state)
+        self.assertEqual(self.m.pc, 0x20c)
+        with self.assertRaises(ValueError):
+            self.m.load_state(b"junk")
+
+    def test_options(self):
+        with self.assertRaises(ValueError):
+            chip8_emu.CPU(quirks="nonsense")
+        with self.assertRaises(ValueError):
+            chip8_emu.CPU(font="nonsense")
+        self.assertEqual(chip8_emu.CPU(ipf=20).ipf, 20)
+
+    def test_framebuffer(self):
+        try:
+            import numpy
+        except ImportError:
+            self.skipTest("numpy is not installed")
+        self.m.run_frames()
+        fb = self.m.framebuffer()
+        self.assertEqual(fb.shape, (chip8_emu.HEIGHT, chip8_emu.WIDTH))
+        self.assertEqual(fb.dtype, numpy.uint8)
+        # The top row of the 5.
+        self.assertEqual(list(fb[0, :5]), [1, 1, 1, 1, 0])
+
+
+if __name__ == "__main__":
+    unittest.main()